@@ -0,0 +1,123 @@
+//! Compares the old design, where one organizer task handles every move and saves finished
+//! games itself, with one actor per game behind a registry that only routes.
+//! The crate is a binary, so both designs are modelled here with the same channels,
+//! and the insert at the end of a game is a sleep.
+//!
+//! cargo run --release --example actor_throughput -- [games] [save_ms]
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, oneshot};
+
+type GameId = u32;
+
+/// what a player sends, the reply is the ack
+struct Move(GameId, oneshot::Sender<()>);
+
+/// moves until the game ends, varied so the games don't all end together
+fn game_length(game_id: GameId) -> u32 {
+    20 + game_id % 40
+}
+
+/// everything in one task, a save stalls every game
+fn spawn_single_task(save: Duration) -> mpsc::Sender<Move> {
+    let (tx, mut rx) = mpsc::channel::<Move>(32);
+    actix_rt::spawn(async move {
+        let mut moves: HashMap<GameId, u32> = HashMap::new();
+        while let Some(Move(game_id, reply)) = rx.recv().await {
+            let played = moves.entry(game_id).or_default();
+            *played += 1;
+            let _ = reply.send(());
+            if *played == game_length(game_id) {
+                tokio::time::sleep(save).await;
+                moves.remove(&game_id);
+            }
+        }
+    });
+    tx
+}
+
+/// one actor per game, the registry only forwards
+fn spawn_actors(save: Duration) -> mpsc::Sender<Move> {
+    let (tx, mut rx) = mpsc::channel::<Move>(32);
+    actix_rt::spawn(async move {
+        let mut games: HashMap<GameId, mpsc::Sender<Move>> = HashMap::new();
+        while let Some(Move(game_id, reply)) = rx.recv().await {
+            let game = games.entry(game_id).or_insert_with(|| {
+                let (game_tx, mut game_rx) = mpsc::channel::<Move>(32);
+                actix_rt::spawn(async move {
+                    let mut played = 0;
+                    while let Some(Move(_, reply)) = game_rx.recv().await {
+                        played += 1;
+                        let _ = reply.send(());
+                        if played == game_length(game_id) {
+                            tokio::time::sleep(save).await;
+                            break;
+                        }
+                    }
+                });
+                game_tx
+            });
+            let _ = game.send(Move(game_id, reply)).await;
+        }
+    });
+    tx
+}
+
+/// every game is played by its own client, one move at a time
+async fn run(organizer: mpsc::Sender<Move>, games: u32) -> (Duration, Vec<Duration>) {
+    let start = Instant::now();
+    let clients: Vec<_> = (0..games)
+        .map(|game_id| {
+            let organizer = organizer.clone();
+            actix_rt::spawn(async move {
+                let mut latencies = Vec::new();
+                for _ in 0..game_length(game_id) {
+                    let sent = Instant::now();
+                    let (reply_tx, reply_rx) = oneshot::channel();
+                    if organizer.send(Move(game_id, reply_tx)).await.is_err() {
+                        break;
+                    }
+                    let _ = reply_rx.await;
+                    latencies.push(sent.elapsed());
+                }
+                latencies
+            })
+        })
+        .collect();
+
+    let mut latencies = Vec::new();
+    for client in clients {
+        latencies.extend(client.await.unwrap_or_default());
+    }
+    (start.elapsed(), latencies)
+}
+
+fn report(design: &str, elapsed: Duration, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    println!(
+        "{design:<12} {:>7} moves in {:>9.1?}, {:>9.0} moves/s, latency p50 {:>9.1?} p99 {:>9.1?} max {:>9.1?}",
+        latencies.len(),
+        elapsed,
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        percentile(50),
+        percentile(99),
+        latencies[latencies.len() - 1],
+    );
+}
+
+#[actix_rt::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let games: u32 = args.next().and_then(|a| a.parse().ok()).unwrap_or(200);
+    let save = Duration::from_millis(args.next().and_then(|a| a.parse().ok()).unwrap_or(5));
+    println!("{games} games, {save:?} per save");
+
+    let (elapsed, latencies) = run(spawn_single_task(save), games).await;
+    report("single task", elapsed, latencies);
+    let (elapsed, latencies) = run(spawn_actors(save), games).await;
+    report("actors", elapsed, latencies);
+}
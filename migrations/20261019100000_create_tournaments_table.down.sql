@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+drop table TournamentPairings;
+drop table TournamentRounds;
+drop table TournamentEntrants;
+drop table Tournaments;
@@ -0,0 +1,49 @@
+create table if not exists Tournaments(
+  id int primary key auto_increment,
+  name varchar(100) not null,
+  creator int not null,
+  pairing_system enum('round_robin', 'swiss') not null,
+  num_of_rounds int not null,
+  current_round int not null default 0,
+  status enum('open', 'running', 'finished') not null default 'open',
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+
+  foreign key (creator) references User(id)
+);
+
+create table if not exists TournamentEntrants(
+  tournament int not null,
+  player int not null,
+  joined_at timestamp default CURRENT_TIMESTAMP,
+
+  primary key (tournament, player),
+  foreign key (tournament) references Tournaments(id),
+  foreign key (player) references User(id)
+);
+
+create table if not exists TournamentRounds(
+  id int primary key auto_increment,
+  tournament int not null,
+  number int not null,
+  started_at timestamp default CURRENT_TIMESTAMP,
+  finished_at timestamp null,
+
+  unique (tournament, number),
+  foreign key (tournament) references Tournaments(id)
+);
+
+-- black is null for a bye
+create table if not exists TournamentPairings(
+  id int primary key auto_increment,
+  round int not null,
+  white int not null,
+  black int,
+  game int,
+  result enum('white', 'black', 'draw'),
+
+  foreign key (round) references TournamentRounds(id),
+  foreign key (white) references User(id),
+  foreign key (black) references User(id),
+  foreign key (game) references Games(id)
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+drop table ArenaScores;
+drop table Arenas;
@@ -0,0 +1,20 @@
+create table if not exists Arenas(
+  id int primary key auto_increment,
+  name varchar(100) not null,
+  creator int not null,
+  starts_at timestamp default CURRENT_TIMESTAMP,
+  ends_at timestamp not null,
+
+  foreign key (creator) references User(id)
+);
+
+create table if not exists ArenaScores(
+  arena int not null,
+  player int not null,
+  score int not null default 0,
+  games int not null default 0,
+
+  primary key (arena, player),
+  foreign key (arena) references Arenas(id),
+  foreign key (player) references User(id)
+);
@@ -0,0 +1,15 @@
+-- open challenges anyone with the link can accept
+create table if not exists Challenges(
+  id int primary key auto_increment,
+  token char(32) not null unique,
+  creator int not null,
+  color enum('white', 'black', 'random') not null default 'random',
+  status enum('open', 'accepted', 'cancelled') not null default 'open',
+  accepted_by int,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+  expires_at timestamp not null,
+
+  foreign key (creator) references User(id),
+  foreign key (accepted_by) references User(id)
+);
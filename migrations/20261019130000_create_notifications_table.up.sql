@@ -0,0 +1,15 @@
+-- a player's inbox, friend requests and game invitations stay here until answered
+create table if not exists Notifications(
+  id int primary key auto_increment,
+  player int not null,
+  sender int,
+  kind enum('friend_request', 'game_invitation') not null,
+  payload json not null,
+  read_at timestamp null,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+
+  index (player, id),
+  foreign key (player) references User(id),
+  foreign key (sender) references User(id)
+);
@@ -0,0 +1,8 @@
+-- Add down migration script here
+
+alter table Friends
+  drop index user_low,
+  drop column user_high,
+  drop column user_low;
+
+drop table FriendRequests;
@@ -0,0 +1,39 @@
+-- one pending request per pair of players, whichever of them sent it
+create table if not exists FriendRequests(
+  id int primary key auto_increment,
+  sender int not null,
+  receiver int not null,
+  user_low int as (least(sender, receiver)) stored,
+  user_high int as (greatest(sender, receiver)) stored,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+
+  unique (user_low, user_high),
+  foreign key (sender) references User(id),
+  foreign key (receiver) references User(id)
+);
+
+-- requests that are still in the inbox
+insert ignore into FriendRequests(sender, receiver, created_at)
+  select sender, player, created_at from Notifications
+  where kind = 'friend_request' and sender is not null;
+
+update Notifications n
+  join FriendRequests r on r.sender = n.sender and r.receiver = n.player
+  set n.payload = json_set(n.payload, '$.request_id', r.id)
+  where n.kind = 'friend_request';
+
+delete from Notifications
+  where kind = 'friend_request' and json_extract(payload, '$.request_id') is null;
+
+-- the same friendship can't be stored twice
+delete f1 from Friends f1
+  join Friends f2
+    on least(f1.friend1, f1.friend2) = least(f2.friend1, f2.friend2)
+    and greatest(f1.friend1, f1.friend2) = greatest(f2.friend1, f2.friend2)
+    and f1.id > f2.id;
+
+alter table Friends
+  add column user_low int as (least(friend1, friend2)) stored,
+  add column user_high int as (greatest(friend1, friend2)) stored,
+  add unique (user_low, user_high);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+alter table User drop column appear_offline;
@@ -0,0 +1,2 @@
+-- hidden from friends' presence while set
+alter table User add column appear_offline boolean not null default false;
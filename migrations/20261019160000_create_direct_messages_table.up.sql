@@ -0,0 +1,17 @@
+-- one-to-one conversations between friends, kept after games end unlike game chat
+create table if not exists DirectMessages(
+  id int primary key auto_increment,
+  sender int not null,
+  receiver int not null,
+  user_low int as (least(sender, receiver)) stored,
+  user_high int as (greatest(sender, receiver)) stored,
+  text varchar(1000) not null,
+  read_at timestamp null,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+
+  index (user_low, user_high, id),
+  index (receiver, read_at),
+  foreign key (sender) references User(id),
+  foreign key (receiver) references User(id)
+);
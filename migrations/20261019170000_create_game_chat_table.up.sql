@@ -0,0 +1,14 @@
+-- chat of finished games, kept for replays and abuse reports
+create table if not exists GameChat(
+  id int primary key auto_increment,
+  game int not null,
+  player int not null,
+  -- number of moves played when the message was sent
+  ply int not null,
+  text text not null,
+  sent_at timestamp not null,
+
+  index (game, id),
+  foreign key (game) references Games(id),
+  foreign key (player) references User(id)
+);
@@ -0,0 +1,8 @@
+-- Add down migration script here
+
+alter table GameChat drop column preset;
+
+alter table User
+  drop column chat_muted_until,
+  drop column free_chat,
+  drop column is_moderator;
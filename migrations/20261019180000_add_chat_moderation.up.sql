@@ -0,0 +1,8 @@
+alter table User
+  add column is_moderator boolean not null default false,
+  -- without free chat a player only sends and sees preset messages
+  add column free_chat boolean not null default true,
+  -- set by moderators, no chat at all until then
+  add column chat_muted_until timestamp null;
+
+alter table GameChat add column preset boolean not null default false;
@@ -0,0 +1,13 @@
+-- blocked players can't reach the blocker: no friend requests, invitations, games or chat
+create table if not exists Blocks(
+  id int primary key auto_increment,
+  blocker int not null,
+  blocked int not null,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+
+  unique (blocker, blocked),
+  index (blocked),
+  foreign key (blocker) references User(id),
+  foreign key (blocked) references User(id)
+);
@@ -0,0 +1,13 @@
+-- Add down migration script here
+
+delete from Notifications where kind = 'moderation_warning';
+alter table Notifications
+  modify kind enum('friend_request', 'game_invitation') not null;
+
+alter table User
+  drop column closed_at,
+  drop column banned_until,
+  drop column banned;
+
+drop table ModerationActions;
+drop table Reports;
@@ -0,0 +1,47 @@
+create table if not exists Reports(
+  id int primary key auto_increment,
+  reporter int not null,
+  reported int not null,
+  -- the game it happened in, its chat is in GameChat
+  game int,
+  reason enum('cheating', 'abuse', 'sandbagging', 'other') not null,
+  comment varchar(1000) not null default '',
+  status enum('open', 'triaged', 'resolved') not null default 'open',
+  -- moderator handling the report
+  assignee int,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+  resolved_at timestamp null,
+
+  index (status, id),
+  foreign key (reporter) references User(id),
+  foreign key (reported) references User(id),
+  foreign key (game) references Games(id),
+  foreign key (assignee) references User(id)
+);
+
+-- audit trail, every moderator action is recorded here
+create table if not exists ModerationActions(
+  id int primary key auto_increment,
+  moderator int not null,
+  player int not null,
+  report int,
+  action enum('triage', 'dismiss', 'warn', 'mute', 'unmute', 'ban', 'unban', 'close_account') not null,
+  note varchar(1000) not null default '',
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+
+  index (player, id),
+  foreign key (moderator) references User(id),
+  foreign key (player) references User(id),
+  foreign key (report) references Reports(id)
+);
+
+alter table User
+  -- banned_until null with banned set is a permanent ban
+  add column banned boolean not null default false,
+  add column banned_until timestamp null,
+  add column closed_at timestamp null;
+
+alter table Notifications
+  modify kind enum('friend_request', 'game_invitation', 'moderation_warning') not null;
@@ -0,0 +1,13 @@
+-- Add down migration script here
+
+delete from ModerationActions where action in ('disable', 'enable', 'set_role');
+alter table ModerationActions
+  modify action enum('triage', 'dismiss', 'warn', 'mute', 'unmute', 'ban', 'unban', 'close_account') not null;
+
+alter table User add column is_moderator boolean not null default false;
+
+update User set is_moderator = true where role in ('moderator', 'admin');
+
+alter table User
+  drop column disabled_at,
+  drop column role;
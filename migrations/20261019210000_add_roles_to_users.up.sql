@@ -0,0 +1,14 @@
+alter table User
+  add column role enum('user', 'moderator', 'admin') not null default 'user',
+  -- set by admins, the account can't log in until it's enabled again
+  add column disabled_at timestamp null;
+
+update User set role = 'moderator' where is_moderator;
+
+alter table User drop column is_moderator;
+
+alter table ModerationActions
+  modify action enum(
+    'triage', 'dismiss', 'warn', 'mute', 'unmute', 'ban', 'unban', 'close_account',
+    'disable', 'enable', 'set_role'
+  ) not null;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+alter table User modify password varchar(128) not null;
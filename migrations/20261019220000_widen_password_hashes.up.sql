@@ -0,0 +1,2 @@
+-- argon2 PHC strings, room for stronger parameters later
+alter table User modify password varchar(255) not null;
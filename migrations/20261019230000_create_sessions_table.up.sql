@@ -0,0 +1,16 @@
+-- one per login, access tokens name their session so it can be revoked
+create table if not exists Sessions(
+  id int primary key auto_increment,
+  player int not null,
+  -- sha256 of the current refresh token, replaced every time it's used
+  refresh_hash char(64) not null,
+  user_agent varchar(255) not null default '',
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+  last_used_at timestamp default CURRENT_TIMESTAMP,
+  expires_at timestamp not null,
+  revoked_at timestamp null,
+
+  index (player, revoked_at),
+  foreign key (player) references User(id)
+);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+delete from User where username = '__deleted_account__';
@@ -0,0 +1,4 @@
+-- games and other history of deleted accounts are moved to this user,
+-- the name is too long to register and it can't log in
+insert into User(username, password, closed_at)
+  values ('__deleted_account__', '!', CURRENT_TIMESTAMP);
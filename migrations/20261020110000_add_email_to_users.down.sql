@@ -0,0 +1,7 @@
+-- Add down migration script here
+
+drop table EmailTokens;
+
+alter table User
+  drop column email_verified_at,
+  drop column email;
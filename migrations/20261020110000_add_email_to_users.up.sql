@@ -0,0 +1,22 @@
+alter table User
+  -- optional, needed for resetting the password
+  add column email varchar(255) null unique,
+  add column email_verified_at timestamp null;
+
+-- single use links sent by mail
+create table if not exists EmailTokens(
+  id int primary key auto_increment,
+  player int not null,
+  purpose enum('verify_email', 'reset_password') not null,
+  -- sha256 of the token in the link
+  token_hash char(64) not null unique,
+  -- the address the mail went to, a token stops working if the email changes
+  email varchar(255) not null,
+
+  created_at timestamp default CURRENT_TIMESTAMP,
+  expires_at timestamp not null,
+  used_at timestamp null,
+
+  index (player, purpose),
+  foreign key (player) references User(id)
+);
@@ -0,0 +1,16 @@
+-- Add down migration script here
+
+delete from ModerationActions where action in ('require_two_factor', 'reset_two_factor');
+alter table ModerationActions
+  modify action enum(
+    'triage', 'dismiss', 'warn', 'mute', 'unmute', 'ban', 'unban', 'close_account',
+    'disable', 'enable', 'set_role'
+  ) not null;
+
+drop table RecoveryCodes;
+
+alter table User
+  drop column two_factor_required,
+  drop column totp_last_step,
+  drop column totp_enabled_at,
+  drop column totp_secret;
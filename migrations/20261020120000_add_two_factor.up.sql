@@ -0,0 +1,26 @@
+alter table User
+  -- base32, set when enrolling, only used once the first code confirmed it
+  add column totp_secret varchar(64) null,
+  add column totp_enabled_at timestamp null,
+  -- time step of the last accepted code, so a code works only once
+  add column totp_last_step bigint null,
+  -- set by admins, the player has to enroll at the next login
+  add column two_factor_required boolean not null default false;
+
+-- single use codes for when the authenticator is lost
+create table if not exists RecoveryCodes(
+  id int primary key auto_increment,
+  player int not null,
+  -- sha256 of the code
+  code_hash char(64) not null,
+  used_at timestamp null,
+
+  index (player, code_hash),
+  foreign key (player) references User(id)
+);
+
+alter table ModerationActions
+  modify action enum(
+    'triage', 'dismiss', 'warn', 'mute', 'unmute', 'ban', 'unban', 'close_account',
+    'disable', 'enable', 'set_role', 'require_two_factor', 'reset_two_factor'
+  ) not null;
@@ -0,0 +1,2 @@
+-- options a game invitation was sent with, so they survive a restart
+alter table Notifications add column game_options json null;
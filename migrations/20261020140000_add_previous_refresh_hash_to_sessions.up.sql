@@ -0,0 +1,2 @@
+-- the refresh token replaced last, presenting it again means it was stolen
+alter table Sessions add column previous_refresh_hash char(64) null;
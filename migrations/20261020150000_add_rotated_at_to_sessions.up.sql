@@ -0,0 +1,2 @@
+-- when the refresh token was last replaced, the previous one is still accepted shortly after
+alter table Sessions add column rotated_at timestamp null;
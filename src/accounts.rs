@@ -0,0 +1,142 @@
+//! Deleting accounts. Personal data goes, history (games, tournaments, reports)
+//! stays but is moved to the `__deleted_account__` placeholder user.
+
+use sqlx::{MySql, Pool};
+
+use crate::PlayerId;
+
+/// username of the placeholder, see its migration
+pub const DELETED_ACCOUNT_USERNAME: &str = "__deleted_account__";
+
+/// in one transaction, the User row is gone at the end
+pub async fn delete(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    let player = player_id as u64;
+    let placeholder = sqlx::query!(
+        "SELECT id FROM User WHERE username=?",
+        DELETED_ACCOUNT_USERNAME
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .id;
+
+    // personal data
+    sqlx::query!("DELETE FROM Sessions WHERE player=?", player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM EmailTokens WHERE player=?", player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM RecoveryCodes WHERE player=?", player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM Friends WHERE friend1=? OR friend2=?", player, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM FriendRequests WHERE sender=? OR receiver=?",
+        player,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM Notifications WHERE player=? OR sender=?",
+        player,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM DirectMessages WHERE sender=? OR receiver=?",
+        player,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM Blocks WHERE blocker=? OR blocked=?", player, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM Challenges WHERE creator=? AND status='open'",
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    // keyed by player, so they can't all be moved to the placeholder
+    sqlx::query!("DELETE FROM TournamentEntrants WHERE player=?", player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM ArenaScores WHERE player=?", player)
+        .execute(&mut *tx)
+        .await?;
+
+    // history
+    sqlx::query!("UPDATE Games SET white=? WHERE white=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE Games SET black=? WHERE black=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE GameChat SET player=? WHERE player=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "UPDATE TournamentPairings SET white=? WHERE white=?",
+        placeholder,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE TournamentPairings SET black=? WHERE black=?",
+        placeholder,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("UPDATE Tournaments SET creator=? WHERE creator=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE Arenas SET creator=? WHERE creator=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE Challenges SET creator=? WHERE creator=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "UPDATE Challenges SET accepted_by=? WHERE accepted_by=?",
+        placeholder,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("UPDATE Reports SET reporter=? WHERE reporter=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE Reports SET reported=? WHERE reported=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE Reports SET assignee=? WHERE assignee=?", placeholder, player)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "UPDATE ModerationActions SET moderator=? WHERE moderator=?",
+        placeholder,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE ModerationActions SET player=? WHERE player=?",
+        placeholder,
+        player,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM User WHERE id=?", player)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
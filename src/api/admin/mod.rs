@@ -0,0 +1,436 @@
+use actix_web::{web, HttpResponse, Scope};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use tokio::sync::{mpsc::Sender, oneshot};
+
+use crate::{
+    api::protocol::{ServerEnvelope, ServerMessage},
+    chess_logic::GameResult,
+    connections::Connections,
+    extractors::role::Admin,
+    game_organizer::GameOrganizerRequest,
+    moderation::{self, ModerationAction},
+    roles::Role,
+    two_factor, GameId, PlayerId,
+};
+
+const MAX_REASON_LENGTH: usize = 1000;
+const MAX_SEARCH_RESULTS: u32 = 20;
+
+pub fn admin_scope() -> Scope {
+    web::scope("/admin")
+        .route("/games", web::get().to(list_games))
+        .route("/games/{game_id}", web::get().to(inspect_game))
+        .route("/games/{game_id}/end", web::post().to(end_game))
+        .route("/broadcast", web::post().to(broadcast))
+        .route("/users", web::get().to(find_users))
+        .route("/users/{player_id}", web::get().to(get_user))
+        .route("/users/{player_id}/disable", web::post().to(disable_user))
+        .route("/users/{player_id}/disable", web::delete().to(enable_user))
+        .route("/users/{player_id}/role", web::put().to(set_role))
+        .route("/users/{player_id}/two_factor", web::put().to(require_two_factor))
+        .route("/users/{player_id}/two_factor", web::delete().to(reset_two_factor))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EndGame {
+    /// shown to both players
+    reason: String,
+    /// a draw if not given
+    result: Option<GameResult>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Broadcast {
+    message: String,
+    /// seconds until the maintenance starts
+    starts_in: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearch {
+    /// start of the username
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Disable {
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRole {
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequireTwoFactor {
+    required: bool,
+}
+
+/// running games, not the finished ones waiting for a rematch
+pub async fn list_games(
+    _admin: Admin,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+) -> HttpResponse {
+    let (tx, rx) = oneshot::channel();
+    let _ = game_organizer.send(GameOrganizerRequest::ListGames(tx)).await;
+    match rx.await {
+        Ok(games) => HttpResponse::Ok().json(games),
+        Err(_) => {
+            HttpResponse::InternalServerError().json(json!({"reason": "Organizer is down"}))
+        }
+    }
+}
+
+/// moves, chat and draw offers of a running game
+pub async fn inspect_game(
+    _admin: Admin,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    game_id: web::Path<GameId>,
+) -> HttpResponse {
+    let (tx, rx) = oneshot::channel();
+    let _ = game_organizer
+        .send(GameOrganizerRequest::InspectGame(game_id.into_inner(), tx))
+        .await;
+    match rx.await {
+        Ok(Some(game)) => HttpResponse::Ok().json(game),
+        // the game could finish before it answers, then the reply is dropped
+        Ok(None) | Err(_) => HttpResponse::NotFound().json(json!({"reason": "No running game"})),
+    }
+}
+
+/// the game is saved with the result, so tournaments and arenas count it as usual
+pub async fn end_game(
+    admin: Admin,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    game_id: web::Path<GameId>,
+    data: web::Json<EndGame>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    let reason = data.reason.trim().to_string();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"reason": "A reason is required"}));
+    }
+
+    let game_id = game_id.into_inner();
+    let result = data.result.unwrap_or(GameResult::Draw);
+    let (tx, rx) = oneshot::channel();
+    let _ = game_organizer
+        .send(GameOrganizerRequest::ForceEnd(game_id, result, reason, tx))
+        .await;
+    match rx.await {
+        Ok(true) => {
+            println!("game {game_id} ended by admin {}", admin.id);
+            HttpResponse::Ok().json(json!({"result": result}))
+        }
+        Ok(false) | Err(_) => HttpResponse::NotFound().json(json!({"reason": "No running game"})),
+    }
+}
+
+/// maintenance notice to every open socket
+pub async fn broadcast(
+    admin: Admin,
+    connections: web::Data<Connections>,
+    data: web::Json<Broadcast>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    if data.message.trim().is_empty() || data.message.chars().count() > MAX_REASON_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"reason": "Bad message"}));
+    }
+
+    let message: ServerEnvelope = ServerMessage::Maintenance {
+        message: data.message,
+        starts_in: data.starts_in,
+    }
+    .into();
+    let sockets = connections.broadcast(message.to_ws()).await;
+    println!("admin {} broadcast to {sockets} sockets", admin.id);
+    HttpResponse::Ok().json(json!({"sockets": sockets}))
+}
+
+pub async fn find_users(
+    _admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    search: web::Query<UserSearch>,
+) -> HttpResponse {
+    // LIKE wildcards in the username are matched literally
+    let prefix = search
+        .username
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    match sqlx::query!(
+        "SELECT id, username, role FROM User
+        WHERE username LIKE CONCAT(?, '%')
+        ORDER BY username
+        LIMIT ?",
+        prefix,
+        MAX_SEARCH_RESULTS,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(users) => {
+            let users: Vec<serde_json::Value> = users
+                .into_iter()
+                .map(|u| {
+                    json!({
+                        "id": u.id,
+                        "username": u.username,
+                        "role": Role::from_db(&u.role),
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(users)
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn get_user(
+    _admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    connections: web::Data<Connections>,
+    player_id: web::Path<PlayerId>,
+) -> HttpResponse {
+    let player_id = player_id.into_inner();
+    let user = match sqlx::query!(
+        "SELECT id, username, country, role, banned `banned!: bool`,
+        UNIX_TIMESTAMP(banned_until) `banned_until: i64`,
+        UNIX_TIMESTAMP(closed_at) `closed_at: i64`,
+        UNIX_TIMESTAMP(disabled_at) `disabled_at: i64`,
+        UNIX_TIMESTAMP(chat_muted_until) `chat_muted_until: i64`,
+        UNIX_TIMESTAMP(totp_enabled_at) `totp_enabled_at: i64`,
+        two_factor_required `two_factor_required!: bool`,
+        (SELECT COUNT(*) FROM Games WHERE white = User.id OR black = User.id) `games!: i64`,
+        (SELECT COUNT(*) FROM Reports WHERE reported = User.id AND status != 'resolved')
+        `open_reports!: i64`
+        FROM User WHERE id=?",
+        player_id as u64,
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"reason": "Player not found"})),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    };
+
+    HttpResponse::Ok().json(json!({
+        "id": user.id,
+        "username": user.username,
+        "country": user.country,
+        "role": Role::from_db(&user.role),
+        "online": connections.is_online(player_id),
+        "banned": user.banned,
+        "banned_until": user.banned_until,
+        "closed_at": user.closed_at,
+        "disabled_at": user.disabled_at,
+        "chat_muted_until": user.chat_muted_until,
+        "two_factor_enabled": user.totp_enabled_at.is_some(),
+        "two_factor_required": user.two_factor_required,
+        "games": user.games,
+        "open_reports": user.open_reports,
+    }))
+}
+
+/// the player can't log in or use an old token until enabled again
+pub async fn disable_user(
+    admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    player_id: web::Path<PlayerId>,
+    data: web::Json<Disable>,
+) -> HttpResponse {
+    let player_id = player_id.into_inner();
+    if player_id == admin.id {
+        return HttpResponse::BadRequest().json(json!({"reason": "You cant disable yourself"}));
+    }
+    set_disabled(&db_pool, admin.id, player_id, true, &data.reason).await
+}
+
+pub async fn enable_user(
+    admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    player_id: web::Path<PlayerId>,
+) -> HttpResponse {
+    set_disabled(&db_pool, admin.id, player_id.into_inner(), false, "").await
+}
+
+/// recorded in the moderation audit trail
+async fn set_disabled(
+    db_pool: &Pool<MySql>,
+    admin: PlayerId,
+    player_id: PlayerId,
+    disable: bool,
+    reason: &str,
+) -> HttpResponse {
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let updated = if disable {
+        sqlx::query!(
+            "UPDATE User SET disabled_at=COALESCE(disabled_at, CURRENT_TIMESTAMP) WHERE id=?",
+            player_id as u64,
+        )
+        .execute(&mut *tx)
+        .await
+    } else {
+        sqlx::query!("UPDATE User SET disabled_at=NULL WHERE id=?", player_id as u64)
+            .execute(&mut *tx)
+            .await
+    };
+    match updated {
+        Ok(res) if res.rows_affected() == 1 => {}
+        // nothing changed, the player is already in that state or doesn't exist
+        Ok(_) => {
+            return HttpResponse::NotFound()
+                .json(json!({"reason": "Player not found or already in that state"}))
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    let action = if disable {
+        ModerationAction::Disable
+    } else {
+        ModerationAction::Enable
+    };
+    let recorded = moderation::record_action(&mut tx, admin, player_id, None, action, reason).await;
+    if recorded.is_err() || tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    println!("player {player_id}: {} by admin {admin}", action.as_str());
+    HttpResponse::Ok().into()
+}
+
+/// the token picks up the new role on its next refresh, at most `ACCESS_TOKEN_MINUTES` later
+pub async fn set_role(
+    admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    player_id: web::Path<PlayerId>,
+    data: web::Json<SetRole>,
+) -> HttpResponse {
+    let player_id = player_id.into_inner();
+    if player_id == admin.id {
+        return HttpResponse::BadRequest().json(json!({"reason": "You cant change your own role"}));
+    }
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let exists = sqlx::query!("SELECT id FROM User WHERE id=? FOR UPDATE", player_id as u64)
+        .fetch_optional(&mut *tx)
+        .await;
+    match exists {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().json(json!({"reason": "Player not found"})),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    let updated = sqlx::query!(
+        "UPDATE User SET role=? WHERE id=?",
+        data.role.as_str(),
+        player_id as u64,
+    )
+    .execute(&mut *tx)
+    .await;
+    let recorded = moderation::record_action(
+        &mut tx,
+        admin.id,
+        player_id,
+        None,
+        ModerationAction::SetRole,
+        data.role.as_str(),
+    )
+    .await;
+    if updated.is_err() || recorded.is_err() || tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    println!("player {player_id} is now {}", data.role.as_str());
+    HttpResponse::Ok().json(json!({"role": data.role}))
+}
+
+/// the player has to set up two-factor at the next login, running sessions aren't affected
+pub async fn require_two_factor(
+    admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    player_id: web::Path<PlayerId>,
+    data: web::Json<RequireTwoFactor>,
+) -> HttpResponse {
+    let player_id = player_id.into_inner();
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let updated = sqlx::query!(
+        "UPDATE User SET two_factor_required=? WHERE id=?",
+        data.required,
+        player_id as u64,
+    )
+    .execute(&mut *tx)
+    .await;
+    match updated {
+        Ok(res) if res.rows_affected() == 1 => {}
+        Ok(_) => {
+            return HttpResponse::NotFound()
+                .json(json!({"reason": "Player not found or already in that state"}))
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    let note = if data.required { "required" } else { "not required" };
+    let recorded = moderation::record_action(
+        &mut tx,
+        admin.id,
+        player_id,
+        None,
+        ModerationAction::RequireTwoFactor,
+        note,
+    )
+    .await;
+    if recorded.is_err() || tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    println!("two-factor {note} for player {player_id} by admin {}", admin.id);
+    HttpResponse::Ok().json(json!({"required": data.required}))
+}
+
+/// when the player lost the app and the recovery codes, they can enroll again
+pub async fn reset_two_factor(
+    admin: Admin,
+    db_pool: web::Data<Pool<MySql>>,
+    player_id: web::Path<PlayerId>,
+) -> HttpResponse {
+    let player_id = player_id.into_inner();
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    match two_factor::disable(&mut tx, player_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::NotFound()
+                .json(json!({"reason": "Player not found or two-factor isnt on"}))
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    let recorded = moderation::record_action(
+        &mut tx,
+        admin.id,
+        player_id,
+        None,
+        ModerationAction::ResetTwoFactor,
+        "",
+    )
+    .await;
+    if recorded.is_err() || tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    println!("two-factor of player {player_id} reset by admin {}", admin.id);
+    HttpResponse::Ok().into()
+}
@@ -0,0 +1,141 @@
+use actix_web::{web, HttpResponse, Scope};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    extractors::authentication_token::AuthenticationToken,
+    game_organizer::GameOrganizerRequest,
+    tournament::arena::ArenaId,
+};
+
+/// players join and leave an arena over the websocket, so they can be paired right away
+pub fn arena_scope() -> Scope {
+    web::scope("/arena")
+        .route("/", web::post().to(create_arena))
+        .route("/", web::get().to(list_arenas))
+        .route("/{id}", web::get().to(get_arena))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewArena {
+    name: String,
+    minutes: u32,
+}
+
+pub async fn create_arena(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    data: web::Json<NewArena>,
+) -> HttpResponse {
+    if !(1..=180).contains(&data.minutes) {
+        return HttpResponse::BadRequest()
+            .json(json!({"reason": "Arena must last between 1 and 180 minutes"}));
+    }
+    if data.name.trim().is_empty() || data.name.len() > 100 {
+        return HttpResponse::BadRequest().json(json!({"reason": "Invalid arena name"}));
+    }
+
+    let arena_id = match sqlx::query!(
+        "INSERT INTO Arenas(name, creator, ends_at)
+        VALUES (?, ?, CURRENT_TIMESTAMP + INTERVAL ? MINUTE)",
+        data.name.trim(),
+        id.id as u64,
+        data.minutes,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(res) => res.last_insert_id(),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    let _ = game_organizer
+        .send(GameOrganizerRequest::NewArena(
+            arena_id,
+            data.name.trim().to_string(),
+            Duration::from_secs(data.minutes as u64 * 60),
+        ))
+        .await;
+
+    HttpResponse::Ok().json(json!({"id": arena_id}))
+}
+
+/// arenas that are still running
+pub async fn list_arenas(db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    match sqlx::query!(
+        "SELECT a.id, a.name, TIMESTAMPDIFF(SECOND, CURRENT_TIMESTAMP, a.ends_at) seconds_left,
+        (SELECT COUNT(*) FROM ArenaScores s WHERE s.arena = a.id) players
+        FROM Arenas a
+        WHERE a.ends_at > CURRENT_TIMESTAMP
+        ORDER BY a.ends_at"
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(rows) => HttpResponse::Ok().json(
+            rows.into_iter()
+                .map(|a| {
+                    json!({
+                        "id": a.id,
+                        "name": a.name,
+                        "seconds_left": a.seconds_left,
+                        "players": a.players,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// leaderboard as saved in db, the live one is pushed over the websocket
+pub async fn get_arena(
+    arena_id: web::Path<ArenaId>,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    let arena_id = arena_id.into_inner();
+    let arena = match sqlx::query!(
+        "SELECT id, name, TIMESTAMPDIFF(SECOND, CURRENT_TIMESTAMP, ends_at) `seconds_left!`
+        FROM Arenas WHERE id=?",
+        arena_id,
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(a) => a,
+        Err(_) => return HttpResponse::NotFound().json(json!({"reason": "Arena not found"})),
+    };
+
+    match sqlx::query!(
+        "SELECT s.player, u.username, u.country, s.score, s.games FROM ArenaScores s
+        JOIN User u ON s.player = u.id
+        WHERE s.arena=?
+        ORDER BY s.score DESC, s.games",
+        arena_id,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(rows) => HttpResponse::Ok().json(json!({
+            "id": arena.id,
+            "name": arena.name,
+            "seconds_left": arena.seconds_left.max(0),
+            "finished": arena.seconds_left <= 0,
+            "standings": rows
+                .into_iter()
+                .map(|s| json!({
+                    "player": s.player,
+                    "username": s.username,
+                    "country": s.country,
+                    "score": s.score,
+                    "games": s.games,
+                }))
+                .collect::<Vec<_>>(),
+        })),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
@@ -0,0 +1,318 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+
+use super::{
+    account_key, email,
+    password::{self, Verified},
+    register::{check_password_req, check_username_req},
+    session, AuthLimiters,
+};
+use crate::{
+    accounts, extractors::authentication_token::AuthenticationToken, mailer::Mailer,
+    presence::Presence, rate_limit::too_many_attempts, sessions, PlayerId,
+};
+
+const MAX_COUNTRY_LENGTH: usize = 55;
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    old_password: String,
+    new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeUsername {
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetCountry {
+    /// null clears it
+    country: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetEmail {
+    /// null removes it
+    email: Option<String>,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccount {
+    password: String,
+}
+
+/// the reply is ready to send if the password is wrong.
+/// Counted with the logins of the account, so a stolen access token can't guess the password
+async fn check_current_password(
+    db_pool: &Pool<MySql>,
+    limiters: &AuthLimiters,
+    player_id: PlayerId,
+    password: String,
+) -> Result<(), HttpResponse> {
+    let user = match sqlx::query!(
+        "SELECT username, password FROM User WHERE id=?",
+        player_id as u64
+    )
+    .fetch_one(db_pool)
+    .await
+    {
+        Ok(user) => user,
+        Err(_) => {
+            return Err(HttpResponse::InternalServerError().json(json!({"reason": "db fail"})))
+        }
+    };
+    let account = account_key(&user.username);
+    limiters
+        .login_account
+        .check(&account)
+        .map_err(too_many_attempts)?;
+    match password::verify_blocking(password, user.password).await {
+        Verified::Wrong => Err(HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad password", "description": "Wrong password"}))),
+        Verified::Correct | Verified::CorrectLegacy => {
+            limiters.login_account.clear(&account);
+            Ok(())
+        }
+    }
+}
+
+/// ends every session, this client gets a new one
+pub async fn change_password(
+    id: AuthenticationToken,
+    req: HttpRequest,
+    db_pool: web::Data<Pool<MySql>>,
+    secret: web::Data<String>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<ChangePassword>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    if let Err(reason) = check_password_req(&data.new_password) {
+        return HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad password", "description": reason}));
+    }
+
+    let old_password = data.old_password;
+    if let Err(reply) = check_current_password(&db_pool, &limiters, id.id, old_password).await {
+        return reply;
+    }
+    let new_hash = password::hash_blocking(data.new_password).await;
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let updated = sqlx::query!(
+        "UPDATE User SET password=? WHERE id=?",
+        new_hash,
+        id.id as u64,
+    )
+    .execute(&mut *tx)
+    .await;
+    let revoked = sessions::revoke_all(&mut tx, id.id).await;
+    let revoked = match (updated, revoked) {
+        (Ok(_), Ok(revoked)) => revoked,
+        _ => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    if tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    println!("player {} changed the password, {revoked} sessions ended", id.id);
+
+    match session::start(&req, &db_pool, id.id, id.role, &secret).await {
+        Ok(cookies) => session::ok_with(cookies).json(json!({"revoked": revoked})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// same rules as when registering
+pub async fn change_username(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<ChangeUsername>,
+) -> HttpResponse {
+    let username = data.into_inner().username;
+    if let Err(reason) = check_username_req(&username) {
+        return HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad username", "description": reason}));
+    }
+    let taken = || {
+        HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad username", "description": "Username already taken"}))
+    };
+
+    let user_exists = sqlx::query!(
+        "SELECT id FROM User WHERE username=? AND id != ?",
+        username,
+        id.id as u64,
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await;
+    match user_exists {
+        Ok(None) => {}
+        Ok(Some(_)) => return taken(),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    match sqlx::query!(
+        "UPDATE User SET username=? WHERE id=?",
+        username,
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({"username": username})),
+        // someone took it since the check
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => taken(),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// shown everywhere the player's name is
+pub async fn set_country(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<SetCountry>,
+) -> HttpResponse {
+    let country = data
+        .into_inner()
+        .country
+        .map(|country| country.trim().to_string())
+        .filter(|country| !country.is_empty());
+    if let Some(country) = &country {
+        if country.chars().count() > MAX_COUNTRY_LENGTH {
+            return HttpResponse::BadRequest().json(json!({"reason": "Country is too long"}));
+        }
+    }
+
+    match sqlx::query!(
+        "UPDATE User SET country=? WHERE id=?",
+        country,
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({"country": country})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// needs the password since resets go to this address, it's unverified until the mailed link
+/// is opened
+pub async fn set_email(
+    req: HttpRequest,
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    mailer: web::Data<dyn Mailer>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<SetEmail>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    let new_email = match data.email.as_deref().map(email::normalize_email) {
+        None => None,
+        Some(Ok(email)) => Some(email),
+        Some(Err(reason)) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"reason": "Bad email", "description": reason}))
+        }
+    };
+    if let Err(reply) = check_current_password(&db_pool, &limiters, id.id, data.password).await {
+        return reply;
+    }
+    let used = || {
+        HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad email", "description": "Email already used"}))
+    };
+
+    let user = match sqlx::query!("SELECT username, email FROM User WHERE id=?", id.id as u64)
+        .fetch_one(db_pool.get_ref())
+        .await
+    {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    if user.email == new_email {
+        return HttpResponse::Ok().json(json!({"email": new_email}));
+    }
+    if let Some(new_email) = &new_email {
+        match sqlx::query!(
+            "SELECT id FROM User WHERE email=? AND id != ?",
+            new_email,
+            id.id as u64,
+        )
+        .fetch_optional(db_pool.get_ref())
+        .await
+        {
+            Ok(None) => {}
+            Ok(Some(_)) => return used(),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}))
+            }
+        }
+        // before anything changes, the new address gets a mail
+        if let Err(reply) = email::check_verification_limit(&limiters, &req, id.id) {
+            return reply;
+        }
+    }
+
+    match sqlx::query!(
+        "UPDATE User SET email=?, email_verified_at=NULL WHERE id=?",
+        new_email,
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => return used(),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+    if let Some(new_email) = &new_email {
+        let sent = email::send_verification(
+            &db_pool,
+            mailer.into_inner(),
+            id.id,
+            &user.username,
+            new_email,
+        )
+        .await;
+        if let Err(e) = sent {
+            println!("Couldn't send the verification mail to {}: {e}", id.id);
+        }
+    }
+    HttpResponse::Ok().json(json!({"email": new_email}))
+}
+
+/// can't be undone, played games stay but show the placeholder instead of the player
+pub async fn delete_account(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    presence: web::Data<Presence>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<DeleteAccount>,
+) -> HttpResponse {
+    let password = data.into_inner().password;
+    if let Err(reply) = check_current_password(&db_pool, &limiters, id.id, password).await {
+        return reply;
+    }
+    // the game would be saved with a player that doesn't exist anymore
+    if presence.in_game(id.id) {
+        return HttpResponse::BadRequest().json(json!({"reason": "Finish your games first"}));
+    }
+
+    match accounts::delete(&db_pool, id.id).await {
+        Ok(()) => {
+            println!("player {} deleted the account", id.id);
+            session::ok_with(session::removal_cookies()).finish()
+        }
+        Err(e) => {
+            println!("Couldn't delete account {}: {e}", id.id);
+            HttpResponse::InternalServerError().json(json!({"reason": "db fail"}))
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+
+use super::{ip_key, password, register::check_password_req, AuthLimiters};
+use crate::{
+    email_tokens::{self, Purpose, RESET_PASSWORD_MINUTES, VERIFY_EMAIL_HOURS},
+    extractors::authentication_token::AuthenticationToken,
+    mailer::{self, Mail, Mailer},
+    rate_limit::too_many_attempts,
+    sessions, PlayerId,
+};
+
+const MAX_EMAIL_LENGTH: usize = 255;
+
+#[derive(Debug, Deserialize)]
+pub struct TokenBody {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordReset {
+    token: String,
+    new_password: String,
+}
+
+/// trimmed and lowercased, only a rough check, the verification mail is the real one
+pub(super) fn normalize_email(email: &str) -> Result<String, String> {
+    let email = email.trim().to_lowercase();
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Err("Email is too long".into());
+    }
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+        }
+        None => false,
+    };
+    if !valid || email.chars().any(char::is_whitespace) {
+        return Err("Not a valid email".into());
+    }
+    Ok(email)
+}
+
+/// before every verification mail, otherwise the server could be made to mail any address
+/// over and over
+pub(super) fn check_verification_limit(
+    limiters: &AuthLimiters,
+    req: &HttpRequest,
+    player_id: PlayerId,
+) -> Result<(), HttpResponse> {
+    let ip = ip_key(req);
+    if let Err(wait) = limiters.verify_ip.check(&ip) {
+        return Err(too_many_attempts(wait));
+    }
+    if let Err(wait) = limiters.verify_account.check(&player_id.to_string()) {
+        limiters.verify_ip.refund(&ip);
+        return Err(too_many_attempts(wait));
+    }
+    Ok(())
+}
+
+/// failures to send are only logged, the player can ask for another mail
+pub(super) async fn send_verification(
+    db_pool: &Pool<MySql>,
+    mailer: Arc<dyn Mailer>,
+    player_id: PlayerId,
+    username: &str,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    let token = email_tokens::create(db_pool, player_id, Purpose::VerifyEmail, email).await?;
+    let link = mailer::link(&format!("/verify_email?token={token}"));
+    mailer::send_in_background(
+        mailer,
+        Mail {
+            to: email.to_string(),
+            subject: "Verify your email".into(),
+            body: format!(
+                "Hi {username},\n\nopen this link to verify your email:\n{link}\n\n\
+                It works for {VERIFY_EMAIL_HOURS} hours."
+            ),
+        },
+    );
+    Ok(())
+}
+
+/// no login needed, the link can be opened on any device
+pub async fn verify_email(
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<TokenBody>,
+) -> HttpResponse {
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let (player_id, email) =
+        match email_tokens::consume(&mut tx, &data.token, Purpose::VerifyEmail).await {
+            Ok(Some(consumed)) => consumed,
+            Ok(None) => return HttpResponse::BadRequest().json(json!({"reason": "Invalid link"})),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}))
+            }
+        };
+    let verified = sqlx::query!(
+        "UPDATE User SET email_verified_at=CURRENT_TIMESTAMP WHERE id=? AND email=?",
+        player_id as u64,
+        email,
+    )
+    .execute(&mut *tx)
+    .await;
+    let verified = match verified {
+        Ok(res) => res.rows_affected() == 1,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    if tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+
+    if verified {
+        HttpResponse::Ok().json(json!({"email": email}))
+    } else {
+        // changed since the mail was sent
+        HttpResponse::BadRequest().json(json!({"reason": "Invalid link"}))
+    }
+}
+
+pub async fn resend_verification(
+    req: HttpRequest,
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    mailer: web::Data<dyn Mailer>,
+    limiters: web::Data<AuthLimiters>,
+) -> HttpResponse {
+    let user = match sqlx::query!(
+        "SELECT username, email, UNIX_TIMESTAMP(email_verified_at) `email_verified_at: i64`
+        FROM User WHERE id=?",
+        id.id as u64,
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let email = match (user.email, user.email_verified_at) {
+        (None, _) => return HttpResponse::BadRequest().json(json!({"reason": "No email"})),
+        (Some(_), Some(_)) => {
+            return HttpResponse::BadRequest().json(json!({"reason": "Email already verified"}))
+        }
+        (Some(email), None) => email,
+    };
+    if let Err(reply) = check_verification_limit(&limiters, &req, id.id) {
+        return reply;
+    }
+
+    let sent = send_verification(
+        &db_pool,
+        mailer.into_inner(),
+        id.id,
+        &user.username,
+        &email,
+    )
+    .await;
+    match sent {
+        Ok(()) => HttpResponse::Ok().into(),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// the reply is the same whether an account has this email or not
+pub async fn request_password_reset(
+    req: HttpRequest,
+    db_pool: web::Data<Pool<MySql>>,
+    mailer: web::Data<dyn Mailer>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<PasswordResetRequest>,
+) -> HttpResponse {
+    let ip = ip_key(&req);
+    // every request counts, not only the ones that send a mail
+    if let Err(wait) = limiters.reset_ip.check(&ip) {
+        return too_many_attempts(wait);
+    }
+    let email = match normalize_email(&data.email) {
+        Ok(email) => email,
+        Err(reason) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"reason": "Bad email", "description": reason}))
+        }
+    };
+    // an unverified address could be someone else's
+    let user = sqlx::query!(
+        "SELECT id, username FROM User WHERE email=? AND email_verified_at IS NOT NULL",
+        email,
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await;
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::Ok().into(),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    let token =
+        match email_tokens::create(&db_pool, user.id as PlayerId, Purpose::ResetPassword, &email)
+            .await
+        {
+            Ok(token) => token,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}))
+            }
+        };
+    let link = mailer::link(&format!("/reset_password?token={token}"));
+    mailer::send_in_background(
+        mailer.into_inner(),
+        Mail {
+            to: email,
+            subject: "Reset your password".into(),
+            body: format!(
+                "Hi {},\n\nopen this link to choose a new password:\n{link}\n\n\
+                It works for {RESET_PASSWORD_MINUTES} minutes. \
+                If you didn't ask for this you can ignore this mail.",
+                user.username
+            ),
+        },
+    );
+    HttpResponse::Ok().into()
+}
+
+/// ends every session, the player logs in again with the new password
+pub async fn reset_password(
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<PasswordReset>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    if let Err(reason) = check_password_req(&data.new_password) {
+        return HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad password", "description": reason}));
+    }
+    // before the transaction, hashing is slow
+    let new_hash = password::hash_blocking(data.new_password).await;
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let (player_id, email) =
+        match email_tokens::consume(&mut tx, &data.token, Purpose::ResetPassword).await {
+            Ok(Some(consumed)) => consumed,
+            Ok(None) => return HttpResponse::BadRequest().json(json!({"reason": "Invalid link"})),
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}))
+            }
+        };
+    let updated = sqlx::query!(
+        "UPDATE User SET password=? WHERE id=? AND email=?",
+        new_hash,
+        player_id as u64,
+        email,
+    )
+    .execute(&mut *tx)
+    .await;
+    match updated {
+        Ok(res) if res.rows_affected() == 1 => {}
+        Ok(_) => return HttpResponse::BadRequest().json(json!({"reason": "Invalid link"})),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+    let revoked = match sessions::revoke_all(&mut tx, player_id).await {
+        Ok(revoked) => revoked,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    if tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    println!("player {player_id} reset the password, {revoked} sessions ended");
+    HttpResponse::Ok().json(json!({"revoked": revoked}))
+}
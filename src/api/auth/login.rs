@@ -1,13 +1,16 @@
-use actix_web::{
-    cookie::{time::OffsetDateTime, CookieBuilder},
-    web, HttpResponse,
-};
-use chrono::{Duration, Utc};
+use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::{MySql, Pool};
 
-use super::{calculate_hash, encode_token};
+use super::{
+    account_key, ip_key,
+    password::{self, Verified},
+    session,
+    two_factor::{encode_partial_token, Stage, PARTIAL_TOKEN_SECONDS},
+    AuthLimiters,
+};
+use crate::{moderation, rate_limit::too_many_attempts, roles::Role, two_factor};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginBody {
@@ -18,16 +21,38 @@ pub struct LoginBody {
 struct UserSelect {
     id: i32,
     password: String,
+    role: String,
 }
 
 pub async fn login(
+    req: HttpRequest,
     credentials: web::Json<LoginBody>,
     secret: web::Data<String>,
     db_pool: web::Data<Pool<MySql>>,
+    limiters: web::Data<AuthLimiters>,
 ) -> HttpResponse {
+    let ip = ip_key(&req);
+    // two-factor codes use `player:<id>`
+    let account = account_key(&credentials.username);
+    // both count as failed until the password turns out right
+    if let Err(wait) = limiters.login_ip.check(&ip) {
+        return too_many_attempts(wait);
+    }
+    if let Err(wait) = limiters.login_account.check(&account) {
+        limiters.login_ip.refund(&ip);
+        return too_many_attempts(wait);
+    }
+    // the same for a wrong username and a wrong password, so usernames can't be probed
+    let invalid_credentials = || {
+        HttpResponse::BadRequest().json(json!({
+            "reason": "Invalid credentials",
+            "description": "Wrong username or password"
+        }))
+    };
+
     let user: UserSelect = match sqlx::query_as!(
         UserSelect,
-        "SELECT id, password
+        "SELECT id, password, role
         FROM User
         WHERE username = ?",
         credentials.username
@@ -37,27 +62,73 @@ pub async fn login(
     {
         Ok(user) => user,
         Err(sqlx::Error::RowNotFound) => {
-            return HttpResponse::BadRequest()
-                .json(json!({"reason": "Bad username", "description": "User not found"}))
+            password::verify_dummy_blocking(credentials.password.clone()).await;
+            return invalid_credentials();
         }
         Err(err) => panic!("Unexpected error, {}", err),
     };
-    let token = encode_token(user.id as usize, secret).await;
-    let cookie = CookieBuilder::new(crate::extractors::authentication_token::COOKIE_NAME, token)
-        .http_only(true)
-        .path("/")
-        .expires(
-            OffsetDateTime::from_unix_timestamp((Utc::now() + Duration::days(30)).timestamp())
-                .unwrap(),
-        )
-        .finish();
-
-    if user.password == calculate_hash(&credentials.password).to_string() {
-        HttpResponse::Ok().cookie(cookie).json(json!({
-            "id": user.id,
-        }))
-    } else {
-        HttpResponse::BadRequest()
-            .json(json!({"reason": "Bad password", "description": "Wrong password"}))
+    let verified =
+        password::verify_blocking(credentials.password.clone(), user.password.clone()).await;
+    if verified == Verified::Wrong {
+        return invalid_credentials();
+    }
+    limiters.login_ip.refund(&ip);
+    limiters.login_account.clear(&account);
+    match moderation::is_refused(&db_pool, user.id as usize).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return HttpResponse::Forbidden().json(json!({
+                "reason": "Banned",
+                "description": "Account is banned or closed"
+            }))
+        }
+        Err(err) => panic!("Unexpected error, {}", err),
+    }
+    if verified == Verified::CorrectLegacy {
+        rehash(&db_pool, user.id, &credentials.password, &user.password).await;
+    }
+
+    // the password isn't enough, the session starts with the code
+    match two_factor::status(&db_pool, user.id as usize).await {
+        Ok(status) => {
+            if let Some(stage) = Stage::of(status) {
+                return HttpResponse::Ok().json(json!({
+                    "id": user.id,
+                    "two_factor": stage,
+                    "partial_token": encode_partial_token(user.id as usize, stage, &secret),
+                    "expires_in": PARTIAL_TOKEN_SECONDS,
+                }));
+            }
+        }
+        Err(err) => panic!("Unexpected error, {}", err),
+    }
+
+    // only now that the password is checked
+    let role = Role::from_db(&user.role);
+    let cookies = match session::start(&req, &db_pool, user.id as usize, role, &secret).await {
+        Ok(cookies) => cookies,
+        Err(err) => panic!("Unexpected error, {}", err),
+    };
+    session::ok_with(cookies).json(json!({
+        "id": user.id,
+        "role": role,
+    }))
+}
+
+/// replaces an old `DefaultHasher` hash, the login works even if this fails
+async fn rehash(db_pool: &Pool<MySql>, user_id: i32, password: &str, old_hash: &str) {
+    let new_hash = password::hash_blocking(password.to_string()).await;
+    // unless the password was changed in the meantime
+    let updated = sqlx::query!(
+        "UPDATE User SET password=? WHERE id=? AND password=?",
+        new_hash,
+        user_id,
+        old_hash,
+    )
+    .execute(db_pool)
+    .await;
+    match updated {
+        Ok(_) => println!("rehashed the password of {user_id}"),
+        Err(e) => println!("Couldn't rehash the password of {user_id}: {e}"),
     }
 }
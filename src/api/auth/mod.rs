@@ -1,63 +1,215 @@
-use actix_web::{web, HttpResponse, Scope};
-use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use sqlx::{MySql, Pool};
-use std::hash::{DefaultHasher, Hash, Hasher};
-
-use crate::extractors::authentication_token::{AuthenticationToken, Claims};
-
-mod login;
-mod register;
-
-#[derive(Debug, Serialize, Deserialize)]
-struct AccessToken {
-    access_token: String,
-    id: i32,
-}
-
-async fn encode_token(id: usize, secret: web::Data<String>) -> String {
-    let exp = (Utc::now() + Duration::days(30)).timestamp() as usize;
-    let claims = Claims { id, exp };
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_str().as_ref()),
-    )
-    .expect("Couldn't make access token");
-    token
-}
-
-pub async fn get_username(
-    id: AuthenticationToken,
-    db_pool: web::Data<Pool<MySql>>,
-) -> HttpResponse {
-    struct Username {
-        username: String,
-    }
-    let Username { username } = sqlx::query_as!(
-        Username,
-        "SELECT username
-        FROM User
-        WHERE id = ?",
-        id.id as u64
-    )
-    .fetch_one(db_pool.get_ref())
-    .await
-    .expect(format!("DB err, there should be a user with id: {}", id.id).as_str());
-    HttpResponse::Ok().json(json!({"username": username}))
-}
-
-pub fn login_scope() -> Scope {
-    web::scope("/auth")
-        .route("/login", web::post().to(login::login))
-        .route("/", web::get().to(get_username))
-        .route("/register", web::post().to(register::register))
-}
-
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
-}
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use std::time::Duration as StdDuration;
+
+use crate::{
+    extractors::{
+        authentication_token::{AuthenticationToken, Claims},
+        ws_authentication::{encode_ticket, TICKET_LIFETIME_SECONDS},
+    },
+    rate_limit::{self, Limiter, LimiterConfig},
+    roles::Role,
+    sessions::{SessionId, ACCESS_TOKEN_MINUTES},
+};
+
+mod account;
+mod email;
+mod login;
+mod password;
+mod register;
+mod session;
+mod two_factor;
+
+/// one set for the server, the workers share the counters
+#[derive(Debug, Clone)]
+pub struct AuthLimiters {
+    /// failed logins from one address, whatever the account
+    login_ip: Limiter,
+    /// failed logins and two-factor codes of one account, from anywhere
+    login_account: Limiter,
+    /// every registration attempt from one address
+    register_ip: Limiter,
+    /// password reset mails requested from one address
+    reset_ip: Limiter,
+    /// verification mails sent for accounts of one address
+    verify_ip: Limiter,
+    /// verification mails of one account, whatever the address it goes to
+    verify_account: Limiter,
+}
+
+const fn minutes(minutes: u64) -> StdDuration {
+    StdDuration::from_secs(minutes * 60)
+}
+
+impl AuthLimiters {
+    pub fn new() -> Self {
+        Self {
+            login_ip: Limiter::new(
+                "login per ip",
+                LimiterConfig {
+                    free_attempts: 10,
+                    base_delay: StdDuration::from_secs(1),
+                    max_delay: StdDuration::from_secs(60),
+                    lockout_after: 50,
+                    lockout: minutes(15),
+                    forget_after: minutes(60),
+                },
+            ),
+            login_account: Limiter::new(
+                "login per account",
+                LimiterConfig {
+                    free_attempts: 3,
+                    base_delay: StdDuration::from_secs(1),
+                    max_delay: minutes(5),
+                    lockout_after: 10,
+                    lockout: minutes(15),
+                    forget_after: minutes(60),
+                },
+            ),
+            register_ip: Limiter::new(
+                "register per ip",
+                LimiterConfig {
+                    free_attempts: 5,
+                    base_delay: StdDuration::from_secs(30),
+                    max_delay: minutes(10),
+                    lockout_after: 20,
+                    lockout: minutes(60),
+                    forget_after: minutes(60),
+                },
+            ),
+            reset_ip: Limiter::new(
+                "password reset per ip",
+                LimiterConfig {
+                    free_attempts: 3,
+                    base_delay: StdDuration::from_secs(60),
+                    max_delay: minutes(15),
+                    lockout_after: 10,
+                    lockout: minutes(60),
+                    forget_after: minutes(60),
+                },
+            ),
+            verify_ip: Limiter::new(
+                "verification mail per ip",
+                LimiterConfig {
+                    free_attempts: 5,
+                    base_delay: StdDuration::from_secs(60),
+                    max_delay: minutes(15),
+                    lockout_after: 15,
+                    lockout: minutes(60),
+                    forget_after: minutes(60),
+                },
+            ),
+            verify_account: Limiter::new(
+                "verification mail per account",
+                LimiterConfig {
+                    free_attempts: 3,
+                    base_delay: StdDuration::from_secs(60),
+                    max_delay: minutes(15),
+                    lockout_after: 10,
+                    lockout: minutes(60),
+                    forget_after: minutes(60),
+                },
+            ),
+        }
+    }
+}
+
+/// the limiters are keyed by it, requests without a peer address share one key
+fn ip_key(req: &HttpRequest) -> String {
+    rate_limit::client_ip(req)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default()
+}
+
+/// key of `login_account` for passwords, usernames compare case insensitively
+fn account_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccessToken {
+    access_token: String,
+    id: i32,
+}
+
+/// short lived, renewed with the session's refresh token
+fn encode_token(id: usize, role: Role, sid: SessionId, secret: &str) -> String {
+    let exp = (Utc::now() + Duration::minutes(ACCESS_TOKEN_MINUTES)).timestamp() as usize;
+    let claims = Claims { id, exp, role, sid };
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_ref()),
+    )
+    .expect("Couldn't make access token");
+    token
+}
+
+pub async fn get_username(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    let user = sqlx::query!(
+        "SELECT username, role, email,
+        UNIX_TIMESTAMP(email_verified_at) `email_verified_at: i64`
+        FROM User
+        WHERE id = ?",
+        id.id as u64
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    .expect(format!("DB err, there should be a user with id: {}", id.id).as_str());
+    HttpResponse::Ok().json(json!({
+        "username": user.username,
+        "role": Role::from_db(&user.role),
+        "email": user.email,
+        "email_verified": user.email_verified_at.is_some(),
+    }))
+}
+
+/// short lived ticket for opening the game websocket, for clients that can't send the cookie
+pub async fn get_ws_ticket(id: AuthenticationToken, secret: web::Data<String>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "ticket": encode_ticket(id, secret.as_str()),
+        "expires_in": TICKET_LIFETIME_SECONDS,
+    }))
+}
+
+pub fn login_scope() -> Scope {
+    web::scope("/auth")
+        .route("/login", web::post().to(login::login))
+        .route("/login/two_factor", web::post().to(two_factor::login_second_step))
+        .route("/login/two_factor/enroll", web::post().to(two_factor::login_enroll))
+        .route("/", web::get().to(get_username))
+        .route("/register", web::post().to(register::register))
+        .route("/ws_ticket", web::post().to(get_ws_ticket))
+        .route("/refresh", web::post().to(session::refresh))
+        .route("/logout", web::post().to(session::logout))
+        .route("/sessions", web::get().to(session::list_sessions))
+        .route(
+            "/sessions/revoke_others",
+            web::post().to(session::revoke_other_sessions),
+        )
+        .route("/sessions/{session_id}", web::delete().to(session::revoke_session))
+        .route("/account", web::delete().to(account::delete_account))
+        .route("/account/password", web::put().to(account::change_password))
+        .route("/account/username", web::put().to(account::change_username))
+        .route("/account/country", web::put().to(account::set_country))
+        .route("/account/email", web::put().to(account::set_email))
+        .route("/email/verify", web::post().to(email::verify_email))
+        .route("/email/resend", web::post().to(email::resend_verification))
+        .route("/password_reset", web::post().to(email::request_password_reset))
+        .route("/password_reset/confirm", web::post().to(email::reset_password))
+        .route("/two_factor", web::get().to(two_factor::get_status))
+        .route("/two_factor", web::delete().to(two_factor::disable))
+        .route("/two_factor/enroll", web::post().to(two_factor::start_enrollment))
+        .route("/two_factor/confirm", web::post().to(two_factor::confirm_enrollment))
+        .route(
+            "/two_factor/recovery_codes",
+            web::post().to(two_factor::replace_recovery_codes),
+        )
+}
@@ -0,0 +1,79 @@
+//! Password hashes in `User.password`.
+//! New ones are Argon2id PHC strings with a salt per user,
+//! old ones are the decimal output of `DefaultHasher` and get rehashed on the next login.
+
+use actix_web::web;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::OnceLock,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    Correct,
+    /// correct, but stored the old way, should be rehashed
+    CorrectLegacy,
+    Wrong,
+}
+
+/// PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`
+pub fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Couldn't hash password")
+        .to_string()
+}
+
+pub fn verify(password: &str, stored: &str) -> Verified {
+    if !stored.starts_with('$') {
+        return if legacy_hash(password).to_string() == stored {
+            Verified::CorrectLegacy
+        } else {
+            Verified::Wrong
+        };
+    }
+    let parsed = match PasswordHash::new(stored) {
+        Ok(parsed) => parsed,
+        Err(_) => return Verified::Wrong,
+    };
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Verified::Correct,
+        Err(_) => Verified::Wrong,
+    }
+}
+
+/// argon2 is slow on purpose, so it runs on the blocking thread pool
+pub async fn hash_blocking(password: String) -> String {
+    web::block(move || hash(&password))
+        .await
+        .expect("Password hashing panicked")
+}
+
+pub async fn verify_blocking(password: String, stored: String) -> Verified {
+    web::block(move || verify(&password, &stored))
+        .await
+        .expect("Password verification panicked")
+}
+
+/// as slow as checking a real hash, so a missing user answers like a wrong password
+pub async fn verify_dummy_blocking(password: String) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    web::block(move || {
+        let stored = DUMMY_HASH.get_or_init(|| hash("not anyones password"));
+        verify(&password, stored)
+    })
+    .await
+    .expect("Password verification panicked");
+}
+
+/// unsalted and not stable across Rust releases, only kept to verify old hashes
+fn legacy_hash(password: &str) -> u64 {
+    let mut s = DefaultHasher::new();
+    password.hash(&mut s);
+    s.finish()
+}
@@ -1,25 +1,36 @@
-use actix_web::{
-    cookie::{time::OffsetDateTime, CookieBuilder},
-    web, HttpResponse,
-};
-use chrono::{Duration, Utc};
+use actix_web::{web, HttpRequest, HttpResponse};
 use serde::Deserialize;
 use serde_json::json;
 use sqlx::{MySql, Pool};
 
-use super::{calculate_hash, encode_token};
+use super::{email, ip_key, password, session, AuthLimiters};
+use crate::{mailer::Mailer, rate_limit::too_many_attempts, roles::Role};
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterBody {
     username: String,
     password: String,
+    /// optional, needed to reset a forgotten password
+    email: Option<String>,
 }
 
 struct UserIdSelect {
     id: i32,
 }
 
-fn check_password_req(pass: &str) -> Result<(), String> {
+pub(super) fn check_username_req(username: &str) -> Result<(), String> {
+    if username.len() < 4 || username.len() > 18 {
+        return Err("Username must be between 4 and 18 characters long".into());
+    }
+    for c in username.chars() {
+        if !c.is_ascii() || c.is_whitespace() {
+            return Err("Username must contain only ascii charaters".into());
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn check_password_req(pass: &str) -> Result<(), String> {
     if pass.len() < 8 || pass.len() > 24 {
         return Err("Password must be between 8 and 24 characters long".into());
     }
@@ -64,20 +75,23 @@ fn check_password_req(pass: &str) -> Result<(), String> {
 }
 
 pub async fn register(
+    req: HttpRequest,
     new_user_data: web::Json<RegisterBody>,
     secret: web::Data<String>,
     db_pool: web::Data<Pool<MySql>>,
+    mailer: web::Data<dyn Mailer>,
+    limiters: web::Data<AuthLimiters>,
 ) -> HttpResponse {
     println!("got response");
-    if new_user_data.username.len() < 4 || new_user_data.username.len() > 18 {
-        return HttpResponse::BadRequest()
-            .json(json!({"reason": "Bad username", "description": "Username must be between 4 and 18 characters long"}));
+    // every attempt counts, a taken username tells that the account exists
+    let ip = ip_key(&req);
+    if let Err(wait) = limiters.register_ip.check(&ip) {
+        return too_many_attempts(wait);
     }
-    for c in new_user_data.username.chars() {
-        if !c.is_ascii() || c.is_whitespace() {
-            return HttpResponse::BadRequest()
-            .json(json!({"reason": "Bad username", "description": "Username must contain only ascii charaters"}));
-        }
+
+    if let Err(reason) = check_username_req(&new_user_data.username) {
+        return HttpResponse::BadRequest()
+            .json(json!({"reason": "Bad username", "description": reason}));
     }
 
     let user_exists = sqlx::query_as!(
@@ -101,11 +115,35 @@ pub async fn register(
             .json(json!({"reason": "Bad password", "description": reason}));
     }
 
+    let email = match new_user_data.email.as_deref().map(email::normalize_email) {
+        None => None,
+        Some(Ok(email)) => Some(email),
+        Some(Err(reason)) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"reason": "Bad email", "description": reason}))
+        }
+    };
+    if let Some(email) = &email {
+        match sqlx::query!("SELECT id FROM User WHERE email = ?", email)
+            .fetch_optional(db_pool.get_ref())
+            .await
+        {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                return HttpResponse::BadRequest()
+                    .json(json!({"reason": "Bad email", "description": "Email already used"}))
+            }
+            Err(err) => return HttpResponse::InternalServerError().body(format!("db error: {err}")),
+        }
+    }
+
+    let password_hash = password::hash_blocking(new_user_data.password.clone()).await;
     match sqlx::query!(
-        "INSERT into User(username, password)
-        values (?, ?);",
+        "INSERT into User(username, password, email)
+        values (?, ?, ?);",
         new_user_data.username,
-        calculate_hash(&new_user_data.password)
+        password_hash,
+        email,
     )
     .execute(db_pool.get_ref())
     .await
@@ -122,22 +160,26 @@ pub async fn register(
             .await
             .expect("User should be in database, because I just inserted it");
 
-            let token = encode_token(user_id.id as usize, secret).await;
-
-            let cookie =
-                CookieBuilder::new(crate::extractors::authentication_token::COOKIE_NAME, token)
-                    .http_only(true)
-                    .path("/")
-                    .expires(
-                        OffsetDateTime::from_unix_timestamp(
-                            (Utc::now() + Duration::days(30)).timestamp(),
-                        )
-                        .unwrap(),
-                    )
-                    .finish();
-            HttpResponse::Ok().cookie(cookie).json(json!({
-                "id": user_id.id,
-            }))
+            let user_id = user_id.id as usize;
+            if let Some(email) = &email {
+                let sent = email::send_verification(
+                    &db_pool,
+                    mailer.into_inner(),
+                    user_id,
+                    &new_user_data.username,
+                    email,
+                )
+                .await;
+                if let Err(err) = sent {
+                    println!("Couldn't send the verification mail to {user_id}: {err}");
+                }
+            }
+            match session::start(&req, &db_pool, user_id, Role::User, &secret).await {
+                Ok(cookies) => session::ok_with(cookies).json(json!({
+                    "id": user_id,
+                })),
+                Err(err) => HttpResponse::InternalServerError().body(format!("db error: {err}")),
+            }
         }
         Err(err) => HttpResponse::InternalServerError().body(format!("db error: {err}")),
     }
@@ -0,0 +1,214 @@
+use actix_web::{
+    cookie::{time::OffsetDateTime, Cookie, CookieBuilder},
+    http::header::USER_AGENT,
+    web, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use sqlx::{MySql, Pool};
+
+use super::encode_token;
+use crate::{
+    extractors::authentication_token::{
+        claims_from_request, AuthenticationToken, COOKIE_NAME, REFRESH_COOKIE_NAME,
+    },
+    moderation,
+    roles::{self, Role},
+    sessions::{self, RefreshToken, Refreshed, SessionId, ACCESS_TOKEN_MINUTES, SESSION_DAYS},
+    PlayerId,
+};
+
+/// the refresh token is only needed by /auth
+const REFRESH_COOKIE_PATH: &str = "/auth";
+
+fn cookie(
+    name: &'static str,
+    value: String,
+    path: &'static str,
+    lifetime: Duration,
+) -> Cookie<'static> {
+    CookieBuilder::new(name, value)
+        .http_only(true)
+        .path(path)
+        .expires(
+            OffsetDateTime::from_unix_timestamp((Utc::now() + lifetime).timestamp()).unwrap(),
+        )
+        .finish()
+}
+
+/// both cookies, already expired, so the browser drops them
+pub(super) fn removal_cookies() -> [Cookie<'static>; 2] {
+    [
+        CookieBuilder::new(COOKIE_NAME, "")
+            .http_only(true)
+            .path("/")
+            .expires(OffsetDateTime::UNIX_EPOCH)
+            .finish(),
+        CookieBuilder::new(REFRESH_COOKIE_NAME, "")
+            .http_only(true)
+            .path(REFRESH_COOKIE_PATH)
+            .expires(OffsetDateTime::UNIX_EPOCH)
+            .finish(),
+    ]
+}
+
+/// access and refresh cookie of the session
+fn session_cookies(
+    player_id: PlayerId,
+    role: Role,
+    refresh: &RefreshToken,
+    secret: &str,
+) -> [Cookie<'static>; 2] {
+    let access = encode_token(player_id, role, refresh.session_id, secret);
+    [
+        cookie(COOKIE_NAME, access, "/", Duration::minutes(ACCESS_TOKEN_MINUTES)),
+        cookie(
+            REFRESH_COOKIE_NAME,
+            refresh.to_cookie_value(),
+            REFRESH_COOKIE_PATH,
+            Duration::days(SESSION_DAYS),
+        ),
+    ]
+}
+
+pub(super) fn ok_with(cookies: [Cookie<'static>; 2]) -> HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    for cookie in cookies {
+        response.cookie(cookie);
+    }
+    response
+}
+
+/// new session on login, the user agent helps telling sessions apart in the list
+pub(super) async fn start(
+    req: &HttpRequest,
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    role: Role,
+    secret: &str,
+) -> Result<[Cookie<'static>; 2], sqlx::Error> {
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .unwrap_or("");
+    let refresh = sessions::create(db_pool, player_id, user_agent).await?;
+    Ok(session_cookies(player_id, role, &refresh, secret))
+}
+
+fn refresh_token(req: &HttpRequest) -> Option<RefreshToken> {
+    RefreshToken::parse(req.cookie(REFRESH_COOKIE_NAME)?.value())
+}
+
+/// new access token and refresh token, the old refresh token stops working
+pub async fn refresh(
+    req: HttpRequest,
+    db_pool: web::Data<Pool<MySql>>,
+    secret: web::Data<String>,
+) -> HttpResponse {
+    let token = match refresh_token(&req) {
+        Some(token) => token,
+        None => return HttpResponse::Unauthorized().json(json!({"reason": "No refresh token"})),
+    };
+    let (player_id, new_token) = match sessions::refresh(&db_pool, &token).await {
+        Ok(Refreshed::Renewed(player_id, new_token)) => (player_id, new_token),
+        // another tab won the race, its cookies mustn't be removed
+        Ok(Refreshed::AlreadyRenewed) => {
+            return HttpResponse::Conflict()
+                .json(json!({"reason": "Session was just refreshed, retry with the new cookie"}))
+        }
+        Ok(Refreshed::Invalid) => {
+            let [access, refresh] = removal_cookies();
+            return HttpResponse::Unauthorized()
+                .cookie(access)
+                .cookie(refresh)
+                .json(json!({"reason": "Session ended"}));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    match moderation::is_refused(&db_pool, player_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            let [access, refresh] = removal_cookies();
+            return HttpResponse::Forbidden()
+                .cookie(access)
+                .cookie(refresh)
+                .json(json!({"reason": "Banned", "description": "Account is banned or closed"}));
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+    // promotions and demotions get into the token here
+    let role = match roles::of_player(&db_pool, player_id).await {
+        Ok(role) => role,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    ok_with(session_cookies(player_id, role, &new_token, &secret)).json(json!({
+        "id": player_id,
+        "role": role,
+    }))
+}
+
+/// works with an expired access token too, the refresh cookie names the session
+pub async fn logout(req: HttpRequest, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    if let Some(token) = refresh_token(&req) {
+        if let Err(e) = sessions::end(&db_pool, &token).await {
+            println!("Couldn't end session {}: {e}", token.session_id);
+        }
+    }
+    if let Ok(claims) = claims_from_request(&req) {
+        if let Err(e) = sessions::revoke(&db_pool, claims.id, claims.sid).await {
+            println!("Couldn't end session {}: {e}", claims.sid);
+        }
+    }
+    ok_with(removal_cookies()).finish()
+}
+
+pub async fn list_sessions(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    match sessions::active(&db_pool, id.id).await {
+        Ok(sessions) => {
+            let sessions: Vec<serde_json::Value> = sessions
+                .into_iter()
+                .map(|s| {
+                    json!({
+                        "id": s.id,
+                        "current": s.id == id.sid,
+                        "user_agent": s.user_agent,
+                        "created_at": s.created_at,
+                        "last_used_at": s.last_used_at,
+                        "expires_at": s.expires_at,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(sessions)
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn revoke_session(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    session_id: web::Path<SessionId>,
+) -> HttpResponse {
+    match sessions::revoke(&db_pool, id.id, session_id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().into(),
+        Ok(false) => HttpResponse::NotFound().json(json!({"reason": "No such session"})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// logs out every other device
+pub async fn revoke_other_sessions(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    match sessions::revoke_others(&db_pool, id.id, id.sid).await {
+        Ok(revoked) => HttpResponse::Ok().json(json!({"revoked": revoked})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
@@ -0,0 +1,318 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{MySql, Pool};
+
+use super::{session, AuthLimiters};
+use crate::{
+    extractors::authentication_token::AuthenticationToken,
+    moderation,
+    rate_limit::too_many_attempts,
+    roles, totp,
+    two_factor::{self, Status},
+    PlayerId,
+};
+
+/// time between the password and the code
+pub const PARTIAL_TOKEN_SECONDS: i64 = 300;
+
+/// what the player still has to do after the password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// send a code from the app or a recovery code
+    Verify,
+    /// required by an admin but not set up yet, enroll first
+    Enroll,
+}
+
+impl Stage {
+    pub fn of(status: Status) -> Option<Stage> {
+        match (status.enabled, status.required) {
+            (true, _) => Some(Stage::Verify),
+            (false, true) => Some(Stage::Enroll),
+            (false, false) => None,
+        }
+    }
+}
+
+/// proves the password was right, nothing else accepts it
+#[derive(Serialize, Deserialize)]
+struct PartialClaims {
+    id: PlayerId,
+    exp: usize,
+    stage: Stage,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PartialLogin {
+    partial_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SecondStep {
+    partial_token: String,
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CodeBody {
+    code: String,
+}
+
+/// signed with a different key, so it can't be used as a normal token
+fn partial_secret(secret: &str) -> String {
+    format!("{secret}.two-factor")
+}
+
+pub(super) fn encode_partial_token(id: PlayerId, stage: Stage, secret: &str) -> String {
+    let exp = (Utc::now() + Duration::seconds(PARTIAL_TOKEN_SECONDS)).timestamp() as usize;
+    encode(
+        &Header::default(),
+        &PartialClaims { id, exp, stage },
+        &EncodingKey::from_secret(partial_secret(secret).as_bytes()),
+    )
+    .expect("Couldn't make partial token")
+}
+
+fn decode_partial_token(token: &str, secret: &str) -> Option<PartialClaims> {
+    decode::<PartialClaims>(
+        token,
+        &DecodingKey::from_secret(partial_secret(secret).as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    )
+    .ok()
+    .map(|token| token.claims)
+}
+
+fn invalid_partial_token() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({
+        "reason": "Invalid partial token",
+        "description": "Log in again"
+    }))
+}
+
+fn wrong_code() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({"reason": "Wrong code"}))
+}
+
+fn db_fail() -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({"reason": "db fail"}))
+}
+
+/// counted per account like passwords, so codes can't be guessed
+async fn check_code(
+    limiters: &AuthLimiters,
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    code: &str,
+) -> Result<(), HttpResponse> {
+    let key = format!("player:{player_id}");
+    limiters
+        .login_account
+        .check(&key)
+        .map_err(too_many_attempts)?;
+    match two_factor::check_code(db_pool, player_id, code).await {
+        Ok(true) => {
+            limiters.login_account.clear(&key);
+            Ok(())
+        }
+        Ok(false) => Err(wrong_code()),
+        Err(_) => {
+            limiters.login_account.refund(&key);
+            Err(db_fail())
+        }
+    }
+}
+
+async fn username(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<String, sqlx::Error> {
+    Ok(
+        sqlx::query!("SELECT username FROM User WHERE id=?", player_id as u64)
+            .fetch_one(db_pool)
+            .await?
+            .username,
+    )
+}
+
+/// the secret and its otpauth uri, the player confirms it with a first code
+async fn enroll(db_pool: &Pool<MySql>, player_id: PlayerId) -> HttpResponse {
+    let secret = match two_factor::start_enrollment(db_pool, player_id).await {
+        Ok(Some(secret)) => secret,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({"reason": "Two-factor is already on"}))
+        }
+        Err(_) => return db_fail(),
+    };
+    let username = match username(db_pool, player_id).await {
+        Ok(username) => username,
+        Err(_) => return db_fail(),
+    };
+    HttpResponse::Ok().json(json!({
+        "secret": secret,
+        "uri": totp::uri(&secret, &username),
+    }))
+}
+
+/// second step of the login, or enrolling when an admin requires two-factor
+pub async fn login_enroll(
+    db_pool: web::Data<Pool<MySql>>,
+    secret: web::Data<String>,
+    data: web::Json<PartialLogin>,
+) -> HttpResponse {
+    match decode_partial_token(&data.partial_token, &secret) {
+        Some(claims) if claims.stage == Stage::Enroll => enroll(&db_pool, claims.id).await,
+        _ => invalid_partial_token(),
+    }
+}
+
+/// the session starts here when two-factor is on.
+/// When enrolling, the code confirms the new secret and the recovery codes are in the reply
+pub async fn login_second_step(
+    req: HttpRequest,
+    db_pool: web::Data<Pool<MySql>>,
+    secret: web::Data<String>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<SecondStep>,
+) -> HttpResponse {
+    let claims = match decode_partial_token(&data.partial_token, &secret) {
+        Some(claims) => claims,
+        None => return invalid_partial_token(),
+    };
+    let player_id = claims.id;
+
+    let recovery_codes = match claims.stage {
+        Stage::Verify => match check_code(&limiters, &db_pool, player_id, &data.code).await {
+            Ok(()) => None,
+            Err(reply) => return reply,
+        },
+        Stage::Enroll => {
+            match two_factor::confirm_enrollment(&db_pool, player_id, &data.code).await {
+                Ok(Some(codes)) => Some(codes),
+                Ok(None) => return wrong_code(),
+                Err(_) => return db_fail(),
+            }
+        }
+    };
+
+    // could have changed since the password was checked
+    match moderation::is_refused(&db_pool, player_id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return HttpResponse::Forbidden().json(json!({
+                "reason": "Banned",
+                "description": "Account is banned or closed"
+            }))
+        }
+        Err(_) => return db_fail(),
+    }
+    let role = match roles::of_player(&db_pool, player_id).await {
+        Ok(role) => role,
+        Err(_) => return db_fail(),
+    };
+    match session::start(&req, &db_pool, player_id, role, &secret).await {
+        Ok(cookies) => session::ok_with(cookies).json(json!({
+            "id": player_id,
+            "role": role,
+            "recovery_codes": recovery_codes,
+        })),
+        Err(_) => db_fail(),
+    }
+}
+
+pub async fn get_status(id: AuthenticationToken, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    let status = match two_factor::status(&db_pool, id.id).await {
+        Ok(status) => status,
+        Err(_) => return db_fail(),
+    };
+    match two_factor::remaining_recovery_codes(&db_pool, id.id).await {
+        Ok(remaining) => HttpResponse::Ok().json(json!({
+            "enabled": status.enabled,
+            "required": status.required,
+            "recovery_codes_left": remaining,
+        })),
+        Err(_) => db_fail(),
+    }
+}
+
+pub async fn start_enrollment(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    enroll(&db_pool, id.id).await
+}
+
+/// the recovery codes are only shown this once
+pub async fn confirm_enrollment(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<CodeBody>,
+) -> HttpResponse {
+    match two_factor::confirm_enrollment(&db_pool, id.id, &data.code).await {
+        Ok(Some(codes)) => {
+            println!("player {} turned on two-factor", id.id);
+            HttpResponse::Ok().json(json!({"recovery_codes": codes}))
+        }
+        Ok(None) => wrong_code(),
+        Err(_) => db_fail(),
+    }
+}
+
+/// needs a current code, the old recovery codes stop working
+pub async fn replace_recovery_codes(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<CodeBody>,
+) -> HttpResponse {
+    if let Err(reply) = check_code(&limiters, &db_pool, id.id, &data.code).await {
+        return reply;
+    }
+    // all the new codes or none, the old ones stay if it fails
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return db_fail(),
+    };
+    let codes = match two_factor::replace_recovery_codes(&mut tx, id.id).await {
+        Ok(codes) => codes,
+        Err(_) => return db_fail(),
+    };
+    if tx.commit().await.is_err() {
+        return db_fail();
+    }
+    HttpResponse::Ok().json(json!({"recovery_codes": codes}))
+}
+
+/// needs a current code, not allowed when an admin requires two-factor
+pub async fn disable(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    limiters: web::Data<AuthLimiters>,
+    data: web::Json<CodeBody>,
+) -> HttpResponse {
+    match two_factor::status(&db_pool, id.id).await {
+        Ok(status) if status.required => {
+            return HttpResponse::BadRequest()
+                .json(json!({"reason": "Two-factor is required for this account"}))
+        }
+        Ok(status) if !status.enabled => {
+            return HttpResponse::BadRequest().json(json!({"reason": "Two-factor isnt on"}))
+        }
+        Ok(_) => {}
+        Err(_) => return db_fail(),
+    }
+    if let Err(reply) = check_code(&limiters, &db_pool, id.id, &data.code).await {
+        return reply;
+    }
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return db_fail(),
+    };
+    if two_factor::disable(&mut tx, id.id).await.is_err() || tx.commit().await.is_err() {
+        return db_fail();
+    }
+    println!("player {} turned off two-factor", id.id);
+    HttpResponse::Ok().into()
+}
@@ -0,0 +1,232 @@
+use actix_web::{web, HttpResponse, Scope};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    api::game_ws::{NewGameOptions, SingleplayerMultiplayer},
+    blocks,
+    chess_logic::Player,
+    extractors::authentication_token::AuthenticationToken,
+    game_organizer::GameOrganizerRequest,
+    sql, PlayerId,
+};
+
+const TOKEN_LENGTH: usize = 32;
+const DEFAULT_EXPIRY_MINUTES: u32 = 60;
+const MAX_EXPIRY_MINUTES: u32 = 7 * 24 * 60;
+
+pub fn challenge_scope() -> Scope {
+    web::scope("/challenge")
+        .route("/", web::post().to(create_challenge))
+        .route("/{token}", web::get().to(get_challenge))
+        .route("/{token}/accept", web::post().to(accept_challenge))
+        .route("/{token}", web::delete().to(cancel_challenge))
+}
+
+/// colour of the challenge creator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeColor {
+    White,
+    Black,
+    Random,
+}
+
+impl ChallengeColor {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChallengeColor::White => "white",
+            ChallengeColor::Black => "black",
+            ChallengeColor::Random => "random",
+        }
+    }
+
+    fn parse(color: &str) -> Option<Self> {
+        match color {
+            "white" => Some(ChallengeColor::White),
+            "black" => Some(ChallengeColor::Black),
+            "random" => Some(ChallengeColor::Random),
+            _ => None,
+        }
+    }
+
+    fn creator_plays(&self) -> Player {
+        match self {
+            ChallengeColor::White => Player::White,
+            ChallengeColor::Black => Player::Black,
+            ChallengeColor::Random if rand::random() => Player::White,
+            ChallengeColor::Random => Player::Black,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewChallenge {
+    color: Option<ChallengeColor>,
+    expires_in_minutes: Option<u32>,
+}
+
+pub async fn create_challenge(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<NewChallenge>,
+) -> HttpResponse {
+    let color = data.color.unwrap_or(ChallengeColor::Random);
+    let expires_in = data.expires_in_minutes.unwrap_or(DEFAULT_EXPIRY_MINUTES);
+    if !(1..=MAX_EXPIRY_MINUTES).contains(&expires_in) {
+        return HttpResponse::BadRequest().json(json!({"reason": "Invalid expiry"}));
+    }
+
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    match sqlx::query!(
+        "INSERT INTO Challenges(token, creator, color, expires_at)
+        VALUES (?, ?, ?, CURRENT_TIMESTAMP + INTERVAL ? MINUTE)",
+        token,
+        id.id as u64,
+        color.as_str(),
+        expires_in,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "token": token,
+            "url": format!("/challenge/{token}"),
+            "expires_in": expires_in as u64 * 60,
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// anyone logged in can look at the challenge before accepting it
+pub async fn get_challenge(
+    _id: AuthenticationToken,
+    token: web::Path<String>,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    let challenge = match sqlx::query!(
+        "SELECT creator, color, status,
+        TIMESTAMPDIFF(SECOND, CURRENT_TIMESTAMP, expires_at) `seconds_left!`
+        FROM Challenges WHERE token=?",
+        token.as_str(),
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::NotFound().json(json!({"reason": "Challenge not found"})),
+    };
+
+    let creator = match sql::get_player_data(&db_pool, challenge.creator as u64).await {
+        Ok(creator) => creator,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    let status = if challenge.status == "open" && challenge.seconds_left <= 0 {
+        "expired"
+    } else {
+        challenge.status.as_str()
+    };
+
+    HttpResponse::Ok().json(json!({
+        "creator": creator,
+        "color": challenge.color,
+        "status": status,
+        "seconds_left": challenge.seconds_left.max(0),
+    }))
+}
+
+/// starts the game, both players get it over the websocket
+pub async fn accept_challenge(
+    id: AuthenticationToken,
+    token: web::Path<String>,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+) -> HttpResponse {
+    let challenge = match sqlx::query!(
+        "SELECT creator, color FROM Challenges WHERE token=?",
+        token.as_str(),
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::NotFound().json(json!({"reason": "Challenge not found"})),
+    };
+    let creator = challenge.creator as PlayerId;
+    if creator == id.id {
+        return HttpResponse::BadRequest()
+            .json(json!({"reason": "You cant accept your own challenge"}));
+    }
+    // same answer as a missing challenge, so the block isn't revealed
+    let blocked = match db_pool.acquire().await {
+        Ok(mut conn) => blocks::between(&mut conn, creator, id.id).await,
+        Err(e) => Err(e),
+    };
+    match blocked {
+        Ok(false) => {}
+        Ok(true) => return HttpResponse::NotFound().json(json!({"reason": "Challenge not found"})),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    // only one player can take it, even if two accept at once
+    let taken = sqlx::query!(
+        "UPDATE Challenges SET status='accepted', accepted_by=?
+        WHERE token=? AND status='open' AND expires_at > CURRENT_TIMESTAMP",
+        id.id as u64,
+        token.as_str(),
+    )
+    .execute(db_pool.get_ref())
+    .await;
+    match taken {
+        Ok(res) if res.rows_affected() == 1 => {}
+        Ok(_) => {
+            return HttpResponse::Gone()
+                .json(json!({"reason": "Challenge is no longer available"}))
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+
+    let color = ChallengeColor::parse(&challenge.color).unwrap_or(ChallengeColor::Random);
+    let players = match color.creator_plays() {
+        Player::White => [creator, id.id],
+        Player::Black => [id.id, creator],
+    };
+    let options = NewGameOptions {
+        prefered_color: None,
+        opponent: None,
+        game_type: SingleplayerMultiplayer::Multiplayer,
+    };
+    let _ = game_organizer
+        .send(GameOrganizerRequest::StartGame(players, options))
+        .await;
+
+    HttpResponse::Ok().into()
+}
+
+pub async fn cancel_challenge(
+    id: AuthenticationToken,
+    token: web::Path<String>,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    match sqlx::query!(
+        "UPDATE Challenges SET status='cancelled' WHERE token=? AND creator=? AND status='open'",
+        token.as_str(),
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(res) if res.rows_affected() == 1 => HttpResponse::Ok().into(),
+        Ok(_) => HttpResponse::NotFound().json(json!({"reason": "No open challenge to cancel"})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    }
+}
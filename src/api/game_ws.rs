@@ -1,52 +1,42 @@
 use crate::{
-    chess_logic::{Player, Position},
-    PlayerId,
-};
-use actix_web::{
-    web::{self, Path},
-    HttpRequest, HttpResponse,
+    api::protocol::{
+        parse_client_message, protocol_schema, ClientMessage, ErrorCode, InvitationState,
+        Responder, ServerEnvelope, ServerMessage, PROTOCOL_VERSION,
+    },
+    chat_moderation::ChatText,
+    chess_logic::Player,
+    extractors::ws_authentication::WsAuthentication,
+    moderation,
+    sessions::{self, SessionId},
+    ConnectionId, GameId, PlayerId,
 };
-use actix_ws::Message;
-use serde::Deserialize;
-use serde_json::Value;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+use std::time::Duration;
 use tokio::sync::mpsc::{self, Sender};
 
-use crate::game_organizer::GameOrganizerRequest;
-
-#[derive(Debug, Deserialize)]
-pub struct WsMessageIncoming {
-    action: String,
-    game_id: Option<u32>,
-    data: Value,
-}
+/// how often an open socket checks that its session wasn't revoked
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
-#[derive(Debug, Clone)]
-enum WsAction {
-    Move {
-        from: Position,
-        to: Position,
-    },
-    Chat(String),
-    #[allow(dead_code)]
-    End(ChessEnd),
-    NewGame(NewGameOptions),
-}
+use crate::game_organizer::GameOrganizerRequest;
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
 pub struct NewGameOptions {
     pub prefered_color: Option<Player>,
     pub opponent: Option<PlayerId>,
     pub game_type: SingleplayerMultiplayer,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
 pub enum SingleplayerMultiplayer {
     Singleplayer,
     Multiplayer,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub enum ChessEnd {
     DrawAsk,
     DrawConfirm,
@@ -54,45 +44,105 @@ pub enum ChessEnd {
     Resign,
 }
 
+/// sent with the game_id of the game that just finished
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+pub enum RematchAction {
+    Offer,
+    Accept,
+    Decline,
+}
+
+/// the player id comes from the token (cookie or `?ticket=`), never from the client
 pub async fn game_ws(
     req: HttpRequest,
     body: web::Payload,
-    path: Path<usize>,
+    auth: WsAuthentication,
     game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    db_pool: web::Data<Pool<MySql>>,
 ) -> HttpResponse {
     let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body).expect("neki");
-    let id = path.into_inner();
+    let id = auth.id;
+    // a player can have multiple sockets open, so each one gets its own id
+    let connection_id: ConnectionId = rand::random();
     actix_rt::spawn(async move {
         use GameOrganizerRequest::*;
         let (tx, mut rx) = mpsc::channel(32);
-        println!("Connection started: {id}");
+        println!("Connection started: {id} ({connection_id})");
 
-        let _ = game_organizer.send(Connect(id, tx)).await;
+        let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+        // the first tick is immediate, the session was just checked
+        session_check.tick().await;
+
+        // nothing is sent to the organizer before the client says hello
+        let mut handshake_done = false;
+        let mut close_reason = None;
         loop {
             tokio::select! {
-                Some(Ok(msg)) = msg_stream.recv() => {
+                _ = session_check.tick() => {
+                    if !session_still_valid(&db_pool, id, auth.sid).await {
+                        close_reason = Some(CloseReason {
+                            code: CloseCode::Policy,
+                            description: Some("Session ended".into()),
+                        });
+                        break;
+                    }
+                }
+                msg = msg_stream.recv() => {
+                    // closed or broken socket, the session check keeps select! from ever reaching else
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        None | Some(Err(_)) => break,
+                    };
                     match msg {
                         Message::Text(msg) => {
+                            let (message_id, envelope) = parse_client_message(&msg);
+                            let responder = Responder::new(tx.clone(), message_id);
+                            let envelope = match envelope {
+                                Ok(envelope) => envelope,
+                                Err(err) => {
+                                    println!("{:?}", err);
+                                    responder.error(ErrorCode::BadMessage, err.to_string()).await;
+                                    continue;
+                                }
+                            };
 
-                            if let Ok(Some((game_id, msg))) = deserialize_ws_msg(msg.to_string().as_str()) {
-                                match msg {
-                                    WsAction::Move { from, to } => {
-                                        let _ = game_organizer.send(Move(id, game_id, from, to)).await;
-                                    }
-                                    WsAction::Chat(text) => {
-                                        let _ = game_organizer.send(Chat(id, game_id, text)).await;
-                                    }
-                                    WsAction::End(reason) => {
-                                        let _ = game_organizer.send(End(id, game_id, reason)).await;
-                                    }
-                                    WsAction::NewGame(options) => {
-                                        let _ = game_organizer.send(NewGame(id, options)).await;
-                                    }
+                            match envelope.message {
+                                ClientMessage::Hello { .. } if handshake_done => {
+                                    responder.error(ErrorCode::BadMessage, "Handshake was already done").await;
+                                }
+                                ClientMessage::Hello { version } if version == PROTOCOL_VERSION => {
+                                    handshake_done = true;
+                                    let hello = ServerEnvelope {
+                                        id: message_id,
+                                        game_id: None,
+                                        message: ServerMessage::Hello { version: PROTOCOL_VERSION },
+                                    };
+                                    let _ = session.text(hello.to_ws()).await;
+                                    let _ = game_organizer.send(Connect(id, connection_id, tx.clone())).await;
+                                }
+                                ClientMessage::Hello { version } => {
+                                    let error = ServerEnvelope {
+                                        id: message_id,
+                                        game_id: None,
+                                        message: ServerMessage::Error {
+                                            code: ErrorCode::UnsupportedVersion,
+                                            message: format!("Server speaks protocol version {PROTOCOL_VERSION}, not {version}"),
+                                        },
+                                    };
+                                    let _ = session.text(error.to_ws()).await;
+                                    close_reason = Some(CloseReason {
+                                        code: CloseCode::Protocol,
+                                        description: Some("Unsupported protocol version".into()),
+                                    });
+                                    break;
+                                }
+                                _ if !handshake_done => {
+                                    responder.error(ErrorCode::HandshakeRequired, "Send hello first").await;
+                                }
+                                message => {
+                                    handle_message(id, connection_id, envelope.game_id, message, responder, &game_organizer).await;
                                 }
                             }
-                        else {
-                            println!("{:?}", deserialize_ws_msg(msg.to_string().as_str()));
-                        }
                         }
                         Message::Pong(_) => {
                             let _ = session.ping(b"").await;
@@ -103,44 +153,125 @@ pub async fn game_ws(
                 Some(msg) = rx.recv() => {
                     let _ = session.text(msg).await;
                 }
+                else => break,
             }
         }
-        let _ = game_organizer.send(GameOrganizerRequest::Close(id)).await;
-        println!("Client connection closed {id}");
+        if close_reason.is_some() {
+            let _ = session.close(close_reason).await;
+        }
+        if handshake_done {
+            let _ = game_organizer
+                .send(GameOrganizerRequest::Close(id, connection_id))
+                .await;
+        }
+        println!("Client connection closed {id} ({connection_id})");
     });
 
     response
 }
 
-fn deserialize_ws_msg(msg: &str) -> Result<Option<(u32, WsAction)>, serde_json::Error> {
-    let message: WsMessageIncoming = serde_json::from_str(msg)?;
-
-    Ok(Some(match message.action.as_str() {
-        "move" => (
-            message.game_id.unwrap(),
-            WsAction::Move {
-                from: serde_json::from_value(message.data["from"].clone())?,
-                to: serde_json::from_value(message.data["to"].clone())?,
-            },
-        ),
-        "chat" => (
-            message.game_id.unwrap(),
-            WsAction::Chat(serde_json::from_value(message.data)?),
-        ),
-        "new_game" => (0, WsAction::NewGame(serde_json::from_value(message.data)?)),
-        "end" => (
-            message.game_id.unwrap(),
-            WsAction::End(match serde_json::from_value::<ChessEnd>(message.data) {
-                Ok(n) => n,
-                Err(_) => {
-                    println!("wrong chess-end type");
-                    return Ok(None);
-                }
-            }),
-        ),
-        _ => {
-            println!("wrong error code");
-            return Ok(None);
+/// false once the session is revoked or the account banned, db errors keep the socket open
+async fn session_still_valid(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    session_id: SessionId,
+) -> bool {
+    let active = sessions::is_active(db_pool, session_id, player_id)
+        .await
+        .unwrap_or(true);
+    let refused = moderation::is_refused(db_pool, player_id)
+        .await
+        .unwrap_or(false);
+    active && !refused
+}
+
+/// forwards a message of an established connection to the organizer
+async fn handle_message(
+    id: PlayerId,
+    connection_id: ConnectionId,
+    game_id: Option<GameId>,
+    message: ClientMessage,
+    responder: Responder,
+    game_organizer: &Sender<GameOrganizerRequest>,
+) {
+    use GameOrganizerRequest::*;
+
+    match message {
+        ClientMessage::NewGame(options) => {
+            let _ = game_organizer.send(NewGame(id, options, responder)).await;
+            return;
+        }
+        ClientMessage::ArenaJoin(arena_id) => {
+            let _ = game_organizer.send(ArenaJoin(id, arena_id, responder)).await;
+            return;
+        }
+        ClientMessage::ArenaLeave(arena_id) => {
+            let _ = game_organizer.send(ArenaLeave(id, arena_id, responder)).await;
+            return;
+        }
+        ClientMessage::InviteAccept(invitation_id) => {
+            let state = InvitationState::Accepted;
+            let _ = game_organizer
+                .send(AnswerInvitation(id, invitation_id, state, responder))
+                .await;
+            return;
+        }
+        ClientMessage::InviteDecline(invitation_id) => {
+            let state = InvitationState::Declined;
+            let _ = game_organizer
+                .send(AnswerInvitation(id, invitation_id, state, responder))
+                .await;
+            return;
         }
-    }))
+        ClientMessage::InviteCancel(invitation_id) => {
+            let state = InvitationState::Cancelled;
+            let _ = game_organizer
+                .send(AnswerInvitation(id, invitation_id, state, responder))
+                .await;
+            return;
+        }
+        ClientMessage::Idle(idle) => {
+            let _ = game_organizer
+                .send(Idle(id, connection_id, idle, responder))
+                .await;
+            return;
+        }
+        _ => {}
+    }
+
+    let game_id = match game_id {
+        Some(game_id) => game_id,
+        None => {
+            responder
+                .error(ErrorCode::BadMessage, "game_id is required")
+                .await;
+            return;
+        }
+    };
+
+    let request = match message {
+        ClientMessage::Move { from, to } => Move(id, game_id, from, to, responder),
+        ClientMessage::Chat(text) => Chat(id, game_id, ChatText::Free(text), responder),
+        ClientMessage::QuickChat(message) => {
+            Chat(id, game_id, ChatText::Quick(message), responder)
+        }
+        ClientMessage::MuteOpponent(mute) => MuteOpponent(id, game_id, mute, responder),
+        ClientMessage::End(reason) => End(id, game_id, reason, responder),
+        ClientMessage::Rematch(action) => Rematch(id, game_id, action, responder),
+        ClientMessage::Hello { .. }
+        | ClientMessage::NewGame(_)
+        | ClientMessage::ArenaJoin(_)
+        | ClientMessage::ArenaLeave(_)
+        | ClientMessage::InviteAccept(_)
+        | ClientMessage::InviteDecline(_)
+        | ClientMessage::InviteCancel(_)
+        | ClientMessage::Idle(_) => {
+            unreachable!("messages without a game are handled before")
+        }
+    };
+    let _ = game_organizer.send(request).await;
+}
+
+pub async fn get_protocol_schema() -> HttpResponse {
+    HttpResponse::Ok().json(protocol_schema())
 }
@@ -0,0 +1,164 @@
+use actix_web::{web, HttpResponse, Scope};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use tokio::sync::mpsc;
+
+use crate::{
+    direct_messages::{self, MessageId, MAX_MESSAGE_LENGTH},
+    extractors::authentication_token::AuthenticationToken,
+    friends,
+    social_organizer::SocialRequest,
+    PlayerId,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+
+pub fn messages_scope() -> Scope {
+    web::scope("/messages")
+        .route("/", web::get().to(unread_messages))
+        .route("/{friend_id}", web::get().to(get_conversation))
+        .route("/{friend_id}", web::post().to(send_message))
+        .route("/{friend_id}/read", web::post().to(read_conversation))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    /// id of the last message already shown
+    before: Option<MessageId>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewMessage {
+    text: String,
+}
+
+/// only friends can message each other
+async fn is_friend(db_pool: &Pool<MySql>, player_id: PlayerId, friend_id: PlayerId) -> bool {
+    let mut conn = match db_pool.acquire().await {
+        Ok(conn) => conn,
+        Err(_) => return false,
+    };
+    friends::are_friends(&mut conn, player_id, friend_id)
+        .await
+        .unwrap_or(false)
+}
+
+/// moderator mutes cover direct messages too
+async fn is_muted(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id FROM User WHERE id=? AND chat_muted_until > CURRENT_TIMESTAMP",
+        player_id as u64,
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .is_some())
+}
+
+/// unread counts per friend
+pub async fn unread_messages(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    match direct_messages::unread_counts(&db_pool, id.id).await {
+        Ok(counts) => {
+            let total: i64 = counts.iter().map(|(_, count)| count).sum();
+            let conversations: Vec<serde_json::Value> = counts
+                .into_iter()
+                .map(|(friend, unread)| json!({"friend": friend, "unread": unread}))
+                .collect();
+            HttpResponse::Ok().json(json!({
+                "conversations": conversations,
+                "unread": total,
+            }))
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn get_conversation(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    friend_id: web::Path<PlayerId>,
+    page: web::Query<Page>,
+) -> HttpResponse {
+    let friend_id = friend_id.into_inner();
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    match direct_messages::history(&db_pool, id.id, friend_id, page.before, limit).await {
+        Ok(messages) => {
+            // there are more if the page is full
+            let next = if messages.len() as u32 == limit {
+                messages.last().map(|m| m.id)
+            } else {
+                None
+            };
+            HttpResponse::Ok().json(json!({
+                "next": next,
+                "messages": messages,
+            }))
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn send_message(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    social_organizer: web::Data<mpsc::Sender<SocialRequest>>,
+    friend_id: web::Path<PlayerId>,
+    data: web::Json<NewMessage>,
+) -> HttpResponse {
+    let friend_id = friend_id.into_inner();
+    let text = data.into_inner().text.trim().to_string();
+    if text.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"reason": "Message is empty"}));
+    }
+    if text.chars().count() > MAX_MESSAGE_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"reason": "Message is too long"}));
+    }
+    if !is_friend(&db_pool, id.id, friend_id).await {
+        return HttpResponse::BadRequest().json(json!({"reason": "You can only message friends"}));
+    }
+    match is_muted(&db_pool, id.id).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return HttpResponse::Forbidden()
+                .json(json!({"reason": "You were muted by a moderator"}))
+        }
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+
+    match direct_messages::send(&db_pool, id.id, friend_id, text).await {
+        Ok(message) => {
+            let message_id = message.id;
+            let _ = social_organizer
+                .send(SocialRequest::DirectMessage(message))
+                .await;
+            HttpResponse::Ok().json(json!({"message_id": message_id}))
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// marks the whole conversation as read and sends the friend a read receipt
+pub async fn read_conversation(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    social_organizer: web::Data<mpsc::Sender<SocialRequest>>,
+    friend_id: web::Path<PlayerId>,
+) -> HttpResponse {
+    let friend_id = friend_id.into_inner();
+    match direct_messages::mark_read(&db_pool, id.id, friend_id).await {
+        Ok(Some(up_to)) => {
+            let _ = social_organizer
+                .send(SocialRequest::MessagesRead(id.id, friend_id, up_to))
+                .await;
+            HttpResponse::Ok().json(json!({"up_to": up_to}))
+        }
+        Ok(None) => HttpResponse::Ok().json(json!({"up_to": null})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
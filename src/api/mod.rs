@@ -1,6 +1,15 @@
+pub mod admin;
+pub mod arena;
 pub mod auth;
+pub mod challenge;
 pub mod game_ws;
 pub mod healthcheck;
+pub mod messages;
+pub mod moderation;
+pub mod notifications;
+pub mod protocol;
+pub mod reports;
 pub mod social;
+pub mod tournament;
 
 pub use healthcheck::healthcheck;
@@ -0,0 +1,612 @@
+use actix_web::{web, HttpResponse, Scope};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    extractors::role::Moderator,
+    game_chat,
+    game_organizer::GameOrganizerRequest,
+    moderation::{self, ModerationAction, ReportId, ReportStatus},
+    notifications::{self, NotificationPayload},
+    roles::Role,
+    social_organizer::SocialRequest,
+    PlayerId,
+};
+
+/// a month
+const MAX_MUTE_MINUTES: u32 = 60 * 24 * 30;
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+
+pub fn moderation_scope() -> Scope {
+    web::scope("/moderation")
+        .route("/mutes", web::get().to(list_mutes))
+        .route("/mutes", web::post().to(mute_player))
+        .route("/mutes/{player_id}", web::delete().to(unmute_player))
+        .route("/reports", web::get().to(list_reports))
+        .route("/reports/{id}", web::get().to(get_report))
+        .route("/reports/{id}/triage", web::post().to(triage_report))
+        .route("/reports/{id}/resolve", web::post().to(resolve_report))
+        .route("/actions", web::get().to(list_actions))
+        .route("/actions", web::post().to(take_action))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Mute {
+    player: PlayerId,
+    minutes: u32,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resolution {
+    action: ModerationAction,
+    /// how long a mute or ban lasts, a ban without it is permanent
+    minutes: Option<u32>,
+    /// for the audit trail, a warning is sent to the player as its message
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayerAction {
+    player: PlayerId,
+    #[serde(flatten)]
+    resolution: Resolution,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportFilter {
+    status: Option<ReportStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActionFilter {
+    player: Option<PlayerId>,
+    /// id of the last action already shown
+    before: Option<u64>,
+    limit: Option<u32>,
+}
+
+/// Senders the actions need to take effect right away, not only on the next login
+struct Organizers<'a> {
+    game: &'a Sender<GameOrganizerRequest>,
+    social: &'a Sender<SocialRequest>,
+}
+
+/// applies the action, resolves the report if there is one and records it in the audit trail,
+/// returns when a mute or ban ends
+async fn apply_action(
+    db_pool: &Pool<MySql>,
+    organizers: Organizers<'_>,
+    moderator: Moderator,
+    player: PlayerId,
+    report: Option<ReportId>,
+    resolution: &Resolution,
+) -> Result<Option<i64>, HttpResponse> {
+    let now = Utc::now().timestamp();
+    let until = match (resolution.action, resolution.minutes) {
+        (ModerationAction::Triage, _) => {
+            let reply = json!({"reason": "Triage isnt a resolution"});
+            return Err(HttpResponse::BadRequest().json(reply));
+        }
+        (
+            ModerationAction::Disable
+            | ModerationAction::Enable
+            | ModerationAction::SetRole
+            | ModerationAction::RequireTwoFactor
+            | ModerationAction::ResetTwoFactor,
+            _,
+        ) => {
+            let reply = json!({"reason": "Only admins can do this, see /admin"});
+            return Err(HttpResponse::BadRequest().json(reply));
+        }
+        (ModerationAction::Mute, Some(minutes)) if (1..=MAX_MUTE_MINUTES).contains(&minutes) => {
+            Some(now + minutes as i64 * 60)
+        }
+        (ModerationAction::Mute, _) => {
+            let reply = json!({
+                "reason": format!("A mute lasts from 1 to {MAX_MUTE_MINUTES} minutes")
+            });
+            return Err(HttpResponse::BadRequest().json(reply));
+        }
+        (ModerationAction::Ban, Some(0)) => {
+            let reply = json!({"reason": "A ban lasts at least a minute"});
+            return Err(HttpResponse::BadRequest().json(reply));
+        }
+        (ModerationAction::Ban, minutes) => minutes.map(|minutes| now + minutes as i64 * 60),
+        _ => None,
+    };
+    if player == moderator.id && resolution.action != ModerationAction::Dismiss {
+        let reply = json!({"reason": "You cant moderate yourself"});
+        return Err(HttpResponse::BadRequest().json(reply));
+    }
+
+    let db_fail = || HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    let mut tx = db_pool.begin().await.map_err(|_| db_fail())?;
+    let target = sqlx::query!("SELECT role FROM User WHERE id=?", player as u64)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|_| db_fail())?;
+    let target_role = match target {
+        Some(target) => Role::from_db(&target.role),
+        None => return Err(HttpResponse::NotFound().json(json!({"reason": "Player not found"}))),
+    };
+    // moderators are only moderated by admins, and admins by nobody.
+    // Dismissing a report doesn't do anything to the player
+    if moderator.role <= target_role && resolution.action != ModerationAction::Dismiss {
+        let reply = json!({"reason": "You can only moderate players with a lower role"});
+        return Err(HttpResponse::Forbidden().json(reply));
+    }
+
+    let updated = match resolution.action {
+        // admin actions were turned away above
+        ModerationAction::Triage
+        | ModerationAction::Disable
+        | ModerationAction::Enable
+        | ModerationAction::SetRole
+        | ModerationAction::RequireTwoFactor
+        | ModerationAction::ResetTwoFactor
+        | ModerationAction::Dismiss
+        | ModerationAction::Warn => Ok(()),
+        ModerationAction::Mute => sqlx::query!(
+            "UPDATE User SET chat_muted_until=FROM_UNIXTIME(?) WHERE id=?",
+            until,
+            player as u64,
+        )
+        .execute(&mut *tx)
+        .await
+        .map(|_| ()),
+        ModerationAction::Unmute => sqlx::query!(
+            "UPDATE User SET chat_muted_until=NULL WHERE id=?",
+            player as u64,
+        )
+        .execute(&mut *tx)
+        .await
+        .map(|_| ()),
+        ModerationAction::Ban => sqlx::query!(
+            "UPDATE User SET banned=TRUE, banned_until=FROM_UNIXTIME(?) WHERE id=?",
+            until,
+            player as u64,
+        )
+        .execute(&mut *tx)
+        .await
+        .map(|_| ()),
+        ModerationAction::Unban => sqlx::query!(
+            "UPDATE User SET banned=FALSE, banned_until=NULL WHERE id=?",
+            player as u64,
+        )
+        .execute(&mut *tx)
+        .await
+        .map(|_| ()),
+        ModerationAction::CloseAccount => sqlx::query!(
+            "UPDATE User SET closed_at=CURRENT_TIMESTAMP WHERE id=? AND closed_at IS NULL",
+            player as u64,
+        )
+        .execute(&mut *tx)
+        .await
+        .map(|_| ()),
+    };
+    let resolved = match report {
+        Some(report) => sqlx::query!(
+            "UPDATE Reports
+            SET status='resolved', resolved_at=CURRENT_TIMESTAMP, assignee=COALESCE(assignee, ?)
+            WHERE id=?",
+            moderator.id as u64,
+            report,
+        )
+        .execute(&mut *tx)
+        .await
+        .map(|_| ()),
+        None => Ok(()),
+    };
+    let recorded = moderation::record_action(
+        &mut tx,
+        moderator.id,
+        player,
+        report,
+        resolution.action,
+        &resolution.note,
+    )
+    .await;
+    if updated.and(resolved).and(recorded).is_err() || tx.commit().await.is_err() {
+        return Err(db_fail());
+    }
+    println!(
+        "moderator {}: {} player {player}",
+        moderator.id,
+        resolution.action.as_str()
+    );
+
+    match resolution.action {
+        ModerationAction::Mute | ModerationAction::Unmute => {
+            let _ = organizers
+                .game
+                .send(GameOrganizerRequest::ChatMute(player, until))
+                .await;
+        }
+        ModerationAction::Warn => {
+            let payload = NotificationPayload::ModerationWarning {
+                message: resolution.note.clone(),
+            };
+            match notifications::create(db_pool, player, payload).await {
+                Ok(notification) => {
+                    let _ = organizers
+                        .social
+                        .send(SocialRequest::Deliver(player, notification))
+                        .await;
+                }
+                Err(e) => println!("Couldn't send the warning: {e}"),
+            }
+        }
+        _ => {}
+    }
+    Ok(until)
+}
+
+pub async fn list_mutes(_moderator: Moderator, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    match sqlx::query!(
+        "SELECT id, username, TIMESTAMPDIFF(SECOND, CURRENT_TIMESTAMP, chat_muted_until)
+        `seconds_left!: i64`
+        FROM User
+        WHERE chat_muted_until > CURRENT_TIMESTAMP
+        ORDER BY chat_muted_until",
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(mutes) => {
+            let mutes: Vec<serde_json::Value> = mutes
+                .into_iter()
+                .map(|m| {
+                    json!({
+                        "player": m.id,
+                        "username": m.username,
+                        "seconds_left": m.seconds_left,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(mutes)
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// no game chat and no direct messages for the given time
+pub async fn mute_player(
+    moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    social_organizer: web::Data<Sender<SocialRequest>>,
+    data: web::Json<Mute>,
+) -> HttpResponse {
+    let data = data.into_inner();
+    let resolution = Resolution {
+        action: ModerationAction::Mute,
+        minutes: Some(data.minutes),
+        note: data.note,
+    };
+    let organizers = Organizers {
+        game: &game_organizer,
+        social: &social_organizer,
+    };
+    match apply_action(&db_pool, organizers, moderator, data.player, None, &resolution).await {
+        Ok(until) => HttpResponse::Ok().json(json!({"muted_until": until})),
+        Err(reply) => reply,
+    }
+}
+
+pub async fn unmute_player(
+    moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    social_organizer: web::Data<Sender<SocialRequest>>,
+    player_id: web::Path<PlayerId>,
+) -> HttpResponse {
+    let resolution = Resolution {
+        action: ModerationAction::Unmute,
+        minutes: None,
+        note: String::new(),
+    };
+    let organizers = Organizers {
+        game: &game_organizer,
+        social: &social_organizer,
+    };
+    let player_id = player_id.into_inner();
+    match apply_action(&db_pool, organizers, moderator, player_id, None, &resolution).await {
+        Ok(_) => HttpResponse::Ok().into(),
+        Err(reply) => reply,
+    }
+}
+
+/// oldest first, open ones by default
+pub async fn list_reports(
+    _moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    filter: web::Query<ReportFilter>,
+) -> HttpResponse {
+    let status = filter.status.unwrap_or(ReportStatus::Open);
+    match sqlx::query!(
+        "SELECT r.id, r.reporter, ru.username reporter_name, r.reported, pu.username reported_name,
+        r.game, r.reason, r.comment, r.status, r.assignee,
+        UNIX_TIMESTAMP(r.created_at) `created_at!: i64`
+        FROM Reports r
+        JOIN User ru ON r.reporter = ru.id
+        JOIN User pu ON r.reported = pu.id
+        WHERE r.status=?
+        ORDER BY r.id
+        LIMIT ?",
+        status.as_str(),
+        MAX_PAGE_SIZE,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(reports) => {
+            let reports: Vec<serde_json::Value> = reports
+                .into_iter()
+                .map(|r| {
+                    json!({
+                        "id": r.id,
+                        "reporter": {"id": r.reporter, "username": r.reporter_name},
+                        "reported": {"id": r.reported, "username": r.reported_name},
+                        "game_id": r.game,
+                        "reason": r.reason,
+                        "comment": r.comment,
+                        "status": r.status,
+                        "assignee": r.assignee,
+                        "created_at": r.created_at,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(reports)
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// the report with the chat of its game and earlier actions against the player
+pub async fn get_report(
+    _moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    report_id: web::Path<ReportId>,
+) -> HttpResponse {
+    let report = match sqlx::query!(
+        "SELECT id, reporter, reported, game, reason, comment, status, assignee,
+        UNIX_TIMESTAMP(created_at) `created_at!: i64`
+        FROM Reports WHERE id=?",
+        report_id.into_inner(),
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    {
+        Ok(Some(report)) => report,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"reason": "Report not found"})),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    };
+
+    let chat = match report.game {
+        Some(game_id) => match game_chat::of_game(&db_pool, game_id as u64).await {
+            Ok(chat) => chat,
+            Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+        },
+        None => Vec::new(),
+    };
+    let history = match sqlx::query!(
+        "SELECT id, moderator, report, action, note, UNIX_TIMESTAMP(created_at) `created_at!: i64`
+        FROM ModerationActions
+        WHERE player=?
+        ORDER BY id DESC",
+        report.reported,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(history) => history,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    };
+    let history: Vec<serde_json::Value> = history
+        .into_iter()
+        .map(|a| {
+            json!({
+                "id": a.id,
+                "moderator": a.moderator,
+                "report": a.report,
+                "action": a.action,
+                "note": a.note,
+                "created_at": a.created_at,
+            })
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "id": report.id,
+        "reporter": report.reporter,
+        "reported": report.reported,
+        "game_id": report.game,
+        "reason": report.reason,
+        "comment": report.comment,
+        "status": report.status,
+        "assignee": report.assignee,
+        "created_at": report.created_at,
+        "chat": chat,
+        "history": history,
+    }))
+}
+
+/// the moderator takes the report
+pub async fn triage_report(
+    moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    report_id: web::Path<ReportId>,
+) -> HttpResponse {
+    let report_id = report_id.into_inner();
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let report = sqlx::query!(
+        "SELECT reported FROM Reports WHERE id=? AND status='open' FOR UPDATE",
+        report_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+    let reported = match report {
+        Ok(Some(report)) => report.reported as PlayerId,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(json!({"reason": "No open report with this id"}))
+        }
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    let updated = sqlx::query!(
+        "UPDATE Reports SET status='triaged', assignee=? WHERE id=?",
+        moderator.id as u64,
+        report_id,
+    )
+    .execute(&mut *tx)
+    .await;
+    let recorded = moderation::record_action(
+        &mut tx,
+        moderator.id,
+        reported,
+        Some(report_id),
+        ModerationAction::Triage,
+        "",
+    )
+    .await;
+    if updated.is_err() || recorded.is_err() || tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+    HttpResponse::Ok().into()
+}
+
+pub async fn resolve_report(
+    moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    social_organizer: web::Data<Sender<SocialRequest>>,
+    report_id: web::Path<ReportId>,
+    data: web::Json<Resolution>,
+) -> HttpResponse {
+    let report_id = report_id.into_inner();
+    let reported = match sqlx::query!(
+        "SELECT reported FROM Reports WHERE id=? AND status != 'resolved'",
+        report_id,
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    {
+        Ok(Some(report)) => report.reported as PlayerId,
+        Ok(None) => {
+            return HttpResponse::NotFound()
+                .json(json!({"reason": "No unresolved report with this id"}))
+        }
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    };
+
+    let organizers = Organizers {
+        game: &game_organizer,
+        social: &social_organizer,
+    };
+    let resolved = apply_action(
+        &db_pool,
+        organizers,
+        moderator,
+        reported,
+        Some(report_id),
+        &data,
+    )
+    .await;
+    match resolved {
+        Ok(until) => HttpResponse::Ok().json(json!({"action": data.action, "until": until})),
+        Err(reply) => reply,
+    }
+}
+
+/// an action without a report, e.g. lifting a ban
+pub async fn take_action(
+    moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+    social_organizer: web::Data<Sender<SocialRequest>>,
+    data: web::Json<PlayerAction>,
+) -> HttpResponse {
+    if data.resolution.action == ModerationAction::Dismiss {
+        return HttpResponse::BadRequest().json(json!({"reason": "Nothing to dismiss"}));
+    }
+    let organizers = Organizers {
+        game: &game_organizer,
+        social: &social_organizer,
+    };
+    let taken = apply_action(
+        &db_pool,
+        organizers,
+        moderator,
+        data.player,
+        None,
+        &data.resolution,
+    )
+    .await;
+    match taken {
+        Ok(until) => {
+            HttpResponse::Ok().json(json!({"action": data.resolution.action, "until": until}))
+        }
+        Err(reply) => reply,
+    }
+}
+
+/// the audit trail, newest first
+pub async fn list_actions(
+    _moderator: Moderator,
+    db_pool: web::Data<Pool<MySql>>,
+    filter: web::Query<ActionFilter>,
+) -> HttpResponse {
+    let limit = filter.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let player = filter.player.map(|p| p as u64);
+    match sqlx::query!(
+        "SELECT a.id, a.moderator, m.username moderator_name, a.player, a.report, a.action, a.note,
+        UNIX_TIMESTAMP(a.created_at) `created_at!: i64`
+        FROM ModerationActions a
+        JOIN User m ON a.moderator = m.id
+        WHERE (? IS NULL OR a.player=?) AND a.id < ?
+        ORDER BY a.id DESC
+        LIMIT ?",
+        player,
+        player,
+        filter.before.unwrap_or(u64::MAX),
+        limit,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(actions) => {
+            // there are more if the page is full
+            let next = if actions.len() as u32 == limit {
+                actions.last().map(|a| a.id)
+            } else {
+                None
+            };
+            let actions: Vec<serde_json::Value> = actions
+                .into_iter()
+                .map(|a| {
+                    json!({
+                        "id": a.id,
+                        "moderator": {"id": a.moderator, "username": a.moderator_name},
+                        "player": a.player,
+                        "report": a.report,
+                        "action": a.action,
+                        "note": a.note,
+                        "created_at": a.created_at,
+                    })
+                })
+                .collect();
+            HttpResponse::Ok().json(json!({"next": next, "actions": actions}))
+        }
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
@@ -0,0 +1,123 @@
+use actix_web::{http::header::ACCEPT_LANGUAGE, web, HttpRequest, HttpResponse, Scope};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+
+use crate::{
+    extractors::authentication_token::AuthenticationToken,
+    notifications::{self, catalog, NotificationId},
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+
+pub fn notifications_scope() -> Scope {
+    web::scope("/notifications")
+        .route("/", web::get().to(list_notifications))
+        .route("/catalog", web::get().to(get_catalog))
+        .route("/read_all", web::post().to(read_all))
+        .route("/{id}/read", web::post().to(read_notification))
+        .route("/{id}", web::delete().to(dismiss_notification))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    /// id of the last notification already shown
+    before: Option<NotificationId>,
+    limit: Option<u32>,
+    /// if set every notification also gets its text in this language
+    locale: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Locale {
+    locale: Option<String>,
+}
+
+/// `?locale=` wins over the Accept-Language header
+fn requested_locale(req: &HttpRequest, locale: Option<&str>) -> &'static str {
+    let header = req
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split([',', ';']).next());
+    catalog::locale(locale.or(header))
+}
+
+pub async fn list_notifications(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    page: web::Query<Page>,
+) -> HttpResponse {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let list = notifications::list(&db_pool, id.id, page.before, limit).await;
+    let unread = notifications::unread_count(&db_pool, id.id).await;
+
+    match (list, unread) {
+        (Ok(list), Ok(unread)) => {
+            // there are more if the page is full
+            let next = if list.len() as u32 == limit {
+                list.last().map(|n| n.id)
+            } else {
+                None
+            };
+            let locale = page.locale.as_deref().map(|l| catalog::locale(Some(l)));
+            let list: Vec<serde_json::Value> = list
+                .into_iter()
+                .map(|n| {
+                    let mut value = json!(n);
+                    if let Some(locale) = locale {
+                        value["text"] = json!(catalog::render(&n.payload, locale));
+                    }
+                    value
+                })
+                .collect();
+
+            HttpResponse::Ok().json(json!({
+                "next": next,
+                "notifications": list,
+                "unread": unread,
+            }))
+        }
+        _ => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// templates for rendering live `request` messages on the client
+pub async fn get_catalog(req: HttpRequest, query: web::Query<Locale>) -> HttpResponse {
+    let locale = requested_locale(&req, query.locale.as_deref());
+    HttpResponse::Ok().json(json!({
+        "locale": locale,
+        "templates": catalog::templates(locale),
+    }))
+}
+
+pub async fn read_notification(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    notification_id: web::Path<NotificationId>,
+) -> HttpResponse {
+    match notifications::mark_read(&db_pool, id.id, Some(notification_id.into_inner())).await {
+        Ok(_) => HttpResponse::Ok().into(),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn read_all(id: AuthenticationToken, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    match notifications::mark_read(&db_pool, id.id, None).await {
+        Ok(marked) => HttpResponse::Ok().json(json!({"marked": marked})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn dismiss_notification(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    notification_id: web::Path<NotificationId>,
+) -> HttpResponse {
+    match notifications::delete(&db_pool, notification_id.into_inner(), id.id).await {
+        Ok(true) => HttpResponse::Ok().into(),
+        Ok(false) => HttpResponse::NotFound().json(json!({"reason": "Notification not found"})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
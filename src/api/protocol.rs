@@ -0,0 +1,308 @@
+//! Websocket protocol between the game server and the client.
+//!
+//! Every message is a json object with an `action` tag and its `data`,
+//! optionally a `game_id` and a client supplied message `id`.
+//! The first message on a socket must be `hello` with the protocol version.
+
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::{
+    api::game_ws::{ChessEnd, NewGameOptions, RematchAction},
+    chat_moderation::QuickMessage,
+    chess_logic::{GameResult, PieceMoves, Position},
+    direct_messages::{self, DirectMessage},
+    notifications::Notification,
+    presence::PresenceStatus,
+    sql::PlayerData,
+    tournament::arena::ArenaId,
+    GameId, PlayerId, WsMessageOutgoing,
+};
+
+/// 2: `request` carries a structured notification instead of html text
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Client supplied id, replies to a message carry the same id
+pub type MessageId = u64;
+
+pub type InvitationId = u64;
+
+/// client -> server
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ClientEnvelope {
+    pub id: Option<MessageId>,
+    pub game_id: Option<GameId>,
+    #[serde(flatten)]
+    pub message: ClientMessage,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "action", content = "data", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Hello { version: u32 },
+    Move { from: Position, to: Position },
+    Chat(String),
+    /// preset message, also shown to players without free chat
+    QuickChat(QuickMessage),
+    /// hide or show the opponent's chat for the rest of the game
+    MuteOpponent(bool),
+    End(ChessEnd),
+    NewGame(NewGameOptions),
+    Rematch(RematchAction),
+    ArenaJoin(ArenaId),
+    ArenaLeave(ArenaId),
+    InviteAccept(InvitationId),
+    InviteDecline(InvitationId),
+    /// withdraw your own invitation
+    InviteCancel(InvitationId),
+    /// the client went idle (hidden tab, no input) or came back
+    Idle(bool),
+}
+
+/// server -> client
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ServerEnvelope {
+    /// id of the client message this is a reply to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<MessageId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_id: Option<GameId>,
+    #[serde(flatten)]
+    pub message: ServerMessage,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "action", content = "data", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Hello { version: u32 },
+    Ack,
+    Error { code: ErrorCode, message: String },
+    Init(InitData),
+    /// legal moves if it's your turn, otherwise just the position
+    Move(Vec<PieceMoves>),
+    /// notation of the last move
+    #[serde(rename = "move info")]
+    MoveInfo(String),
+    Chat(String),
+    End(EndData),
+    Rematch(RematchData),
+    /// a new notification, same as in the notifications list
+    Request(Notification),
+    /// pushed to arena players whenever the standings change
+    ArenaLeaderboard(ArenaLeaderboard),
+    /// sent to both sides whenever the invitation changes
+    Invitation(InvitationData),
+    /// a friend's status changed, also sent for every friend when the socket connects
+    Presence(PresenceData),
+    /// sent to the receiver and to the sender's other sockets
+    DirectMessage(DirectMessage),
+    /// read receipt, the friend read your messages up to and including `up_to`
+    MessagesRead { by: PlayerId, up_to: direct_messages::MessageId },
+    /// from the admins to every socket, e.g. before a restart
+    Maintenance {
+        message: String,
+        /// seconds until the maintenance starts
+        starts_in: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// message isn't valid json or doesn't match the protocol
+    BadMessage,
+    HandshakeRequired,
+    UnsupportedVersion,
+    UnknownGame,
+    /// the game couldn't be started, it's gone
+    GameAborted,
+    /// the game can't take more messages right now, try again
+    GameBusy,
+    NotYourTurn,
+    IllegalMove,
+    NoRematchOffer,
+    UnknownArena,
+    UnknownInvitation,
+    /// a moderator muted you
+    ChatMuted,
+    ChatRateLimited,
+    MessageTooLong,
+    /// you turned off free chat, only quick chat works
+    FreeChatDisabled,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct InitData {
+    pub opponent: PlayerData,
+    /// (sent by you, text)
+    pub chat: Vec<(bool, String)>,
+    pub moves: Vec<String>,
+    /// None if there is no draw offer, otherwise whether the opponent offered it
+    pub ask_draw: Option<bool>,
+    pub new_game: bool,
+    /// "white" or "black"
+    pub playing: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum EndData {
+    Checkmate { win: bool },
+    Resign { win: bool },
+    DrawConfirm,
+    DrawCancel,
+    /// data is true for the player who has to answer the offer
+    DrawAsk { data: bool },
+    /// an admin ended the game
+    Terminated { reason: String, result: GameResult },
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RematchData {
+    /// by_you is true for the player who offered the rematch
+    Offer { by_you: bool },
+    Accepted { new_game_id: GameId },
+    Declined,
+    /// timed out or one of the players left
+    Expired,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ArenaLeaderboard {
+    pub arena_id: ArenaId,
+    pub name: String,
+    pub seconds_left: u64,
+    pub finished: bool,
+    /// best first
+    pub standings: Vec<ArenaStanding>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ArenaStanding {
+    pub player: PlayerId,
+    pub score: u32,
+    pub games: u32,
+    /// two wins in a row, the next results count double
+    pub on_fire: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct InvitationData {
+    pub invitation_id: InvitationId,
+    pub from: PlayerData,
+    pub to: PlayerId,
+    pub state: InvitationState,
+    pub seconds_left: u64,
+    /// the new game, once accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_id: Option<GameId>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PresenceData {
+    pub player: PlayerId,
+    #[serde(flatten)]
+    pub status: PresenceStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InvitationState {
+    Pending,
+    Accepted,
+    Declined,
+    Cancelled,
+    Expired,
+}
+
+impl ServerMessage {
+    pub fn in_game(self, game_id: GameId) -> ServerEnvelope {
+        ServerEnvelope {
+            id: None,
+            game_id: Some(game_id),
+            message: self,
+        }
+    }
+}
+
+impl From<ServerMessage> for ServerEnvelope {
+    fn from(message: ServerMessage) -> Self {
+        Self {
+            id: None,
+            game_id: None,
+            message,
+        }
+    }
+}
+
+impl ServerEnvelope {
+    pub fn to_ws(&self) -> WsMessageOutgoing {
+        serde_json::to_string(self).expect("Message to string serialization shouldn't fail")
+    }
+}
+
+/// Replies to a single client message on the socket it came from
+#[derive(Debug)]
+pub struct Responder {
+    channel: mpsc::Sender<WsMessageOutgoing>,
+    message_id: Option<MessageId>,
+}
+
+impl Responder {
+    pub fn new(channel: mpsc::Sender<WsMessageOutgoing>, message_id: Option<MessageId>) -> Self {
+        Self {
+            channel,
+            message_id,
+        }
+    }
+
+    /// only messages with an id get acknowledged
+    pub async fn ack(self) {
+        if self.message_id.is_none() {
+            return;
+        }
+        self.reply(ServerMessage::Ack).await;
+    }
+
+    pub async fn error(self, code: ErrorCode, message: impl Into<String>) {
+        self.reply(ServerMessage::Error {
+            code,
+            message: message.into(),
+        })
+        .await;
+    }
+
+    async fn reply(self, message: ServerMessage) {
+        let envelope = ServerEnvelope {
+            id: self.message_id,
+            game_id: None,
+            message,
+        };
+        let _ = self.channel.send(envelope.to_ws()).await;
+    }
+}
+
+/// parses a client message, the id is returned even if the rest of the message is wrong,
+/// so the error reply can reference it
+pub fn parse_client_message(
+    msg: &str,
+) -> (Option<MessageId>, Result<ClientEnvelope, serde_json::Error>) {
+    let value: serde_json::Value = match serde_json::from_str(msg) {
+        Ok(v) => v,
+        Err(err) => return (None, Err(err)),
+    };
+    let message_id = value.get("id").and_then(|id| id.as_u64());
+    (message_id, serde_json::from_value(value))
+}
+
+/// JSON Schema of the whole protocol, for the frontend
+pub fn protocol_schema() -> serde_json::Value {
+    json!({
+        "version": PROTOCOL_VERSION,
+        "client": schema_for!(ClientEnvelope),
+        "server": schema_for!(ServerEnvelope),
+    })
+}
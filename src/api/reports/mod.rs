@@ -0,0 +1,95 @@
+use actix_web::{web, HttpResponse, Scope};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+
+use crate::{
+    extractors::authentication_token::AuthenticationToken,
+    moderation::{ReportId, ReportReason},
+    PlayerId,
+};
+
+const MAX_COMMENT_LENGTH: usize = 1000;
+
+pub fn reports_scope() -> Scope {
+    web::scope("/reports").route("/", web::post().to(create_report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewReport {
+    player: PlayerId,
+    /// id from Games, moderators see its chat with the report
+    game_id: Option<u64>,
+    reason: ReportReason,
+    #[serde(default)]
+    comment: String,
+}
+
+/// from a game or a profile, reporting the same thing twice returns the open report
+pub async fn create_report(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<NewReport>,
+) -> HttpResponse {
+    if data.player == id.id {
+        return HttpResponse::BadRequest().json(json!({"reason": "You cant report yourself"}));
+    }
+    if data.comment.chars().count() > MAX_COMMENT_LENGTH {
+        return HttpResponse::BadRequest().json(json!({"reason": "Comment is too long"}));
+    }
+
+    if let Some(game_id) = data.game_id {
+        let played = sqlx::query!(
+            "SELECT id FROM Games
+            WHERE id=? AND ((white=? AND black=?) OR (white=? AND black=?))",
+            game_id,
+            id.id as u64,
+            data.player as u64,
+            data.player as u64,
+            id.id as u64,
+        )
+        .fetch_optional(db_pool.get_ref())
+        .await;
+        match played {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return HttpResponse::BadRequest()
+                    .json(json!({"reason": "You didnt play this game against the player"}))
+            }
+            Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+        }
+    }
+
+    let open = sqlx::query!(
+        "SELECT id FROM Reports
+        WHERE reporter=? AND reported=? AND game <=> ? AND status != 'resolved'",
+        id.id as u64,
+        data.player as u64,
+        data.game_id,
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await;
+    match open {
+        Ok(Some(report)) => {
+            return HttpResponse::Ok().json(json!({"report_id": report.id as ReportId}))
+        }
+        Ok(None) => {}
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+
+    match sqlx::query!(
+        "INSERT INTO Reports(reporter, reported, game, reason, comment) VALUES (?, ?, ?, ?, ?)",
+        id.id as u64,
+        data.player as u64,
+        data.game_id,
+        data.reason.as_str(),
+        data.comment.trim(),
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(res) => HttpResponse::Ok().json(json!({"report_id": res.last_insert_id()})),
+        // most likely the player doesn't exist
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "Couldnt save the report"})),
+    }
+}
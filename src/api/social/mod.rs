@@ -9,8 +9,14 @@ use std::collections::HashMap;
 use tokio::sync::mpsc;
 
 use crate::{
+    blocks,
     extractors::authentication_token::AuthenticationToken,
+    friends::{self, FriendRequestId, SendOutcome},
     game_organizer::GameOrganizerRequest,
+    game_chat,
+    notifications::{self, NotificationId, NotificationPayload},
+    presence::Presence,
+    social_organizer::SocialRequest,
     sql::{self, PlayerData},
     PlayerId,
 };
@@ -19,18 +25,36 @@ pub fn social_scope() -> Scope {
     web::scope("/social")
         .route("/profile", web::get().to(get_info))
         .route("/profile/{id}", web::get().to(get_info_other))
+        .route("/presence", web::get().to(get_presence))
+        .route("/presence", web::put().to(set_presence))
+        .route("/chat_settings", web::get().to(get_chat_settings))
+        .route("/chat_settings", web::put().to(set_chat_settings))
         .route("/", web::post().to(add_player))
         .route("/{id}", web::delete().to(delete_player))
         .route("/possible_friends", web::get().to(get_possible_friends))
+        .route("/friend_requests", web::get().to(get_friend_requests))
+        .route("/friend_requests/{id}", web::delete().to(cancel_friend_request))
         .route("/download_fen/{game_id}", web::get().to(get_fen_file))
+        .route("/games/{game_id}", web::get().to(get_game_replay))
+        .route("/blocks", web::get().to(get_blocks))
+        .route("/blocks", web::post().to(block_player))
+        .route("/blocks/{id}", web::delete().to(unblock_player))
 }
 
-pub async fn get_info(id: AuthenticationToken, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+pub async fn get_info(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    presence: web::Data<Presence>,
+) -> HttpResponse {
     println!("auth data");
-    get_info_inner(id.id as u64, db_pool).await
+    get_info_inner(id.id as u64, db_pool, &presence).await
 }
 
-async fn get_info_inner(id: u64, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+async fn get_info_inner(
+    id: u64,
+    db_pool: web::Data<Pool<MySql>>,
+    presence: &Presence,
+) -> HttpResponse {
     let player_data = sql::get_player_data(&db_pool, id)
         .await
         .expect("Error when fetching data from dg: get_player_data");
@@ -38,6 +62,15 @@ async fn get_info_inner(id: u64, db_pool: web::Data<Pool<MySql>>) -> HttpRespons
     let friends = sql::get_friends(&db_pool, id)
         .await
         .expect("Error when fetching data from db: get_friends");
+    let friends: Vec<serde_json::Value> = friends
+        .into_iter()
+        .map(|friend| {
+            let status = presence.status(friend.id as PlayerId);
+            let mut value = json!(friend);
+            value["presence"] = json!(status);
+            value
+        })
+        .collect();
 
     let games = sql::get_player_games(&db_pool, id)
         .await
@@ -79,15 +112,117 @@ async fn get_info_inner(id: u64, db_pool: web::Data<Pool<MySql>>) -> HttpRespons
     }))
 }
 
-pub async fn get_info_other(id: web::Path<i32>, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+pub async fn get_info_other(
+    id: web::Path<i32>,
+    db_pool: web::Data<Pool<MySql>>,
+    presence: web::Data<Presence>,
+) -> HttpResponse {
     println!("path data");
-    get_info_inner(id.into_inner() as u64, db_pool).await
+    get_info_inner(id.into_inner() as u64, db_pool, &presence).await
+}
+
+pub async fn get_presence(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    presence: web::Data<Presence>,
+) -> HttpResponse {
+    match sqlx::query!(
+        "SELECT appear_offline `appear_offline!: bool` FROM User WHERE id=?",
+        id.id as u64,
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(user) => HttpResponse::Ok().json(json!({
+            "appear_offline": user.appear_offline,
+            "presence": presence.status(id.id),
+        })),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresenceSettings {
+    appear_offline: bool,
+}
+
+/// with appear_offline friends see you as offline, even while playing
+pub async fn set_presence(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    presence: web::Data<Presence>,
+    social_organizer: web::Data<mpsc::Sender<SocialRequest>>,
+    data: web::Json<PresenceSettings>,
+) -> HttpResponse {
+    if sqlx::query!(
+        "UPDATE User SET appear_offline=? WHERE id=?",
+        data.appear_offline,
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .is_err()
+    {
+        return HttpResponse::BadRequest().json(json!({"reason": "db fail"}));
+    }
+
+    presence.set_appear_offline(id.id, data.appear_offline);
+    let _ = social_organizer
+        .send(SocialRequest::PresenceChanged(id.id))
+        .await;
+    HttpResponse::Ok().json(json!({"appear_offline": data.appear_offline}))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatSettings {
+    /// without free chat you only send and see quick messages
+    free_chat: bool,
+}
+
+pub async fn get_chat_settings(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    match sqlx::query!(
+        "SELECT free_chat `free_chat!: bool`,
+        TIMESTAMPDIFF(SECOND, CURRENT_TIMESTAMP, chat_muted_until) muted_for
+        FROM User WHERE id=?",
+        id.id as u64,
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(user) => HttpResponse::Ok().json(json!({
+            "free_chat": user.free_chat,
+            "muted_for": user.muted_for.filter(|seconds| *seconds > 0),
+        })),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// applies to games started after the change
+pub async fn set_chat_settings(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<ChatSettings>,
+) -> HttpResponse {
+    match sqlx::query!(
+        "UPDATE User SET free_chat=? WHERE id=?",
+        data.free_chat,
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({"free_chat": data.free_chat})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct NewPlayer {
     id: PlayerId,
-    request_id: Option<u32>,
+    request_id: Option<FriendRequestId>,
     msg_type: PlayerRequestType,
 }
 
@@ -96,23 +231,16 @@ pub enum PlayerRequestType {
     New,
     Accept,
     Reject,
-    DeleteNotification(u64),
+    DeleteNotification(NotificationId),
 }
 
 pub async fn add_player(
     id: AuthenticationToken,
     db_pool: web::Data<Pool<MySql>>,
     data: web::Json<NewPlayer>,
-    game_organizer: web::Data<mpsc::Sender<GameOrganizerRequest>>,
+    social_organizer: web::Data<mpsc::Sender<SocialRequest>>,
 ) -> HttpResponse {
-    if sql::get_friends(&db_pool, id.id as u64)
-        .await
-        .expect("Error when fetching data from db: get_friends")
-        .into_iter()
-        .any(|p| p.id == data.id as i32)
-    {
-        return HttpResponse::BadRequest().json(json!({"reason": "Player is already friend"}));
-    } else if id.id == data.id {
+    if id.id == data.id {
         if let PlayerRequestType::DeleteNotification(_) = data.msg_type {
         } else {
             return HttpResponse::BadRequest().json(json!({"reason": "You cant add yourself"}));
@@ -122,29 +250,47 @@ pub async fn add_player(
     match data.msg_type {
         PlayerRequestType::New => {
             println!("new friend req");
-            let request_id = rand::random();
-            let _ = game_organizer
-                .send(GameOrganizerRequest::FriendNew(request_id, id.id, data.id))
-                .await;
-
-            HttpResponse::Ok().json(json!({"request_id": request_id}))
-        }
-        PlayerRequestType::Accept => {
-            let request_id = match data.request_id {
-                Some(n) => n,
-                None => {
+            let request_id = match friends::send_request(&db_pool, id.id, data.id).await {
+                Ok(SendOutcome::Sent(request_id)) => request_id,
+                Ok(SendOutcome::Accepted) => {
+                    return HttpResponse::Ok().json(json!({"accepted": true}))
+                }
+                Ok(SendOutcome::AlreadyFriends) => {
                     return HttpResponse::BadRequest()
-                        .json(json!({"reason": "Request id wasnt specified"}))
+                        .json(json!({"reason": "Player is already friend"}))
+                }
+                Ok(SendOutcome::AlreadyPending) => {
+                    return HttpResponse::BadRequest()
+                        .json(json!({"reason": "Request was already sent"}))
                 }
+                // the blocked side doesn't find out, the request just never arrives
+                Ok(SendOutcome::Blocked) => {
+                    return match blocks::has_blocked(&db_pool, id.id, data.id).await {
+                        Ok(true) => HttpResponse::BadRequest()
+                            .json(json!({"reason": "You blocked this player"})),
+                        _ => HttpResponse::Ok().json(json!({"request_id": null})),
+                    }
+                }
+                Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
             };
-            let _ = game_organizer
-                .send(GameOrganizerRequest::FriendAccept(
-                    request_id, data.id, id.id,
-                ))
-                .await;
-            HttpResponse::Ok().into()
+            let from = match sql::get_player_data(&db_pool, id.id as u64).await {
+                Ok(from) => from,
+                Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+            };
+            let payload = NotificationPayload::FriendRequest { from, request_id };
+            match notifications::create(&db_pool, data.id, payload).await {
+                Ok(notification) => {
+                    let _ = social_organizer
+                        .send(SocialRequest::Deliver(data.id, notification))
+                        .await;
+                }
+                // the request is saved, it's still listed under friend_requests
+                Err(e) => println!("friend request notification failed: {e}"),
+            }
+
+            HttpResponse::Ok().json(json!({"request_id": request_id}))
         }
-        PlayerRequestType::Reject => {
+        PlayerRequestType::Accept | PlayerRequestType::Reject => {
             let request_id = match data.request_id {
                 Some(n) => n,
                 None => {
@@ -152,20 +298,19 @@ pub async fn add_player(
                         .json(json!({"reason": "Request id wasnt specified"}))
                 }
             };
-
-            let _ = game_organizer
-                .send(GameOrganizerRequest::FriendReject(
-                    request_id, data.id, id.id,
-                ))
-                .await;
-            HttpResponse::Ok().into()
+            let accept = data.msg_type == PlayerRequestType::Accept;
+            match friends::answer_request(&db_pool, request_id, id.id, accept).await {
+                Ok(Some(_)) => HttpResponse::Ok().into(),
+                Ok(None) => HttpResponse::NotFound().json(json!({"reason": "Request not found"})),
+                Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+            }
         }
         PlayerRequestType::DeleteNotification(req_id) => {
             println!("wooooowwww");
-            let _ = game_organizer
-                .send(GameOrganizerRequest::DeleteNotification(id.id, req_id))
-                .await;
-            HttpResponse::Ok().into()
+            match notifications::delete(&db_pool, req_id, id.id).await {
+                Ok(_) => HttpResponse::Ok().into(),
+                Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+            }
         }
     }
 }
@@ -220,10 +365,119 @@ pub async fn get_possible_friends(
         }
 }
 
+pub async fn get_friend_requests(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    let to_json = |requests: Vec<friends::PendingRequest>| -> Vec<serde_json::Value> {
+        requests
+            .into_iter()
+            .map(|(request_id, player)| json!({"request_id": request_id, "player": player}))
+            .collect()
+    };
+
+    match friends::pending_requests(&db_pool, id.id).await {
+        Ok((incoming, outgoing)) => HttpResponse::Ok().json(json!({
+            "incoming": to_json(incoming),
+            "outgoing": to_json(outgoing),
+        })),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// the sender takes back a request that wasn't answered yet
+pub async fn cancel_friend_request(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    request_id: web::Path<FriendRequestId>,
+) -> HttpResponse {
+    match friends::cancel_request(&db_pool, request_id.into_inner(), id.id).await {
+        Ok(Some(_)) => HttpResponse::Ok().into(),
+        Ok(None) => HttpResponse::NotFound().json(json!({"reason": "Request not found"})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Block {
+    player: PlayerId,
+}
+
+pub async fn get_blocks(id: AuthenticationToken, db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    match blocks::blocked_by(&db_pool, id.id).await {
+        Ok(blocked) => HttpResponse::Ok().json(json!({"blocked": blocked})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// also removes the player from friends
+pub async fn block_player(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<mpsc::Sender<GameOrganizerRequest>>,
+    data: web::Json<Block>,
+) -> HttpResponse {
+    if data.player == id.id {
+        return HttpResponse::BadRequest().json(json!({"reason": "You cant block yourself"}));
+    }
+    if blocks::block(&db_pool, id.id, data.player).await.is_err() {
+        return HttpResponse::BadRequest().json(json!({"reason": "db fail"}));
+    }
+    let _ = game_organizer
+        .send(GameOrganizerRequest::Block(id.id, data.player, true))
+        .await;
+    HttpResponse::Ok().into()
+}
+
+pub async fn unblock_player(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<mpsc::Sender<GameOrganizerRequest>>,
+    player_id: web::Path<PlayerId>,
+) -> HttpResponse {
+    let player_id = player_id.into_inner();
+    match blocks::unblock(&db_pool, id.id, player_id).await {
+        Ok(true) => {
+            let _ = game_organizer
+                .send(GameOrganizerRequest::Block(id.id, player_id, false))
+                .await;
+            HttpResponse::Ok().into()
+        }
+        Ok(false) => HttpResponse::NotFound().json(json!({"reason": "Player isnt blocked"})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportOptions {
+    /// embed the game chat as pgn comments
+    #[serde(default)]
+    chat: bool,
+}
+
+/// usernames of both players, for chat comments
+async fn game_player_names(
+    db_pool: &Pool<MySql>,
+    game_id: u64,
+) -> Result<HashMap<PlayerId, String>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT u.id, u.username FROM User u
+        JOIN Games g ON u.id IN (g.white, g.black)
+        WHERE g.id=?",
+        game_id,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id as PlayerId, row.username))
+    .collect())
+}
+
 pub async fn get_fen_file(
     id: AuthenticationToken,
     db_pool: web::Data<Pool<MySql>>,
     game_id: web::Path<u64>,
+    options: web::Query<ExportOptions>,
 ) -> HttpResponse {
     let game_id = game_id.into_inner();
     let res = match sqlx::query!(
@@ -239,9 +493,21 @@ pub async fn get_fen_file(
         Err(_) => return HttpResponse::BadRequest().body("Game id not found"),
     };
 
-    let file_content =
+    let mut file_content =
         std::fs::read_to_string(format!("/games/{}.pgn", res.game_file_uuid)).unwrap();
 
+    if options.chat {
+        let chat = game_chat::of_game(&db_pool, game_id).await;
+        let names = game_player_names(&db_pool, game_id).await;
+        match (chat, names) {
+            (Ok(chat), Ok(names)) => {
+                let moves = game_chat::pgn_moves(&file_content);
+                file_content = game_chat::pgn_with_chat(&moves, &chat, &names);
+            }
+            _ => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+        }
+    }
+
     HttpResponse::Ok()
         .content_type(ContentType::plaintext())
         .insert_header(ContentDisposition::attachment(format!(
@@ -250,3 +516,44 @@ pub async fn get_fen_file(
         )))
         .body(file_content)
 }
+
+/// moves and chat of a finished game, only for its players
+pub async fn get_game_replay(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    game_id: web::Path<u64>,
+) -> HttpResponse {
+    let game_id = game_id.into_inner();
+    let game = match sqlx::query!(
+        "SELECT white, black, win, game_file_uuid FROM Games
+        WHERE id=? AND (white=? OR black=?)",
+        game_id,
+        id.id as u64,
+        id.id as u64,
+    )
+    .fetch_optional(db_pool.as_ref())
+    .await
+    {
+        Ok(Some(game)) => game,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"reason": "Game not found"})),
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    };
+
+    let moves = match std::fs::read_to_string(format!("/games/{}.pgn", game.game_file_uuid)) {
+        Ok(pgn) => game_chat::pgn_moves(&pgn),
+        Err(_) => Vec::new(),
+    };
+    let chat = match game_chat::of_game(&db_pool, game_id).await {
+        Ok(chat) => chat,
+        Err(_) => return HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    };
+
+    HttpResponse::Ok().json(json!({
+        "id": game_id,
+        "white": game.white,
+        "black": game.black,
+        "win": game.win,
+        "moves": moves,
+        "chat": chat,
+    }))
+}
@@ -0,0 +1,281 @@
+use actix_web::{web, HttpResponse, Scope};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{MySql, Pool};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    extractors::authentication_token::AuthenticationToken,
+    game_organizer::GameOrganizerRequest,
+    tournament::{self, berger, PairingSystem, TournamentId},
+};
+
+pub fn tournament_scope() -> Scope {
+    web::scope("/tournament")
+        .route("/", web::post().to(create_tournament))
+        .route("/", web::get().to(list_tournaments))
+        .route("/{id}", web::get().to(get_tournament))
+        .route("/{id}/join", web::post().to(join_tournament))
+        .route("/{id}/start", web::post().to(start_tournament))
+        .route("/{id}/standings", web::get().to(get_standings))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewTournament {
+    name: String,
+    pairing_system: PairingSystem,
+    /// only for swiss, a round-robin has as many rounds as it needs
+    rounds: Option<u32>,
+}
+
+pub async fn create_tournament(
+    id: AuthenticationToken,
+    db_pool: web::Data<Pool<MySql>>,
+    data: web::Json<NewTournament>,
+) -> HttpResponse {
+    let rounds = match (data.pairing_system, data.rounds) {
+        (PairingSystem::RoundRobin, _) => 0, // set when the tournament starts
+        (PairingSystem::Swiss, Some(rounds)) if rounds > 0 => rounds,
+        (PairingSystem::Swiss, _) => {
+            return HttpResponse::BadRequest()
+                .json(json!({"reason": "Swiss tournament needs number of rounds"}))
+        }
+    };
+    if data.name.trim().is_empty() || data.name.len() > 100 {
+        return HttpResponse::BadRequest().json(json!({"reason": "Invalid tournament name"}));
+    }
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    let tournament_id = match sqlx::query!(
+        "INSERT INTO Tournaments(name, creator, pairing_system, num_of_rounds) VALUES (?, ?, ?, ?)",
+        data.name.trim(),
+        id.id as u64,
+        data.pairing_system.as_str(),
+        rounds,
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(res) => res.last_insert_id(),
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    // the creator plays in his own tournament
+    if sqlx::query!(
+        "INSERT INTO TournamentEntrants(tournament, player) VALUES (?, ?)",
+        tournament_id,
+        id.id as u64,
+    )
+    .execute(&mut *tx)
+    .await
+    .is_err()
+        || tx.commit().await.is_err()
+    {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+
+    HttpResponse::Ok().json(json!({"id": tournament_id}))
+}
+
+pub async fn list_tournaments(db_pool: web::Data<Pool<MySql>>) -> HttpResponse {
+    match sqlx::query!(
+        "SELECT t.id, t.name, t.pairing_system, t.num_of_rounds, t.current_round, t.status,
+        (SELECT COUNT(*) FROM TournamentEntrants e WHERE e.tournament = t.id) entrants
+        FROM Tournaments t
+        WHERE t.status != 'finished'
+        ORDER BY t.created_at DESC"
+    )
+    .fetch_all(db_pool.get_ref())
+    .await
+    {
+        Ok(rows) => HttpResponse::Ok().json(
+            rows.into_iter()
+                .map(|t| {
+                    json!({
+                        "id": t.id,
+                        "name": t.name,
+                        "pairing_system": t.pairing_system,
+                        "num_of_rounds": t.num_of_rounds,
+                        "current_round": t.current_round,
+                        "status": t.status,
+                        "entrants": t.entrants,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn get_tournament(
+    tournament_id: web::Path<TournamentId>,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    let tournament_id = tournament_id.into_inner();
+    let tournament = match sqlx::query!(
+        "SELECT id, name, creator, pairing_system, num_of_rounds, current_round, status
+        FROM Tournaments WHERE id=?",
+        tournament_id,
+    )
+    .fetch_one(db_pool.get_ref())
+    .await
+    {
+        Ok(t) => t,
+        Err(_) => return HttpResponse::NotFound().json(json!({"reason": "Tournament not found"})),
+    };
+
+    let entrants = sqlx::query_as!(
+        crate::sql::PlayerData,
+        "SELECT u.id, u.username, u.country FROM TournamentEntrants e
+        JOIN User u ON e.player = u.id
+        WHERE e.tournament=?
+        ORDER BY e.joined_at, e.player",
+        tournament_id,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await;
+
+    let pairings = sqlx::query!(
+        "SELECT r.number round, p.white, p.black, p.game, p.result FROM TournamentPairings p
+        JOIN TournamentRounds r ON p.round = r.id
+        WHERE r.tournament=?
+        ORDER BY r.number, p.id",
+        tournament_id,
+    )
+    .fetch_all(db_pool.get_ref())
+    .await;
+
+    match (entrants, pairings) {
+        (Ok(entrants), Ok(pairings)) => HttpResponse::Ok().json(json!({
+            "id": tournament.id,
+            "name": tournament.name,
+            "creator": tournament.creator,
+            "pairing_system": tournament.pairing_system,
+            "num_of_rounds": tournament.num_of_rounds,
+            "current_round": tournament.current_round,
+            "status": tournament.status,
+            "entrants": entrants,
+            "pairings": pairings
+                .into_iter()
+                .map(|p| json!({
+                    "round": p.round,
+                    "white": p.white,
+                    "black": p.black,
+                    "game": p.game,
+                    "result": p.result,
+                }))
+                .collect::<Vec<_>>(),
+        })),
+        _ => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+pub async fn join_tournament(
+    id: AuthenticationToken,
+    tournament_id: web::Path<TournamentId>,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    let tournament_id = tournament_id.into_inner();
+    let status = match sqlx::query!("SELECT status FROM Tournaments WHERE id=?", tournament_id)
+        .fetch_one(db_pool.get_ref())
+        .await
+    {
+        Ok(t) => t.status,
+        Err(_) => return HttpResponse::NotFound().json(json!({"reason": "Tournament not found"})),
+    };
+    if status != "open" {
+        return HttpResponse::BadRequest().json(json!({"reason": "Tournament already started"}));
+    }
+
+    match sqlx::query!(
+        "INSERT IGNORE INTO TournamentEntrants(tournament, player) VALUES (?, ?)",
+        tournament_id,
+        id.id as u64,
+    )
+    .execute(db_pool.get_ref())
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().into(),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
+
+/// only the creator can start it, then the first round is paired and its games started
+pub async fn start_tournament(
+    id: AuthenticationToken,
+    tournament_id: web::Path<TournamentId>,
+    db_pool: web::Data<Pool<MySql>>,
+    game_organizer: web::Data<Sender<GameOrganizerRequest>>,
+) -> HttpResponse {
+    let tournament_id = tournament_id.into_inner();
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+
+    let tournament = match sqlx::query!(
+        "SELECT creator, pairing_system, status FROM Tournaments WHERE id=? FOR UPDATE",
+        tournament_id,
+    )
+    .fetch_one(&mut *tx)
+    .await
+    {
+        Ok(t) => t,
+        Err(_) => return HttpResponse::NotFound().json(json!({"reason": "Tournament not found"})),
+    };
+    if tournament.creator as u64 != id.id as u64 {
+        return HttpResponse::Forbidden()
+            .json(json!({"reason": "Only the creator can start the tournament"}));
+    }
+    if tournament.status != "open" {
+        return HttpResponse::BadRequest().json(json!({"reason": "Tournament already started"}));
+    }
+
+    let entrants = match tournament::entrants(&mut tx, tournament_id).await {
+        Ok(entrants) => entrants,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    if entrants.len() < 2 {
+        return HttpResponse::BadRequest().json(json!({"reason": "Not enough players"}));
+    }
+
+    if PairingSystem::parse(&tournament.pairing_system) == Some(PairingSystem::RoundRobin) {
+        let rounds = berger::rounds(entrants.len()) as u32;
+        if sqlx::query!(
+            "UPDATE Tournaments SET num_of_rounds=? WHERE id=?",
+            rounds,
+            tournament_id,
+        )
+        .execute(&mut *tx)
+        .await
+        .is_err()
+        {
+            return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+        }
+    }
+
+    let games = match tournament::start_next_round(&mut tx, tournament_id).await {
+        Ok(games) => games,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"reason": "db fail"})),
+    };
+    if tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().json(json!({"reason": "db fail"}));
+    }
+
+    let _ = game_organizer
+        .send(GameOrganizerRequest::StartTournamentGames(games))
+        .await;
+    HttpResponse::Ok().into()
+}
+
+pub async fn get_standings(
+    tournament_id: web::Path<TournamentId>,
+    db_pool: web::Data<Pool<MySql>>,
+) -> HttpResponse {
+    match tournament::get_standings(&db_pool, tournament_id.into_inner()).await {
+        Ok(standings) => HttpResponse::Ok().json(standings),
+        Err(_) => HttpResponse::BadRequest().json(json!({"reason": "db fail"})),
+    }
+}
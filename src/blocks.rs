@@ -0,0 +1,151 @@
+//! Block list. A block works both ways, neither player can reach the other,
+//! but only the blocker can lift it.
+
+use sqlx::{MySql, MySqlConnection, Pool};
+use std::collections::HashSet;
+
+use crate::{sql::PlayerData, PlayerId};
+
+/// All blocks as (blocker, blocked), kept in memory by the game organizer
+#[derive(Debug, Default)]
+pub struct BlockList(HashSet<(PlayerId, PlayerId)>);
+
+impl BlockList {
+    pub async fn load(db_pool: &Pool<MySql>) -> Result<Self, sqlx::Error> {
+        Ok(Self(
+            sqlx::query!("SELECT blocker, blocked FROM Blocks")
+                .fetch_all(db_pool)
+                .await?
+                .into_iter()
+                .map(|row| (row.blocker as PlayerId, row.blocked as PlayerId))
+                .collect(),
+        ))
+    }
+
+    pub fn set(&mut self, blocker: PlayerId, blocked: PlayerId, block: bool) {
+        if block {
+            self.0.insert((blocker, blocked));
+        } else {
+            self.0.remove(&(blocker, blocked));
+        }
+    }
+
+    /// either of them blocked the other
+    pub fn between(&self, a: PlayerId, b: PlayerId) -> bool {
+        self.0.contains(&(a, b)) || self.0.contains(&(b, a))
+    }
+}
+
+/// either of them blocked the other
+pub async fn between(
+    conn: &mut MySqlConnection,
+    a: PlayerId,
+    b: PlayerId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id FROM Blocks WHERE (blocker=? AND blocked=?) OR (blocker=? AND blocked=?)",
+        a as u64,
+        b as u64,
+        b as u64,
+        a as u64,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .is_some())
+}
+
+pub async fn has_blocked(
+    db_pool: &Pool<MySql>,
+    blocker: PlayerId,
+    blocked: PlayerId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id FROM Blocks WHERE blocker=? AND blocked=?",
+        blocker as u64,
+        blocked as u64,
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .is_some())
+}
+
+/// also ends the friendship and drops pending friend requests between them
+pub async fn block(
+    db_pool: &Pool<MySql>,
+    blocker: PlayerId,
+    blocked: PlayerId,
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query!(
+        "INSERT IGNORE INTO Blocks(blocker, blocked) VALUES (?, ?)",
+        blocker as u64,
+        blocked as u64,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM Friends WHERE user_low=LEAST(?, ?) AND user_high=GREATEST(?, ?)",
+        blocker as u64,
+        blocked as u64,
+        blocker as u64,
+        blocked as u64,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM FriendRequests WHERE user_low=LEAST(?, ?) AND user_high=GREATEST(?, ?)",
+        blocker as u64,
+        blocked as u64,
+        blocker as u64,
+        blocked as u64,
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM Notifications
+        WHERE kind='friend_request' AND ((player=? AND sender=?) OR (player=? AND sender=?))",
+        blocker as u64,
+        blocked as u64,
+        blocked as u64,
+        blocker as u64,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// true if there was a block to lift
+pub async fn unblock(
+    db_pool: &Pool<MySql>,
+    blocker: PlayerId,
+    blocked: PlayerId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "DELETE FROM Blocks WHERE blocker=? AND blocked=?",
+        blocker as u64,
+        blocked as u64,
+    )
+    .execute(db_pool)
+    .await?
+    .rows_affected()
+        == 1)
+}
+
+/// players the player blocked, not the ones who blocked them
+pub async fn blocked_by(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+) -> Result<Vec<PlayerData>, sqlx::Error> {
+    sqlx::query_as!(
+        PlayerData,
+        "SELECT u.id, u.username, u.country FROM Blocks b
+        JOIN User u ON b.blocked = u.id
+        WHERE b.blocker=?
+        ORDER BY b.id",
+        player_id as u64,
+    )
+    .fetch_all(db_pool)
+    .await
+}
@@ -0,0 +1,232 @@
+//! Checks on game chat before it reaches the opponent:
+//! rate limit, length, word filter and preset messages for players without free chat.
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::PlayerId;
+
+pub const MAX_CHAT_LENGTH: usize = 300;
+const RATE_LIMIT_MESSAGES: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Preset messages, the only ones shown to players who turned off free chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuickMessage {
+    Hello,
+    GoodLuck,
+    HaveFun,
+    GoodGame,
+    WellPlayed,
+    Thanks,
+    Oops,
+}
+
+impl QuickMessage {
+    pub fn text(self) -> &'static str {
+        match self {
+            QuickMessage::Hello => "Hello!",
+            QuickMessage::GoodLuck => "Good luck!",
+            QuickMessage::HaveFun => "Have fun!",
+            QuickMessage::GoodGame => "Good game!",
+            QuickMessage::WellPlayed => "Well played!",
+            QuickMessage::Thanks => "Thank you!",
+            QuickMessage::Oops => "Oops!",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ChatText {
+    Free(String),
+    Quick(QuickMessage),
+}
+
+impl ChatText {
+    pub fn is_preset(&self) -> bool {
+        matches!(self, ChatText::Quick(_))
+    }
+
+    pub fn into_text(self) -> String {
+        match self {
+            ChatText::Free(text) => text,
+            ChatText::Quick(message) => message.text().to_string(),
+        }
+    }
+}
+
+/// At most `RATE_LIMIT_MESSAGES` per player in any `RATE_LIMIT_WINDOW`, over all games
+#[derive(Debug, Default)]
+pub struct ChatLimiter(HashMap<PlayerId, VecDeque<Instant>>);
+
+impl ChatLimiter {
+    /// counts the message if it's allowed
+    pub fn allow(&mut self, player_id: PlayerId) -> bool {
+        self.allow_at(player_id, Instant::now())
+    }
+
+    fn allow_at(&mut self, player_id: PlayerId, now: Instant) -> bool {
+        let sent = self.0.entry(player_id).or_default();
+        while sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= RATE_LIMIT_WINDOW)
+        {
+            sent.pop_front();
+        }
+        if sent.len() >= RATE_LIMIT_MESSAGES {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    pub fn forget(&mut self, player_id: PlayerId) {
+        self.0.remove(&player_id);
+    }
+}
+
+/// Masks whole words from a list with `*`, case insensitive
+#[derive(Debug, Default)]
+pub struct WordFilter {
+    words: HashSet<String>,
+}
+
+impl WordFilter {
+    /// words from the file in `CHAT_WORD_FILTER`, one per line, nothing is filtered without it
+    pub fn from_env() -> Self {
+        let path = match std::env::var("CHAT_WORD_FILTER") {
+            Ok(path) => path,
+            Err(_) => return Self::default(),
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(list) => Self {
+                words: list
+                    .lines()
+                    .map(|word| word.trim().to_lowercase())
+                    .filter(|word| !word.is_empty())
+                    .collect(),
+            },
+            Err(e) => {
+                println!("Couldn't read word filter {path}: {e}");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn mask(&self, text: &str) -> String {
+        if self.words.is_empty() {
+            return text.to_string();
+        }
+        let mut masked = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars() {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            self.push_word(&mut masked, &word);
+            word.clear();
+            masked.push(c);
+        }
+        self.push_word(&mut masked, &word);
+        masked
+    }
+
+    fn push_word(&self, masked: &mut String, word: &str) {
+        if self.words.contains(&word.to_lowercase()) {
+            masked.extend(word.chars().map(|_| '*'));
+        } else {
+            masked.push_str(word);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(words: &[&str]) -> WordFilter {
+        WordFilter {
+            words: words.iter().map(|word| word.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn masks_listed_words_in_any_case() {
+        let filter = filter(&["darn"]);
+        assert_eq!(filter.mask("darn"), "****");
+        assert_eq!(filter.mask("Darn it, DARN"), "**** it, ****");
+    }
+
+    #[test]
+    fn masks_whole_words_only() {
+        let filter = filter(&["darn"]);
+        assert_eq!(filter.mask("darned undarn"), "darned undarn");
+        assert_eq!(filter.mask("darn-it darn!"), "****-it ****!");
+        assert_eq!(filter.mask("(darn)"), "(****)");
+    }
+
+    #[test]
+    fn masks_non_ascii_words_by_character() {
+        let filter = filter(&["čudo"]);
+        assert_eq!(filter.mask("ČUDO je"), "**** je");
+    }
+
+    #[test]
+    fn empty_filter_keeps_the_text() {
+        let filter = WordFilter::default();
+        assert_eq!(filter.mask("darn it"), "darn it");
+    }
+
+    #[test]
+    fn limiter_allows_a_burst_then_refuses() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            assert!(limiter.allow_at(1, start));
+        }
+        assert!(!limiter.allow_at(1, start));
+        assert!(!limiter.allow_at(1, start + RATE_LIMIT_WINDOW - Duration::from_millis(1)));
+        // other players have their own limit
+        assert!(limiter.allow_at(2, start));
+    }
+
+    #[test]
+    fn limiter_allows_again_after_the_window() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for i in 0..RATE_LIMIT_MESSAGES as u64 {
+            assert!(limiter.allow_at(1, start + Duration::from_secs(i)));
+        }
+        // only the first message left the window
+        let later = start + RATE_LIMIT_WINDOW;
+        assert!(limiter.allow_at(1, later));
+        assert!(!limiter.allow_at(1, later));
+    }
+
+    #[test]
+    fn refused_messages_dont_count() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for _ in 0..RATE_LIMIT_MESSAGES * 3 {
+            limiter.allow_at(1, start);
+        }
+        assert!(limiter.allow_at(1, start + RATE_LIMIT_WINDOW));
+    }
+
+    #[test]
+    fn forget_resets_the_limit() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for _ in 0..RATE_LIMIT_MESSAGES {
+            limiter.allow_at(1, start);
+        }
+        limiter.forget(1);
+        assert!(limiter.allow_at(1, start));
+    }
+}
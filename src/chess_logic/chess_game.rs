@@ -1,4 +1,3 @@
-use serde_json::{json, Value};
 use std::collections::HashMap;
 
 use super::{
@@ -7,9 +6,12 @@ use super::{
         BishopDirection, Direction, KingDirection, KnightDirection, PawnEatingDirection,
         RookDirection,
     },
-    Player, Position, PositionWithDirection,
+    PieceMoves, Player, Position, PositionWithDirection,
+};
+use crate::{
+    chess_logic::direction::get_direction_from_id, game_chat::ChatLine, sql::PlayerData, GameId,
+    PlayerId,
 };
-use crate::{chess_logic::direction::get_direction_from_id, sql::PlayerData, GameId, PlayerId};
 
 #[derive(Debug)]
 pub struct ChessGame {
@@ -26,7 +28,7 @@ pub struct ChessGame {
 
     calculated_legal_moves: Option<(bool, [[Option<Vec<PositionWithDirection>>; 8]; 8])>,
 
-    pub current_chat_data: Vec<(PlayerId, String)>,
+    pub current_chat_data: Vec<ChatLine>,
     pub current_move_data: Vec<String>,
 
     /// who requested draw (player id)
@@ -293,21 +295,24 @@ impl ChessGame {
         (has_moves, moves)
     }
 
-    pub fn get_moves_as_json(&mut self) -> Vec<Value> {
+    pub fn get_piece_moves(&mut self) -> Vec<PieceMoves> {
         let mut moves = self.get_moves();
         let mut final_moves = Vec::new();
 
         for x in 0..8 {
             for y in 0..8 {
                 if let Some(piece) = self.board.get(Position::new(x, y)).as_ref() {
-                    final_moves.push(json!({
-                        "filename": piece.get_piece_name(),
-                        "position": piece.get_position(),
-                        "moves": match moves.1[y as usize][x as usize].take() {
-                            Some(legal_moves) => legal_moves.into_iter().map(|(to, _dir_id)| to).collect::<Vec<Position>>(),
+                    final_moves.push(PieceMoves {
+                        filename: piece.get_piece_name(),
+                        position: piece.get_position(),
+                        moves: match moves.1[y as usize][x as usize].take() {
+                            Some(legal_moves) => legal_moves
+                                .into_iter()
+                                .map(|(to, _dir_id)| to)
+                                .collect::<Vec<Position>>(),
                             None => Vec::with_capacity(0),
                         },
-                    }));
+                    });
                 }
             }
         }
@@ -315,17 +320,18 @@ impl ChessGame {
         final_moves
     }
 
-    pub fn get_position_as_json(&mut self) -> Vec<Value> {
+    /// same as `get_piece_moves`, but without any moves (for the player who isn't on turn)
+    pub fn get_piece_positions(&mut self) -> Vec<PieceMoves> {
         let mut final_moves = Vec::new();
 
         for x in 0..8 {
             for y in 0..8 {
                 if let Some(piece) = self.board.get(Position::new(x, y)).as_ref() {
-                    final_moves.push(json!({
-                        "filename": piece.get_piece_name(),
-                        "position": piece.get_position(),
-                        "moves": []
-                    }));
+                    final_moves.push(PieceMoves {
+                        filename: piece.get_piece_name(),
+                        position: piece.get_position(),
+                        moves: Vec::new(),
+                    });
                 }
             }
         }
@@ -7,10 +7,11 @@ pub mod direction;
 pub mod piece;
 
 pub use board::Board;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// Chess player (white or black)
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum Player {
     White,
     Black,
@@ -28,7 +29,7 @@ impl Player {
         *self = self.opponent();
     }
 
-    fn opponent(&self) -> Self {
+    pub fn opponent(&self) -> Self {
         match self {
             Player::White => Player::Black,
             Player::Black => Player::White,
@@ -36,11 +37,56 @@ impl Player {
     }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, Hash, PartialEq, Eq)]
+/// Result of a finished game, same as the `win` column of Games
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GameResult {
+    White,
+    Black,
+    Draw,
+}
+
+impl GameResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameResult::White => "white",
+            GameResult::Black => "black",
+            GameResult::Draw => "draw",
+        }
+    }
+
+    pub fn parse(result: &str) -> Option<Self> {
+        match result {
+            "white" => Some(GameResult::White),
+            "black" => Some(GameResult::Black),
+            "draw" => Some(GameResult::Draw),
+            _ => None,
+        }
+    }
+
+    /// points the player who played with `colour` got, in half points (so a draw is 1)
+    pub fn half_points(&self, colour: Player) -> u32 {
+        match (self, colour) {
+            (GameResult::Draw, _) => 1,
+            (GameResult::White, Player::White) | (GameResult::Black, Player::Black) => 2,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Hash, PartialEq, Eq, JsonSchema)]
 pub struct Position(i32, i32);
 
 pub type PositionWithDirection = (Position, i32);
 
+/// A piece on the board with squares it can move to
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PieceMoves {
+    pub filename: String,
+    pub position: Position,
+    pub moves: Vec<Position>,
+}
+
 #[allow(dead_code)]
 impl Position {
     pub fn new(x: i32, y: i32) -> Self {
@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc;
+
+use crate::{api::protocol::ServerEnvelope, ConnectionId, PlayerId, WsMessageOutgoing};
+
+/// All websockets a single player currently has open (e.g. multiple tabs)
+#[derive(Debug, Default)]
+struct PlayerConnections(HashMap<ConnectionId, mpsc::Sender<WsMessageOutgoing>>);
+
+/// Open websockets of every online player.
+/// Shared between the game organizer, the game actors and the social organizer,
+/// so any of them can message a player without going through another task.
+#[derive(Debug, Default, Clone)]
+pub struct Connections(Arc<Mutex<HashMap<PlayerId, PlayerConnections>>>);
+
+impl Connections {
+    pub fn insert(
+        &self,
+        player_id: PlayerId,
+        connection_id: ConnectionId,
+        channel: mpsc::Sender<WsMessageOutgoing>,
+    ) {
+        self.0
+            .lock()
+            .expect("Connections lock poisoned")
+            .entry(player_id)
+            .or_default()
+            .0
+            .insert(connection_id, channel);
+    }
+
+    /// removes only the socket that closed, the player stays online while he has any open
+    pub fn remove(&self, player_id: PlayerId, connection_id: ConnectionId) {
+        let mut players = self.0.lock().expect("Connections lock poisoned");
+        if let Some(connections) = players.get_mut(&player_id) {
+            connections.0.remove(&connection_id);
+            if connections.0.is_empty() {
+                players.remove(&player_id);
+            }
+        }
+    }
+
+    pub fn is_online(&self, player_id: PlayerId) -> bool {
+        self.0
+            .lock()
+            .expect("Connections lock poisoned")
+            .contains_key(&player_id)
+    }
+
+    /// number of open sockets of the player
+    pub fn count(&self, player_id: PlayerId) -> usize {
+        self.0
+            .lock()
+            .expect("Connections lock poisoned")
+            .get(&player_id)
+            .map(|connections| connections.0.len())
+            .unwrap_or(0)
+    }
+
+    /// clones the senders, so the lock isn't held while sending
+    fn channels(&self, player_id: PlayerId) -> Vec<mpsc::Sender<WsMessageOutgoing>> {
+        match self
+            .0
+            .lock()
+            .expect("Connections lock poisoned")
+            .get(&player_id)
+        {
+            Some(connections) => connections.0.values().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// sends the message to every socket of the player
+    pub async fn send(&self, player_id: PlayerId, msg: WsMessageOutgoing) {
+        for channel in self.channels(player_id) {
+            let _ = channel.send(msg.clone()).await;
+        }
+    }
+
+    pub async fn send_all(&self, player_id: PlayerId, msgs: &[WsMessageOutgoing]) {
+        for channel in self.channels(player_id) {
+            for msg in msgs {
+                let _ = channel.send(msg.clone()).await;
+            }
+        }
+    }
+
+    /// sends the message to every open socket, returns how many there were
+    pub async fn broadcast(&self, msg: WsMessageOutgoing) -> usize {
+        let channels: Vec<mpsc::Sender<WsMessageOutgoing>> = self
+            .0
+            .lock()
+            .expect("Connections lock poisoned")
+            .values()
+            .flat_map(|connections| connections.0.values().cloned())
+            .collect();
+        for channel in &channels {
+            let _ = channel.send(msg.clone()).await;
+        }
+        channels.len()
+    }
+
+    /// sends specified message to all of the player's sockets
+    pub async fn send_message(&self, player_id: PlayerId, message: impl Into<ServerEnvelope>) {
+        self.send(player_id, message.into().to_ws()).await;
+    }
+}
@@ -0,0 +1,157 @@
+//! One-to-one conversations between friends.
+//! Messages are saved first, the social organizer then pushes them live.
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
+
+use crate::PlayerId;
+
+pub type MessageId = u64;
+
+/// same as the column size
+pub const MAX_MESSAGE_LENGTH: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DirectMessage {
+    pub id: MessageId,
+    pub from: PlayerId,
+    pub to: PlayerId,
+    pub text: String,
+    pub read: bool,
+    /// unix timestamp
+    pub created_at: i64,
+}
+
+struct MessageRow {
+    id: i32,
+    sender: i32,
+    receiver: i32,
+    text: String,
+    read: bool,
+    created_at: i64,
+}
+
+impl From<MessageRow> for DirectMessage {
+    fn from(row: MessageRow) -> Self {
+        Self {
+            id: row.id as MessageId,
+            from: row.sender as PlayerId,
+            to: row.receiver as PlayerId,
+            text: row.text,
+            read: row.read,
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub async fn send(
+    db_pool: &Pool<MySql>,
+    from: PlayerId,
+    to: PlayerId,
+    text: String,
+) -> Result<DirectMessage, sqlx::Error> {
+    let id = sqlx::query!(
+        "INSERT INTO DirectMessages(sender, receiver, text) VALUES (?, ?, ?)",
+        from as u64,
+        to as u64,
+        text,
+    )
+    .execute(db_pool)
+    .await?
+    .last_insert_id();
+
+    Ok(DirectMessage {
+        id,
+        from,
+        to,
+        text,
+        read: false,
+        created_at: Utc::now().timestamp(),
+    })
+}
+
+/// newest first, `before` is the id of the last message of the previous page
+pub async fn history(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    friend_id: PlayerId,
+    before: Option<MessageId>,
+    limit: u32,
+) -> Result<Vec<DirectMessage>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        MessageRow,
+        "SELECT id, sender, receiver, text, read_at IS NOT NULL `read!: bool`,
+        UNIX_TIMESTAMP(created_at) `created_at!: i64`
+        FROM DirectMessages
+        WHERE user_low=LEAST(?, ?) AND user_high=GREATEST(?, ?) AND id < ?
+        ORDER BY id DESC
+        LIMIT ?",
+        player_id as u64,
+        friend_id as u64,
+        player_id as u64,
+        friend_id as u64,
+        before.unwrap_or(u64::MAX),
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(rows.into_iter().map(DirectMessage::from).collect())
+}
+
+/// (friend, unread messages from them), only friends with unread messages
+pub async fn unread_counts(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+) -> Result<Vec<(PlayerId, i64)>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT sender, COUNT(*) count FROM DirectMessages
+        WHERE receiver=? AND read_at IS NULL
+        GROUP BY sender",
+        player_id as u64,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.sender as PlayerId, row.count))
+    .collect())
+}
+
+/// marks everything the friend sent as read,
+/// returns the newest message that was marked so the friend can get a read receipt
+pub async fn mark_read(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    friend_id: PlayerId,
+) -> Result<Option<MessageId>, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    let up_to = sqlx::query!(
+        "SELECT MAX(id) id FROM DirectMessages
+        WHERE sender=? AND receiver=? AND read_at IS NULL",
+        friend_id as u64,
+        player_id as u64,
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .id;
+    let up_to = match up_to {
+        Some(up_to) => up_to as MessageId,
+        None => return Ok(None),
+    };
+
+    sqlx::query!(
+        "UPDATE DirectMessages SET read_at=CURRENT_TIMESTAMP
+        WHERE sender=? AND receiver=? AND id <= ? AND read_at IS NULL",
+        friend_id as u64,
+        player_id as u64,
+        up_to,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(up_to))
+}
@@ -0,0 +1,103 @@
+//! Single use tokens for the links in verification and password reset mails.
+//! Only a hash is stored, a token works once and until it expires.
+
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, MySqlConnection, Pool};
+
+use crate::PlayerId;
+
+const TOKEN_LENGTH: usize = 43;
+pub const VERIFY_EMAIL_HOURS: i64 = 48;
+pub const RESET_PASSWORD_MINUTES: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl Purpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Purpose::VerifyEmail => "verify_email",
+            Purpose::ResetPassword => "reset_password",
+        }
+    }
+
+    fn lifetime_minutes(&self) -> i64 {
+        match self {
+            Purpose::VerifyEmail => VERIFY_EMAIL_HOURS * 60,
+            Purpose::ResetPassword => RESET_PASSWORD_MINUTES,
+        }
+    }
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// the token for the link, earlier unused tokens with the same purpose stop working
+pub async fn create(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    purpose: Purpose,
+    email: &str,
+) -> Result<String, sqlx::Error> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+    let mut tx = db_pool.begin().await?;
+    sqlx::query!(
+        "UPDATE EmailTokens SET used_at=CURRENT_TIMESTAMP
+        WHERE player=? AND purpose=? AND used_at IS NULL",
+        player_id as u64,
+        purpose.as_str(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO EmailTokens(player, purpose, token_hash, email, expires_at)
+        VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP + INTERVAL ? MINUTE)",
+        player_id as u64,
+        purpose.as_str(),
+        hash(&token),
+        email,
+        purpose.lifetime_minutes(),
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(token)
+}
+
+/// marks the token used, the player and the address it was sent to if it was valid.
+/// The address no longer being the player's email is checked by the caller.
+pub async fn consume(
+    conn: &mut MySqlConnection,
+    token: &str,
+    purpose: Purpose,
+) -> Result<Option<(PlayerId, String)>, sqlx::Error> {
+    let found = sqlx::query!(
+        "SELECT id, player, email FROM EmailTokens
+        WHERE token_hash=? AND purpose=? AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        FOR UPDATE",
+        hash(token),
+        purpose.as_str(),
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    let found = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    sqlx::query!(
+        "UPDATE EmailTokens SET used_at=CURRENT_TIMESTAMP WHERE id=?",
+        found.id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(Some((found.player as PlayerId, found.email)))
+}
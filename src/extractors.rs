@@ -1,25 +1,43 @@
 pub mod authentication_token {
     use actix_web::{
-        dev::Payload, error::ErrorUnauthorized, web, Error as ActixWebError, FromRequest,
-        HttpRequest,
+        dev::Payload,
+        error::{ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized},
+        web, Error as ActixWebError, FromRequest, HttpRequest,
     };
+    use futures::future::LocalBoxFuture;
     use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
     use serde::{Deserialize, Serialize};
-    use std::future::{ready, Ready};
+    use sqlx::{MySql, Pool};
 
-    use crate::PlayerId;
+    use crate::{
+        moderation,
+        roles::Role,
+        sessions::{self, SessionId},
+        PlayerId,
+    };
 
+    /// the short lived access token
     pub const COOKIE_NAME: &'static str = "jwt_token";
+    /// only sent to /auth, where the access token is renewed
+    pub const REFRESH_COOKIE_NAME: &str = "refresh_token";
 
     #[derive(Serialize, Deserialize)]
     pub struct Claims {
         pub id: usize,
         pub exp: usize,
+        /// role when the token was issued, a refresh picks up changes
+        pub role: Role,
+        /// session the token belongs to, tokens without one aren't accepted anymore
+        pub sid: SessionId,
     }
 
     #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
     pub struct AuthenticationToken {
         pub id: usize,
+        /// when the token expires (unix timestamp)
+        pub exp: usize,
+        pub role: Role,
+        pub sid: SessionId,
     }
 
     impl Into<PlayerId> for AuthenticationToken {
@@ -28,46 +46,278 @@ pub mod authentication_token {
         }
     }
 
+    /// reads the token from the cookie and validates it
+    pub fn claims_from_request(req: &HttpRequest) -> Result<Claims, ActixWebError> {
+        let authentication_token = match req
+            .cookies()
+            .expect("Panicked on getting cookies from request")
+            .iter()
+            .filter(|c| c.name() == COOKIE_NAME)
+            .next()
+        {
+            Some(cookie) => cookie.value().to_string(),
+            None => return Err(ErrorUnauthorized("No authentication token sent!")),
+        };
+
+        if authentication_token.is_empty() {
+            return Err(ErrorUnauthorized("Authentication token has foreign chars!"));
+        }
+
+        let secret = &req
+            .app_data::<web::Data<String>>()
+            .expect("no secret in app_data");
+
+        let token_result = decode::<Claims>(
+            &authentication_token,
+            &DecodingKey::from_secret(secret.as_ref().as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        );
+
+        match token_result {
+            Ok(token) => Ok(token.claims),
+            Err(_e) => Err(ErrorUnauthorized("Invalid authentication token sent!")),
+        }
+    }
+
+    pub fn db_pool_from_request(req: &HttpRequest) -> web::Data<Pool<MySql>> {
+        req.app_data::<web::Data<Pool<MySql>>>()
+            .expect("no db pool in app_data")
+            .clone()
+    }
+
+    /// a valid token isn't enough once its session is revoked or the account is banned or closed
+    pub async fn check_account(
+        db_pool: &Pool<MySql>,
+        player_id: PlayerId,
+        session_id: SessionId,
+    ) -> Result<(), ActixWebError> {
+        match sessions::is_active(db_pool, session_id, player_id).await {
+            Ok(true) => {}
+            Ok(false) => return Err(ErrorUnauthorized("Session ended")),
+            Err(_) => return Err(ErrorInternalServerError("db fail")),
+        }
+        match moderation::is_refused(db_pool, player_id).await {
+            Ok(false) => Ok(()),
+            Ok(true) => Err(ErrorForbidden("Account is banned or closed")),
+            Err(_) => Err(ErrorInternalServerError("db fail")),
+        }
+    }
+
     impl FromRequest for AuthenticationToken {
         type Error = ActixWebError;
-        type Future = Ready<Result<Self, Self::Error>>;
+        type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+        fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+            let claims = claims_from_request(req);
+            let db_pool = db_pool_from_request(req);
+
+            Box::pin(async move {
+                let claims = claims?;
+                check_account(&db_pool, claims.id, claims.sid).await?;
+                Ok(AuthenticationToken {
+                    id: claims.id,
+                    exp: claims.exp,
+                    role: claims.role,
+                    sid: claims.sid,
+                })
+            })
+        }
+    }
+}
+
+/// Authentication for the game websocket.
+/// Browsers send the cookie on upgrade, other clients get a short lived ticket
+/// from `/auth/ws_ticket` and pass it as `?ticket=`.
+pub mod ws_authentication {
+    use actix_web::{
+        dev::Payload, error::ErrorUnauthorized, web, Error as ActixWebError, FromRequest,
+        HttpRequest,
+    };
+    use futures::future::LocalBoxFuture;
+    use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+    use serde::{Deserialize, Serialize};
+
+    use super::authentication_token::{
+        check_account, claims_from_request, db_pool_from_request, AuthenticationToken, COOKIE_NAME,
+    };
+    use crate::{sessions::SessionId, PlayerId};
+
+    pub const TICKET_LIFETIME_SECONDS: i64 = 30;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct WsTicketClaims {
+        pub id: usize,
+        pub exp: usize,
+        /// session of the token the ticket was issued for, the socket is closed when it ends
+        pub sid: SessionId,
+    }
+
+    /// tickets are signed with a different key, so they can't be used as a normal token
+    fn ticket_secret(secret: &str) -> String {
+        format!("{secret}.ws-ticket")
+    }
+
+    pub fn encode_ticket(token: AuthenticationToken, secret: &str) -> String {
+        let exp = (chrono::Utc::now() + chrono::Duration::seconds(TICKET_LIFETIME_SECONDS))
+            .timestamp() as usize;
+        let claims = WsTicketClaims {
+            id: token.id,
+            exp: exp.min(token.exp),
+            sid: token.sid,
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(ticket_secret(secret).as_bytes()),
+        )
+        .expect("Couldn't make ws ticket")
+    }
+
+    #[derive(Deserialize)]
+    struct TicketQuery {
+        ticket: String,
+    }
+
+    /// The socket outlives the short access token, it stays open as long as the session does
+    #[derive(Debug, Clone, Copy)]
+    pub struct WsAuthentication {
+        pub id: PlayerId,
+        pub sid: SessionId,
+    }
+
+    fn authenticate(req: &HttpRequest) -> Result<WsAuthentication, ActixWebError> {
+        let has_cookie = req
+            .cookies()
+            .map(|cookies| cookies.iter().any(|c| c.name() == COOKIE_NAME))
+            .unwrap_or(false);
+        if has_cookie {
+            return claims_from_request(req).map(|claims| WsAuthentication {
+                id: claims.id,
+                sid: claims.sid,
+            });
+        }
+
+        let ticket = match web::Query::<TicketQuery>::from_query(req.query_string()) {
+            Ok(query) => query.into_inner().ticket,
+            Err(_) => return Err(ErrorUnauthorized("No authentication token sent!")),
+        };
+
+        let secret = &req
+            .app_data::<web::Data<String>>()
+            .expect("no secret in app_data");
+
+        let ticket_result = decode::<WsTicketClaims>(
+            &ticket,
+            &DecodingKey::from_secret(ticket_secret(secret.as_str()).as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        );
+
+        match ticket_result {
+            Ok(ticket) => Ok(WsAuthentication {
+                id: ticket.claims.id,
+                sid: ticket.claims.sid,
+            }),
+            Err(_e) => Err(ErrorUnauthorized("Invalid ws ticket sent!")),
+        }
+    }
+
+    impl FromRequest for WsAuthentication {
+        type Error = ActixWebError;
+        type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+        fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+            let auth = authenticate(req);
+            let db_pool = db_pool_from_request(req);
+
+            Box::pin(async move {
+                let auth = auth?;
+                check_account(&db_pool, auth.id, auth.sid).await?;
+                Ok(auth)
+            })
+        }
+    }
+}
+
+/// Logged in player with at least the required role.
+/// The role in the token turns ordinary players away without a db query,
+/// the db is still checked so a demotion applies right away.
+/// A promoted player gets the new role into the token with the next refresh.
+pub mod role {
+    use actix_web::{
+        dev::Payload,
+        error::{ErrorForbidden, ErrorInternalServerError},
+        Error as ActixWebError, FromRequest, HttpRequest,
+    };
+    use futures::future::LocalBoxFuture;
+    use std::marker::PhantomData;
+
+    use super::authentication_token::{check_account, claims_from_request, db_pool_from_request};
+    use crate::{
+        roles::{self, Role},
+        PlayerId,
+    };
+
+    pub trait RequiredRole {
+        const ROLE: Role;
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct ModeratorRole;
+
+    impl RequiredRole for ModeratorRole {
+        const ROLE: Role = Role::Moderator;
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct AdminRole;
+
+    impl RequiredRole for AdminRole {
+        const ROLE: Role = Role::Admin;
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct HasRole<R> {
+        pub id: PlayerId,
+        /// the player's current role, at least the required one
+        pub role: Role,
+        required: PhantomData<R>,
+    }
+
+    /// moderators and admins
+    pub type Moderator = HasRole<ModeratorRole>;
+    pub type Admin = HasRole<AdminRole>;
+
+    fn forbidden(required: Role) -> ActixWebError {
+        ErrorForbidden(format!("Only a {} can do this", required.as_str()))
+    }
+
+    impl<R: RequiredRole + 'static> FromRequest for HasRole<R> {
+        type Error = ActixWebError;
+        type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
 
         fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
-            let req = req.clone();
-
-            let authentication_token = match req
-                .cookies()
-                .expect("Panicked on getting cookies from request")
-                .iter()
-                .filter(|c| c.name() == COOKIE_NAME)
-                .next()
-            {
-                Some(cookie) => cookie.value().to_string(),
-                None => return ready(Err(ErrorUnauthorized("No authentication token sent!"))),
-            };
-
-            if authentication_token.is_empty() {
-                return ready(Err(ErrorUnauthorized(
-                    "Authentication token has foreign chars!",
-                )));
-            }
-
-            let secret = &req
-                .app_data::<web::Data<String>>()
-                .expect("no secret in app_data");
-
-            let token_result = decode::<Claims>(
-                &authentication_token,
-                &DecodingKey::from_secret(secret.as_ref().as_bytes()),
-                &Validation::new(Algorithm::HS256),
-            );
-
-            match token_result {
-                Ok(token) => ready(Ok(AuthenticationToken {
-                    id: token.claims.id,
-                })),
-                Err(_e) => ready(Err(ErrorUnauthorized("Invalid authentication token sent!"))),
-            }
+            let claims = claims_from_request(req);
+            let db_pool = db_pool_from_request(req);
+
+            Box::pin(async move {
+                let claims = claims?;
+                if claims.role < R::ROLE {
+                    return Err(forbidden(R::ROLE));
+                }
+                check_account(&db_pool, claims.id, claims.sid).await?;
+                let role = roles::of_player(&db_pool, claims.id)
+                    .await
+                    .map_err(|_| ErrorInternalServerError("db fail"))?;
+                if role < R::ROLE {
+                    return Err(forbidden(R::ROLE));
+                }
+
+                Ok(HasRole {
+                    id: claims.id,
+                    role,
+                    required: PhantomData,
+                })
+            })
         }
     }
 }
@@ -0,0 +1,229 @@
+//! Friend requests, at most one pending request per pair of players.
+//! If both players ask each other, the second request accepts the first one.
+
+use sqlx::{MySql, MySqlConnection, Pool};
+
+use crate::{blocks, sql::PlayerData, PlayerId};
+
+pub type FriendRequestId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    Sent(FriendRequestId),
+    /// the other player already asked, so now they are friends
+    Accepted,
+    AlreadyFriends,
+    AlreadyPending,
+    /// one of them blocked the other, nothing was sent
+    Blocked,
+}
+
+pub async fn are_friends(
+    conn: &mut MySqlConnection,
+    a: PlayerId,
+    b: PlayerId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id FROM Friends WHERE user_low=LEAST(?, ?) AND user_high=GREATEST(?, ?)",
+        a as u64,
+        b as u64,
+        a as u64,
+        b as u64,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .is_some())
+}
+
+/// the unique pair makes adding the same friendship twice a no-op
+async fn add_friends(
+    conn: &mut MySqlConnection,
+    a: PlayerId,
+    b: PlayerId,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT IGNORE INTO Friends(friend1, friend2) VALUES (?, ?)",
+        a as u64,
+        b as u64,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// removes the request and its notification from the receiver's inbox
+async fn remove_request(
+    conn: &mut MySqlConnection,
+    request_id: FriendRequestId,
+    sender: PlayerId,
+    receiver: PlayerId,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM FriendRequests WHERE id=?", request_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM Notifications WHERE kind='friend_request' AND player=? AND sender=?",
+        receiver as u64,
+        sender as u64,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn send_request(
+    db_pool: &Pool<MySql>,
+    sender: PlayerId,
+    receiver: PlayerId,
+) -> Result<SendOutcome, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    if blocks::between(&mut tx, sender, receiver).await? {
+        return Ok(SendOutcome::Blocked);
+    }
+    if are_friends(&mut tx, sender, receiver).await? {
+        return Ok(SendOutcome::AlreadyFriends);
+    }
+
+    let pending = sqlx::query!(
+        "SELECT id, sender FROM FriendRequests
+        WHERE user_low=LEAST(?, ?) AND user_high=GREATEST(?, ?)
+        FOR UPDATE",
+        sender as u64,
+        receiver as u64,
+        sender as u64,
+        receiver as u64,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let outcome = match pending {
+        Some(request) if request.sender as PlayerId == sender => SendOutcome::AlreadyPending,
+        // mutual request
+        Some(request) => {
+            add_friends(&mut tx, sender, receiver).await?;
+            remove_request(&mut tx, request.id as FriendRequestId, receiver, sender).await?;
+            SendOutcome::Accepted
+        }
+        None => {
+            let request_id = sqlx::query!(
+                "INSERT INTO FriendRequests(sender, receiver) VALUES (?, ?)",
+                sender as u64,
+                receiver as u64,
+            )
+            .execute(&mut *tx)
+            .await?
+            .last_insert_id();
+            SendOutcome::Sent(request_id)
+        }
+    };
+
+    tx.commit().await?;
+    Ok(outcome)
+}
+
+/// only the receiver can answer, returns the sender if the request existed
+pub async fn answer_request(
+    db_pool: &Pool<MySql>,
+    request_id: FriendRequestId,
+    receiver: PlayerId,
+    accept: bool,
+) -> Result<Option<PlayerId>, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    let sender = match sqlx::query!(
+        "SELECT sender FROM FriendRequests WHERE id=? AND receiver=? FOR UPDATE",
+        request_id,
+        receiver as u64,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    {
+        Some(request) => request.sender as PlayerId,
+        None => return Ok(None),
+    };
+
+    if accept {
+        add_friends(&mut tx, sender, receiver).await?;
+    }
+    remove_request(&mut tx, request_id, sender, receiver).await?;
+
+    tx.commit().await?;
+    Ok(Some(sender))
+}
+
+/// only the sender can cancel, returns the receiver if the request existed
+pub async fn cancel_request(
+    db_pool: &Pool<MySql>,
+    request_id: FriendRequestId,
+    sender: PlayerId,
+) -> Result<Option<PlayerId>, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    let receiver = match sqlx::query!(
+        "SELECT receiver FROM FriendRequests WHERE id=? AND sender=? FOR UPDATE",
+        request_id,
+        sender as u64,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    {
+        Some(request) => request.receiver as PlayerId,
+        None => return Ok(None),
+    };
+    remove_request(&mut tx, request_id, sender, receiver).await?;
+
+    tx.commit().await?;
+    Ok(Some(receiver))
+}
+
+/// (request id, the other player)
+pub type PendingRequest = (FriendRequestId, PlayerData);
+
+/// incoming and outgoing pending requests of the player
+pub async fn pending_requests(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+) -> Result<(Vec<PendingRequest>, Vec<PendingRequest>), sqlx::Error> {
+    let incoming = sqlx::query!(
+        "SELECT r.id, u.id user_id, u.username, u.country FROM FriendRequests r
+        JOIN User u ON r.sender = u.id
+        WHERE r.receiver=?
+        ORDER BY r.id",
+        player_id as u64,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|r| {
+        let user = PlayerData {
+            id: r.user_id,
+            username: r.username,
+            country: r.country,
+        };
+        (r.id as FriendRequestId, user)
+    })
+    .collect();
+
+    let outgoing = sqlx::query!(
+        "SELECT r.id, u.id user_id, u.username, u.country FROM FriendRequests r
+        JOIN User u ON r.receiver = u.id
+        WHERE r.sender=?
+        ORDER BY r.id",
+        player_id as u64,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|r| {
+        let user = PlayerData {
+            id: r.user_id,
+            username: r.username,
+            country: r.country,
+        };
+        (r.id as FriendRequestId, user)
+    })
+    .collect();
+
+    Ok((incoming, outgoing))
+}
@@ -0,0 +1,117 @@
+//! Chat of a game, saved with the game so it can be replayed or reported later.
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
+use std::collections::HashMap;
+
+use crate::PlayerId;
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ChatLine {
+    pub player: PlayerId,
+    pub text: String,
+    /// number of moves played when it was sent
+    pub ply: usize,
+    /// one of the quick messages
+    pub preset: bool,
+    /// unix timestamp
+    pub sent_at: i64,
+}
+
+impl ChatLine {
+    pub fn new(player: PlayerId, text: String, ply: usize, preset: bool) -> Self {
+        Self {
+            player,
+            text,
+            ply,
+            preset,
+            sent_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+/// `game_db_id` is the id in Games, not the GameId of the running game
+pub async fn save(
+    db_pool: &Pool<MySql>,
+    game_db_id: u64,
+    chat: &[ChatLine],
+) -> Result<(), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    for line in chat {
+        sqlx::query!(
+            "INSERT INTO GameChat(game, player, ply, text, preset, sent_at)
+            VALUES (?, ?, ?, ?, ?, FROM_UNIXTIME(?))",
+            game_db_id,
+            line.player as u64,
+            line.ply as u32,
+            line.text,
+            line.preset,
+            line.sent_at,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn of_game(db_pool: &Pool<MySql>, game_db_id: u64) -> Result<Vec<ChatLine>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT player, text, ply, preset `preset: bool`, UNIX_TIMESTAMP(sent_at) `sent_at!: i64`
+        FROM GameChat
+        WHERE game=?
+        ORDER BY id",
+        game_db_id,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|row| ChatLine {
+        player: row.player as PlayerId,
+        text: row.text,
+        ply: row.ply as usize,
+        preset: row.preset,
+        sent_at: row.sent_at,
+    })
+    .collect())
+}
+
+/// moves of a saved movetext, without the move numbers
+pub fn pgn_moves(pgn: &str) -> Vec<String> {
+    pgn.split_whitespace()
+        .filter(|token| !token.ends_with('.'))
+        .map(String::from)
+        .collect()
+}
+
+/// movetext with every chat line as a `{name: text}` comment after the move it was sent at
+pub fn pgn_with_chat(
+    moves: &[String],
+    chat: &[ChatLine],
+    names: &HashMap<PlayerId, String>,
+) -> String {
+    let comment = |line: &ChatLine| {
+        let name = names.get(&line.player).map(String::as_str).unwrap_or("?");
+        // braces would end the comment early
+        let text: String = line.text.chars().filter(|c| *c != '{' && *c != '}').collect();
+        format!("{{{name}: {text}}} ")
+    };
+
+    let mut text: String = chat.iter().filter(|l| l.ply == 0).map(comment).collect();
+    let mut after_comment = !text.is_empty();
+    for (i, mv) in moves.iter().enumerate() {
+        if i % 2 == 0 {
+            text.push_str(&format!("{}. ", (i / 2) + 1));
+        } else if after_comment {
+            // black's move needs its number again after a comment
+            text.push_str(&format!("{}... ", (i / 2) + 1));
+        }
+        text.push_str(&format!("{mv} "));
+
+        let comments: String = chat.iter().filter(|l| l.ply == i + 1).map(comment).collect();
+        after_comment = !comments.is_empty();
+        text.push_str(&comments);
+    }
+    text
+}
@@ -0,0 +1,83 @@
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use super::{
+    game_actor::{GameRequest, GameSnapshot},
+    GameOrganizer,
+};
+use crate::{
+    chess_logic::GameResult,
+    tournament::{arena::ArenaId, PairingId},
+    GameId, PlayerId,
+};
+
+/// A running game as admins see it in the list
+#[derive(Debug, Serialize)]
+pub struct LiveGame {
+    pub game_id: GameId,
+    /// white first
+    pub players: [PlayerId; 2],
+    pub singleplayer: bool,
+    pub tournament_pairing: Option<PairingId>,
+    pub arena: Option<ArenaId>,
+}
+
+impl GameOrganizer {
+    pub(super) fn live_games(&self) -> Vec<LiveGame> {
+        let mut games: Vec<LiveGame> = self
+            .current_games
+            .iter()
+            .map(|(game_id, game)| LiveGame {
+                game_id: *game_id,
+                players: game.players,
+                singleplayer: game.players[0] == game.players[1],
+                tournament_pairing: game.tournament_pairing,
+                arena: game.arena,
+            })
+            .collect();
+        games.sort_by_key(|game| game.game_id);
+        games
+    }
+
+    /// the game's actor answers, the organizer doesn't wait for it
+    pub(super) async fn inspect_game(
+        &self,
+        game_id: GameId,
+        reply: oneshot::Sender<Option<GameSnapshot>>,
+    ) {
+        match self.current_games.get(&game_id) {
+            // if the game just finished the reply is dropped, which reads as None
+            Some(game) => {
+                let tx = game.tx.clone();
+                actix_rt::spawn(async move {
+                    let _ = tx.send(GameRequest::Inspect(reply)).await;
+                });
+            }
+            None => {
+                let _ = reply.send(None);
+            }
+        }
+    }
+
+    /// false if there is no such game
+    pub(super) async fn force_end(
+        &self,
+        game_id: GameId,
+        result: GameResult,
+        reason: String,
+        reply: oneshot::Sender<bool>,
+    ) {
+        let tx = match self.current_games.get(&game_id) {
+            Some(game) => game.tx.clone(),
+            None => {
+                let _ = reply.send(false);
+                return;
+            }
+        };
+        // a busy game would hold up the organizer, so wait for its mailbox in a task
+        actix_rt::spawn(async move {
+            let sent = tx.send(GameRequest::ForceEnd(result, reason)).await.is_ok();
+            let _ = reply.send(sent);
+        });
+    }
+}
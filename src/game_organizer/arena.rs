@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+use super::{
+    game_actor::{GameHandle, GameOutcome},
+    GameOrganizer, GameOrganizerRequest,
+};
+use crate::{
+    api::{
+        game_ws::{NewGameOptions, SingleplayerMultiplayer},
+        protocol::{ErrorCode, Responder, ServerEnvelope, ServerMessage},
+    },
+    tournament::arena::{self, Arena, ArenaId},
+    PlayerId,
+};
+
+impl GameOrganizer {
+    /// the arena already exists in db, from now on players can join
+    pub(super) fn new_arena(&mut self, arena_id: ArenaId, name: String, duration: Duration) {
+        self.add_arena(arena_id, Arena::new(name, Instant::now() + duration));
+    }
+
+    fn add_arena(&mut self, arena_id: ArenaId, arena: Arena) {
+        let ends_in = arena.ends_at.saturating_duration_since(Instant::now());
+        self.arenas.insert(arena_id, arena);
+
+        let tx = self.tx.clone();
+        actix_rt::spawn(async move {
+            tokio::time::sleep(ends_in).await;
+            let _ = tx.send(GameOrganizerRequest::ArenaEnded(arena_id)).await;
+        });
+    }
+
+    /// arenas that are still running when the server starts, their players join again
+    pub(super) async fn load_arenas(&mut self) {
+        match arena::load_running(&self.db_pool).await {
+            Ok(arenas) => {
+                for (arena_id, arena) in arenas {
+                    self.add_arena(arena_id, arena);
+                }
+            }
+            Err(e) => println!("Couldn't load arenas: {e}"),
+        }
+    }
+
+    pub(super) async fn arena_join(
+        &mut self,
+        player_id: PlayerId,
+        arena_id: ArenaId,
+        responder: Responder,
+    ) {
+        let arena = match self.arenas.get_mut(&arena_id) {
+            Some(arena) if !arena.is_over() => arena,
+            _ => {
+                let msg = format!("No running arena {arena_id}");
+                responder.error(ErrorCode::UnknownArena, msg).await;
+                return;
+            }
+        };
+        arena.join(player_id);
+        responder.ack().await;
+
+        self.save_arena_score(arena_id, player_id);
+        self.pair_arena(arena_id);
+        self.send_leaderboard(arena_id).await;
+    }
+
+    pub(super) async fn arena_leave(
+        &mut self,
+        player_id: PlayerId,
+        arena_id: ArenaId,
+        responder: Responder,
+    ) {
+        match self.arenas.get_mut(&arena_id) {
+            Some(arena) => arena.leave(player_id),
+            None => {
+                let msg = format!("No running arena {arena_id}");
+                responder.error(ErrorCode::UnknownArena, msg).await;
+                return;
+            }
+        }
+        responder.ack().await;
+    }
+
+    /// a player who went offline can't be paired anymore
+    pub(super) fn leave_arenas(&mut self, player_id: PlayerId) {
+        for arena in self.arenas.values_mut() {
+            arena.leave(player_id);
+        }
+    }
+
+    pub(super) async fn arena_game_finished(
+        &mut self,
+        arena_id: ArenaId,
+        game: &GameHandle,
+        outcome: GameOutcome,
+    ) {
+        let arena = match self.arenas.get_mut(&arena_id) {
+            Some(arena) => arena,
+            // games still running when the arena ends don't count
+            None => return,
+        };
+        arena.record_result(game.players, outcome.result);
+
+        for player_id in game.players {
+            self.save_arena_score(arena_id, player_id);
+        }
+        self.pair_arena(arena_id);
+        self.send_leaderboard(arena_id).await;
+    }
+
+    pub(super) async fn arena_ended(&mut self, arena_id: ArenaId) {
+        self.send_leaderboard(arena_id).await;
+        self.arenas.remove(&arena_id);
+    }
+
+    fn pair_arena(&mut self, arena_id: ArenaId) {
+        let blocks = &self.blocks;
+        let games = match self.arenas.get_mut(&arena_id) {
+            Some(arena) => arena.pair_waiting(|a, b| blocks.between(a, b)),
+            None => return,
+        };
+        let options = NewGameOptions {
+            prefered_color: None,
+            opponent: None,
+            game_type: SingleplayerMultiplayer::Multiplayer,
+        };
+        for (white, black) in games {
+            let game_id = self.start_game([white, black], options);
+            if let Some(handle) = self.current_games.get_mut(&game_id) {
+                handle.arena = Some(arena_id);
+            }
+        }
+    }
+
+    fn save_arena_score(&self, arena_id: ArenaId, player_id: PlayerId) {
+        let (score, games) = match self.arenas.get(&arena_id).and_then(|a| a.score(player_id)) {
+            Some(score) => score,
+            None => return,
+        };
+        let db_pool = self.db_pool.clone();
+        actix_rt::spawn(async move {
+            if let Err(e) = arena::save_score(&db_pool, arena_id, player_id, score, games).await {
+                println!("Couldn't save arena score: {e}");
+            }
+        });
+    }
+
+    async fn send_leaderboard(&self, arena_id: ArenaId) {
+        let arena = match self.arenas.get(&arena_id) {
+            Some(arena) => arena,
+            None => return,
+        };
+        let message = ServerMessage::ArenaLeaderboard(arena.leaderboard(arena_id));
+        let envelope = ServerEnvelope::from(message).to_ws();
+        for player_id in arena.players() {
+            self.connections.send(player_id, envelope.clone()).await;
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::Utc;
+
+use super::{game_actor::GameRequest, GameOrganizer};
+use crate::{
+    api::protocol::{ErrorCode, Responder},
+    chat_moderation::{ChatText, MAX_CHAT_LENGTH},
+    GameId, PlayerId,
+};
+
+impl GameOrganizer {
+    /// mutes that are still running when the server starts
+    pub(super) async fn load_chat_mutes(&mut self) {
+        let mutes = sqlx::query!(
+            "SELECT id, UNIX_TIMESTAMP(chat_muted_until) `muted_until!: i64` FROM User
+            WHERE chat_muted_until > CURRENT_TIMESTAMP",
+        )
+        .fetch_all(&self.db_pool)
+        .await;
+        match mutes {
+            Ok(mutes) => {
+                for mute in mutes {
+                    self.chat_mutes.insert(mute.id as PlayerId, mute.muted_until);
+                }
+            }
+            Err(e) => println!("Couldn't load chat mutes: {e}"),
+        }
+    }
+
+    /// the db is already updated, this only keeps the organizer in sync
+    pub(super) fn chat_mute(&mut self, player_id: PlayerId, until: Option<i64>) {
+        match until {
+            Some(until) => self.chat_mutes.insert(player_id, until),
+            None => self.chat_mutes.remove(&player_id),
+        };
+    }
+
+    fn is_muted(&mut self, player_id: PlayerId) -> bool {
+        match self.chat_mutes.get(&player_id) {
+            Some(until) if *until > Utc::now().timestamp() => true,
+            Some(_) => {
+                self.chat_mutes.remove(&player_id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// checks and filters the message before the game gets it
+    pub(super) async fn chat(
+        &mut self,
+        player_id: PlayerId,
+        game_id: GameId,
+        text: ChatText,
+        responder: Responder,
+    ) {
+        if self.is_muted(player_id) {
+            responder
+                .error(ErrorCode::ChatMuted, "You were muted by a moderator")
+                .await;
+            return;
+        }
+        let text = match text {
+            ChatText::Free(text) if text.chars().count() > MAX_CHAT_LENGTH => {
+                responder
+                    .error(
+                        ErrorCode::MessageTooLong,
+                        format!("Messages can have at most {MAX_CHAT_LENGTH} characters"),
+                    )
+                    .await;
+                return;
+            }
+            ChatText::Free(text) => ChatText::Free(self.word_filter.mask(&text)),
+            quick => quick,
+        };
+        // the message just never arrives
+        let blocked = match self.current_games.get(&game_id) {
+            Some(game) => self.blocks.between(game.players[0], game.players[1]),
+            None => false,
+        };
+        if blocked {
+            responder.ack().await;
+            return;
+        }
+        if !self.chat_limiter.allow(player_id) {
+            responder
+                .error(ErrorCode::ChatRateLimited, "You are sending messages too fast")
+                .await;
+            return;
+        }
+
+        self.route(player_id, game_id, responder, |r| {
+            GameRequest::Chat(player_id, text, r)
+        })
+        .await;
+    }
+}
@@ -0,0 +1,590 @@
+use futures::future::join_all;
+use serde::Serialize;
+use sqlx::{MySql, Pool};
+use std::collections::HashSet;
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+};
+
+use super::GameOrganizerRequest;
+use crate::{
+    api::{
+        game_ws::{ChessEnd, NewGameOptions},
+        protocol::{EndData, ErrorCode, InitData, Responder, ServerMessage},
+    },
+    chat_moderation::ChatText,
+    chess_logic::{ChessGame, GameResult, Position},
+    connections::Connections,
+    game_chat::{self, ChatLine},
+    sql::PlayerData,
+    tournament::{arena::ArenaId, PairingId},
+    GameId, PlayerId, WsMessageOutgoing,
+};
+
+/// Requests for a single running game, sent to its actor by the game organizer
+#[derive(Debug)]
+pub enum GameRequest {
+    Move(PlayerId, Position, Position, Responder),
+    /// already checked and filtered by the organizer
+    Chat(PlayerId, ChatText, Responder),
+    /// stop or start showing the opponent's chat to the player
+    Mute(PlayerId, bool, Responder),
+    End(PlayerId, ChessEnd, Responder),
+    /// send the whole game state to a newly opened socket
+    Init(PlayerId, mpsc::Sender<WsMessageOutgoing>),
+    /// for admins, the whole state of the game
+    Inspect(oneshot::Sender<Option<GameSnapshot>>),
+    /// ended by an admin with the given result and reason
+    ForceEnd(GameResult, String),
+}
+
+impl GameRequest {
+    /// so the request can still be answered if the game is gone
+    pub fn into_responder(self) -> Option<Responder> {
+        match self {
+            GameRequest::Move(_, _, _, responder)
+            | GameRequest::Chat(_, _, responder)
+            | GameRequest::Mute(_, _, responder)
+            | GameRequest::End(_, _, responder) => Some(responder),
+            GameRequest::Init(..) | GameRequest::Inspect(_) | GameRequest::ForceEnd(..) => None,
+        }
+    }
+}
+
+/// What the game organizer keeps about a running game, the game itself lives in its own task
+#[derive(Debug, Clone)]
+pub struct GameHandle {
+    pub players: [PlayerId; 2],
+    /// kept for the rematch
+    pub options: NewGameOptions,
+    /// set if the game was paired by a tournament
+    pub tournament_pairing: Option<PairingId>,
+    pub arena: Option<ArenaId>,
+    pub tx: mpsc::Sender<GameRequest>,
+}
+
+/// How the game ended, reported to the organizer once it's saved
+#[derive(Debug, Clone, Copy)]
+pub struct GameOutcome {
+    /// id of the row in Games, None if saving failed
+    pub db_id: Option<u64>,
+    pub result: GameResult,
+}
+
+/// State of a running game, for admins
+#[derive(Debug, Serialize)]
+pub struct GameSnapshot {
+    pub game_id: GameId,
+    /// white first
+    pub players: [PlayerData; 2],
+    pub to_move: PlayerId,
+    pub moves: Vec<String>,
+    /// who offered a draw
+    pub draw_offer: Option<PlayerId>,
+    /// everything that was sent, also what the opponent didn't see
+    pub chat: Vec<ChatLine>,
+}
+
+/// Owns one `ChessGame` and handles all of its requests in its own task,
+/// so a slow game (e.g. saving to db) doesn't stall any other game
+pub struct GameActor {
+    game: ChessGame,
+    connections: Connections,
+    organizer: mpsc::Sender<GameOrganizerRequest>,
+    db_pool: Pool<MySql>,
+    /// set once the game is over, the actor stops then
+    outcome: Option<GameOutcome>,
+    /// players who muted their opponent in this game
+    muted: HashSet<PlayerId>,
+    /// players who only want preset messages, read when the game starts
+    free_chat_off: HashSet<PlayerId>,
+}
+
+impl GameActor {
+    /// spawns a new game, if both players are the same it's a singleplayer game
+    pub fn spawn(
+        game_id: GameId,
+        players: [PlayerId; 2],
+        options: NewGameOptions,
+        connections: Connections,
+        organizer: mpsc::Sender<GameOrganizerRequest>,
+        db_pool: Pool<MySql>,
+    ) -> GameHandle {
+        let (tx, mut rx) = mpsc::channel::<GameRequest>(32);
+
+        actix_rt::spawn(async move {
+            let players_info: Vec<PlayerData> = if players[0] == players[1] {
+                vec![
+                    PlayerData::singleplayer(players[0]),
+                    PlayerData::singleplayer(players[1]),
+                ]
+            } else {
+                let loaded: Result<Vec<PlayerData>, sqlx::Error> = join_all(vec![
+                    crate::sql::get_player_data(&db_pool, players[0] as u64),
+                    crate::sql::get_player_data(&db_pool, players[1] as u64),
+                ])
+                .await
+                .into_iter()
+                .collect();
+                match loaded {
+                    Ok(players_info) => players_info,
+                    Err(e) => {
+                        println!("Couldn't load the players of game {game_id}: {e}");
+                        drop(rx);
+                        let _ = organizer
+                            .send(GameOrganizerRequest::GameAborted(game_id))
+                            .await;
+                        return;
+                    }
+                }
+            };
+
+            let mut game = ChessGame::new(players_info);
+            game.game_id = game_id;
+
+            let free_chat_off = if players[0] == players[1] {
+                HashSet::new()
+            } else {
+                sqlx::query!(
+                    "SELECT id FROM User WHERE id IN (?, ?) AND NOT free_chat",
+                    players[0] as u64,
+                    players[1] as u64,
+                )
+                .fetch_all(&db_pool)
+                .await
+                .map(|rows| rows.into_iter().map(|r| r.id as PlayerId).collect())
+                .unwrap_or_default()
+            };
+
+            let mut actor = Self {
+                game,
+                connections,
+                organizer,
+                db_pool,
+                outcome: None,
+                muted: HashSet::new(),
+                free_chat_off,
+            };
+            actor.init_players().await;
+
+            while let Some(msg) = rx.recv().await {
+                use GameRequest::*;
+                match msg {
+                    Move(p_id, from, to, responder) => {
+                        actor.r#move(p_id, from, to, responder).await;
+                    }
+                    Chat(p_id, text, responder) => actor.chat(p_id, text, responder).await,
+                    Mute(p_id, mute, responder) => {
+                        if mute {
+                            actor.muted.insert(p_id);
+                        } else {
+                            actor.muted.remove(&p_id);
+                        }
+                        responder.ack().await;
+                    }
+                    End(p_id, reason, responder) => {
+                        actor.end(p_id, reason).await;
+                        responder.ack().await;
+                    }
+                    Inspect(reply) => {
+                        let _ = reply.send(Some(actor.snapshot()));
+                    }
+                    ForceEnd(result, reason) => actor.force_end(result, reason).await,
+                    Init(p_id, channel) => {
+                        let chat = actor.visible_chat(p_id);
+                        for msg in init_chess_game(p_id, &mut actor.game, chat) {
+                            let _ = channel.send(msg).await;
+                        }
+                    }
+                }
+
+                if actor.outcome.is_some() {
+                    break;
+                }
+            }
+
+            // close the mailbox first, so the organizer can't wait on us while we wait on it
+            drop(rx);
+            let _ = actor
+                .organizer
+                .send(GameOrganizerRequest::GameFinished(game_id, actor.outcome))
+                .await;
+        });
+
+        GameHandle {
+            players,
+            options,
+            tournament_pairing: None,
+            arena: None,
+            tx,
+        }
+    }
+
+    async fn init_players(&mut self) {
+        let mut players = self.game.players.to_vec();
+        players.dedup(); // singleplayer
+        for player in players {
+            let chat = self.visible_chat(player);
+            let msgs = init_chess_game(player, &mut self.game, chat);
+            self.connections.send_all(player, &msgs).await;
+        }
+    }
+
+    pub async fn r#move(
+        &mut self,
+        player_id: PlayerId,
+        from: Position,
+        to: Position,
+        responder: Responder,
+    ) -> Option<()> {
+        let game_id = self.game.game_id;
+        let is_checkmate;
+        {
+            let game = &mut self.game;
+
+            if player_id != game.players[game.current_player_id] {
+                responder
+                    .error(ErrorCode::NotYourTurn, "It's not your turn")
+                    .await;
+                return None;
+            }
+
+            let (has_moves, move_string_representation) = match game.move_piece(from, to) {
+                Ok(s) => s,
+                Err(_) => {
+                    responder
+                        .error(ErrorCode::IllegalMove, format!("{from}-{to} is not a legal move"))
+                        .await;
+                    return None;
+                }
+            };
+            is_checkmate = !has_moves;
+            responder.ack().await;
+
+            game.current_move_data
+                .push(move_string_representation.clone());
+
+            for id in game.players {
+                // send legal moves only if you are the current player
+                let move_data = {
+                    if game.players[game.current_player_id] == id {
+                        game.get_piece_moves()
+                    } else {
+                        game.get_piece_positions()
+                    }
+                };
+
+                self.connections
+                    .send_message(id, ServerMessage::Move(move_data).in_game(game_id))
+                    .await;
+
+                let move_info = ServerMessage::MoveInfo(move_string_representation.clone());
+                self.connections
+                    .send_message(id, move_info.in_game(game_id))
+                    .await;
+            }
+            if is_checkmate {
+                for id in game.players {
+                    self.connections
+                        .send_message(
+                            id,
+                            ServerMessage::End(EndData::Checkmate {
+                                win: id == player_id,
+                            })
+                            .in_game(game_id),
+                        )
+                        .await;
+                }
+            }
+        }
+
+        // checkmate
+        if is_checkmate {
+            self.end_game("win", player_id).await;
+        }
+        Some(())
+    }
+
+    /// whether the receiver gets to see the line
+    fn can_see(&self, receiver: PlayerId, line: &ChatLine) -> bool {
+        if line.player == receiver {
+            return true;
+        }
+        if self.muted.contains(&receiver) {
+            return false;
+        }
+        line.preset || !self.free_chat_off.contains(&receiver)
+    }
+
+    /// (sent by you, text) for the init message
+    fn visible_chat(&self, player_id: PlayerId) -> Vec<(bool, String)> {
+        self.game
+            .current_chat_data
+            .iter()
+            .filter(|line| self.can_see(player_id, line))
+            .map(|line| (line.player == player_id, line.text.clone()))
+            .collect()
+    }
+
+    /// everything is kept for the record, even what the opponent doesn't see
+    pub async fn chat(&mut self, player_id: PlayerId, text: ChatText, responder: Responder) {
+        if !text.is_preset() && self.free_chat_off.contains(&player_id) {
+            responder
+                .error(ErrorCode::FreeChatDisabled, "You turned off free chat")
+                .await;
+            return;
+        }
+
+        let game_id = self.game.game_id;
+        let ply = self.game.current_move_data.len();
+        let preset = text.is_preset();
+        let line = ChatLine::new(player_id, text.into_text(), ply, preset);
+
+        for opponent_id in self.game.players.iter().filter(|p_id| **p_id != player_id) {
+            if !self.can_see(*opponent_id, &line) {
+                continue;
+            }
+            self.connections
+                .send_message(
+                    *opponent_id,
+                    ServerMessage::Chat(line.text.clone()).in_game(game_id),
+                )
+                .await;
+        }
+        self.game.current_chat_data.push(line);
+        responder.ack().await;
+    }
+
+    pub async fn end(&mut self, player_id: PlayerId, reason: ChessEnd) -> Option<()> {
+        let win;
+        {
+            let game: &mut ChessGame = &mut self.game;
+            let game_id = game.game_id;
+            match reason {
+                ChessEnd::Resign => {
+                    for id in game.players {
+                        self.connections
+                            .send_message(
+                                id,
+                                ServerMessage::End(EndData::Resign {
+                                    win: id != player_id,
+                                })
+                                .in_game(game_id),
+                            )
+                            .await;
+                    }
+                    win = "lose";
+                }
+                ChessEnd::DrawConfirm => {
+                    if let Some(id) = game.current_draw_status {
+                        if id == player_id {
+                            // the other player must accept / deny the draw
+                            return Some(());
+                        }
+                    }
+                    for id in game.players {
+                        self.connections
+                            .send_message(
+                                id,
+                                ServerMessage::End(EndData::DrawConfirm).in_game(game_id),
+                            )
+                            .await;
+                    }
+                    win = "draw";
+                }
+                ChessEnd::DrawCancel => {
+                    if let Some(id) = game.current_draw_status {
+                        if id == player_id {
+                            // the other player must accept / deny the draw
+                            return Some(());
+                        }
+                    }
+                    game.current_draw_status = None;
+
+                    for id in game.players {
+                        self.connections
+                            .send_message(
+                                id,
+                                ServerMessage::End(EndData::DrawCancel).in_game(game_id),
+                            )
+                            .await;
+                    }
+                    return Some(());
+                }
+                ChessEnd::DrawAsk => {
+                    game.current_draw_status = Some(player_id);
+                    for id in game.players {
+                        self.connections
+                            .send_message(
+                                id,
+                                ServerMessage::End(EndData::DrawAsk {
+                                    data: id != player_id,
+                                })
+                                .in_game(game_id),
+                            )
+                            .await;
+                    }
+                    return Some(());
+                }
+            }
+        }
+        self.end_game(win, player_id).await
+    }
+
+    fn snapshot(&self) -> GameSnapshot {
+        let game = &self.game;
+        GameSnapshot {
+            game_id: game.game_id,
+            players: game.players_info.clone(),
+            to_move: game.players[game.current_player_id],
+            moves: game.current_move_data.clone(),
+            draw_offer: game.current_draw_status,
+            chat: game.current_chat_data.clone(),
+        }
+    }
+
+    /// an admin ended the game, the players are told why
+    pub async fn force_end(&mut self, result: GameResult, reason: String) {
+        let game_id = self.game.game_id;
+        for id in self.game.players {
+            self.connections
+                .send_message(
+                    id,
+                    ServerMessage::End(EndData::Terminated {
+                        reason: reason.clone(),
+                        result,
+                    })
+                    .in_game(game_id),
+                )
+                .await;
+        }
+        println!("game {game_id} ended by an admin: {reason}");
+        self.save(result).await;
+    }
+
+    async fn end_game(&mut self, win: &str, player_id: PlayerId) -> Option<()> {
+        let game: &ChessGame = &self.game;
+        let result = match win {
+            "draw" => GameResult::Draw,
+            "lose" => {
+                if game.players[0] != player_id {
+                    GameResult::White
+                } else {
+                    GameResult::Black
+                }
+            }
+            "win" => {
+                if game.players[0] == player_id {
+                    GameResult::White
+                } else {
+                    GameResult::Black
+                }
+            }
+            _ => unreachable!("Status should only be win, lose or draw"),
+        };
+        self.save(result).await;
+        Some(())
+    }
+
+    /// saves the game, the actor stops after this
+    async fn save(&mut self, result: GameResult) {
+        let uuid = uuid::Uuid::new_v4();
+        let game: &ChessGame = &self.game;
+        let db_id = sqlx::query!(
+            "Insert into Games(white, black, game_file_uuid, num_of_moves, win, singleplayer)
+            values (?, ?, ?, ?, ?, ?)",
+            game.players[0] as u64,
+            game.players[1] as u64,
+            uuid.to_string(),
+            game.current_move_data.len() as u16,
+            result.as_str(),
+            game.players[0] == game.players[1],
+        )
+        .execute(&self.db_pool)
+        .await
+        .ok()
+        .map(|res| res.last_insert_id());
+        let _ = save_game(game.current_move_data.clone(), uuid).await;
+        if let Some(db_id) = db_id {
+            if let Err(e) = game_chat::save(&self.db_pool, db_id, &game.current_chat_data).await {
+                println!("Couldn't save game chat: {e}");
+            }
+        }
+        self.outcome = Some(GameOutcome { db_id, result });
+    }
+}
+
+/// messages that (re)initialize the game on the player's client
+pub fn init_chess_game(
+    player_id: PlayerId,
+    game: &mut ChessGame,
+    chat: Vec<(bool, String)>,
+) -> [WsMessageOutgoing; 2] {
+    let opponent = match game
+        .players_info
+        .iter()
+        .filter(|p| p.id as usize != player_id)
+        .next()
+        // if the players have the same id, then its singleplayer
+    {
+        Some(p) => p.clone(),
+        None => PlayerData::singleplayer(player_id),
+    };
+    let black_or_white = {
+        if game.players_info[0].id as usize == player_id {
+            "white"
+        } else {
+            "black"
+        }
+    };
+
+    let ask_draw = {
+        if let Some(id) = game.current_draw_status {
+            Some(id != player_id)
+        } else {
+            None
+        }
+    };
+
+    let init = ServerMessage::Init(InitData {
+        opponent,
+        chat,
+        moves: game.current_move_data.clone(),
+        ask_draw,
+        new_game: false,
+        playing: black_or_white.to_string(),
+    })
+    .in_game(game.game_id);
+
+    let move_data = {
+        if player_id == game.players[game.current_player_id] {
+            game.get_piece_moves()
+        } else {
+            game.get_piece_positions()
+        }
+    };
+    let moves = ServerMessage::Move(move_data).in_game(game.game_id);
+
+    [init.to_ws(), moves.to_ws()]
+}
+
+async fn save_game(moves: Vec<String>, uuid: uuid::Uuid) -> std::io::Result<()> {
+    let mut f = File::create(std::path::Path::new(&format!("../games/{}.pgn", uuid))).await?;
+    let mut text = String::new();
+    moves.into_iter().enumerate().for_each(|(i, mv)| {
+        if i % 2 == 0 {
+            text.push_str(&format!("{}. ", (i / 2) + 1));
+        }
+        text.push_str(&format!("{mv} "));
+    });
+    f.write_all(
+        text.chars()
+            .filter(|b| *b != '\0')
+            .collect::<String>()
+            .as_bytes(),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use super::{GameOrganizer, GameOrganizerRequest};
+use crate::{
+    api::{
+        game_ws::{NewGameOptions, SingleplayerMultiplayer},
+        protocol::{
+            ErrorCode, InvitationData, InvitationId, InvitationState, Responder, ServerEnvelope,
+            ServerMessage,
+        },
+    },
+    notifications::{self, NotificationPayload},
+    sql::{self, PlayerData},
+    GameId, PlayerId, WsMessageOutgoing,
+};
+
+/// how long the opponent has to answer
+const INVITATION_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// A game invitation waiting for the opponent's answer,
+/// it's also saved as the opponent's notification and has the same id
+#[derive(Debug)]
+pub struct Invitation {
+    from: PlayerData,
+    to: PlayerId,
+    options: NewGameOptions,
+    expires_at: Instant,
+}
+
+impl Invitation {
+    fn from_id(&self) -> PlayerId {
+        self.from.id as PlayerId
+    }
+
+    fn data(
+        &self,
+        invitation_id: InvitationId,
+        state: InvitationState,
+        game_id: Option<GameId>,
+    ) -> InvitationData {
+        InvitationData {
+            invitation_id,
+            from: self.from.clone(),
+            to: self.to,
+            state,
+            seconds_left: self
+                .expires_at
+                .saturating_duration_since(Instant::now())
+                .as_secs(),
+            game_id,
+        }
+    }
+}
+
+impl GameOrganizer {
+    /// player asked a specific opponent for a game,
+    /// it's saved in a task so the organizer doesn't wait for the db
+    pub(super) fn invite(
+        &mut self,
+        player_id: PlayerId,
+        opponent_id: PlayerId,
+        options: NewGameOptions,
+    ) {
+        // silently dropped, the sender doesn't find out about the block
+        if player_id == opponent_id || self.blocks.between(player_id, opponent_id) {
+            return;
+        }
+        // asking again doesn't spam the opponent
+        if self
+            .invitations
+            .values()
+            .any(|i| i.from_id() == player_id && i.to == opponent_id)
+        {
+            return;
+        }
+
+        let db_pool = self.db_pool.clone();
+        let tx = self.tx.clone();
+        actix_rt::spawn(async move {
+            let from = match sql::get_player_data(&db_pool, player_id as u64).await {
+                Ok(from) => from,
+                Err(_) => return,
+            };
+            let payload = NotificationPayload::GameInvitation { from: from.clone() };
+            let notification = notifications::create(&db_pool, opponent_id, payload).await;
+            let invitation_id = match notification {
+                Ok(notification) => notification.id,
+                Err(e) => {
+                    println!("Couldn't save invitation: {e}");
+                    return;
+                }
+            };
+            // without them a restart would bring the invitation back with the defaults
+            let saved = sqlx::query!(
+                "UPDATE Notifications SET game_options=? WHERE id=?",
+                serde_json::to_value(options).expect("Options serialization shouldn't fail"),
+                invitation_id,
+            )
+            .execute(&db_pool)
+            .await;
+            if let Err(e) = saved {
+                println!("Couldn't save invitation options: {e}");
+            }
+
+            let invitation = Invitation {
+                from,
+                to: opponent_id,
+                options,
+                expires_at: Instant::now() + INVITATION_TIMEOUT,
+            };
+            let request = GameOrganizerRequest::InvitationSaved(invitation_id, invitation);
+            let _ = tx.send(request).await;
+        });
+    }
+
+    /// the sender could have asked twice or been blocked while the invitation was saved
+    pub(super) async fn invitation_saved(
+        &mut self,
+        invitation_id: InvitationId,
+        invitation: Invitation,
+    ) {
+        let (from, to) = (invitation.from_id(), invitation.to);
+        if self.blocks.between(from, to)
+            || self
+                .invitations
+                .values()
+                .any(|i| i.from_id() == from && i.to == to)
+        {
+            self.delete_notification(invitation_id, to);
+            return;
+        }
+        self.add_invitation(invitation_id, invitation);
+        self.send_invitation(invitation_id, InvitationState::Pending, None)
+            .await;
+    }
+
+    fn add_invitation(&mut self, invitation_id: InvitationId, invitation: Invitation) {
+        let expires_in = invitation
+            .expires_at
+            .saturating_duration_since(Instant::now());
+        self.invitations.insert(invitation_id, invitation);
+
+        let tx = self.tx.clone();
+        actix_rt::spawn(async move {
+            tokio::time::sleep(expires_in).await;
+            let _ = tx
+                .send(GameOrganizerRequest::InvitationExpired(invitation_id))
+                .await;
+        });
+    }
+
+    /// invitations that didn't expire yet survive a restart, the rest are removed from the inbox
+    pub(super) async fn load_invitations(&mut self) {
+        let rows = match sqlx::query!(
+            "SELECT id, player, payload, game_options,
+            TIMESTAMPDIFF(SECOND, created_at, CURRENT_TIMESTAMP) `age!: i64`
+            FROM Notifications WHERE kind='game_invitation'"
+        )
+        .fetch_all(&self.db_pool)
+        .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                println!("Couldn't load invitations: {e}");
+                return;
+            }
+        };
+
+        for row in rows {
+            let (invitation_id, to) = (row.id as InvitationId, row.player as PlayerId);
+            let age = Duration::from_secs(row.age.max(0) as u64);
+            let from = match serde_json::from_value(row.payload) {
+                Ok(NotificationPayload::GameInvitation { from }) if age < INVITATION_TIMEOUT => {
+                    from
+                }
+                _ => {
+                    self.delete_notification(invitation_id, to);
+                    continue;
+                }
+            };
+            // invitations from before the options were saved get the defaults
+            let options = row
+                .game_options
+                .and_then(|options| serde_json::from_value(options).ok())
+                .unwrap_or(NewGameOptions {
+                    prefered_color: None,
+                    opponent: Some(to),
+                    game_type: SingleplayerMultiplayer::Multiplayer,
+                });
+            self.add_invitation(
+                invitation_id,
+                Invitation {
+                    from,
+                    to,
+                    options,
+                    expires_at: Instant::now() + (INVITATION_TIMEOUT - age),
+                },
+            );
+        }
+    }
+
+    /// only the invited player can accept or decline, only the sender can cancel
+    pub(super) async fn answer_invitation(
+        &mut self,
+        player_id: PlayerId,
+        invitation_id: InvitationId,
+        state: InvitationState,
+        responder: Responder,
+    ) {
+        let allowed = match self.invitations.get(&invitation_id) {
+            Some(invitation) => match state {
+                InvitationState::Cancelled => invitation.from_id() == player_id,
+                _ => invitation.to == player_id,
+            },
+            None => false,
+        };
+        if !allowed {
+            let msg = format!("No pending invitation {invitation_id}");
+            responder.error(ErrorCode::UnknownInvitation, msg).await;
+            return;
+        }
+        responder.ack().await;
+
+        let game_id = match state {
+            InvitationState::Accepted => {
+                let invitation = &self.invitations[&invitation_id];
+                let players = [invitation.from_id(), invitation.to];
+                let options = invitation.options;
+                Some(self.start_game(players, options))
+            }
+            _ => None,
+        };
+        self.send_invitation(invitation_id, state, game_id).await;
+        self.remove_invitation(invitation_id);
+    }
+
+    pub(super) async fn invitation_expired(&mut self, invitation_id: InvitationId) {
+        if !self.invitations.contains_key(&invitation_id) {
+            return;
+        }
+        self.send_invitation(invitation_id, InvitationState::Expired, None)
+            .await;
+        self.remove_invitation(invitation_id);
+    }
+
+    pub(super) async fn expire_invitations_between(&mut self, a: PlayerId, b: PlayerId) {
+        let between: Vec<InvitationId> = self
+            .invitations
+            .iter()
+            .filter(|(_, i)| (i.from_id() == a && i.to == b) || (i.from_id() == b && i.to == a))
+            .map(|(id, _)| *id)
+            .collect();
+        for invitation_id in between {
+            self.invitation_expired(invitation_id).await;
+        }
+    }
+
+    /// an answered invitation doesn't belong in the inbox anymore
+    fn remove_invitation(&mut self, invitation_id: InvitationId) {
+        if let Some(invitation) = self.invitations.remove(&invitation_id) {
+            self.delete_notification(invitation_id, invitation.to);
+        }
+    }
+
+    /// in a task, the organizer doesn't wait for the db
+    fn delete_notification(&self, invitation_id: InvitationId, player_id: PlayerId) {
+        let db_pool = self.db_pool.clone();
+        actix_rt::spawn(async move {
+            if let Err(e) = notifications::delete(&db_pool, invitation_id, player_id).await {
+                println!("Couldn't delete invitation: {e}");
+            }
+        });
+    }
+
+    /// pending invitations from and to the player, for a newly opened socket
+    pub(super) async fn send_pending_invitations(
+        &self,
+        player_id: PlayerId,
+        channel: &mpsc::Sender<WsMessageOutgoing>,
+    ) {
+        for (invitation_id, invitation) in self.invitations.iter() {
+            if invitation.from_id() != player_id && invitation.to != player_id {
+                continue;
+            }
+            let data = invitation.data(*invitation_id, InvitationState::Pending, None);
+            let message = ServerEnvelope::from(ServerMessage::Invitation(data));
+            let _ = channel.send(message.to_ws()).await;
+        }
+    }
+
+    /// both sides get every change of the invitation
+    async fn send_invitation(
+        &self,
+        invitation_id: InvitationId,
+        state: InvitationState,
+        game_id: Option<GameId>,
+    ) {
+        let invitation = match self.invitations.get(&invitation_id) {
+            Some(invitation) => invitation,
+            None => return,
+        };
+        for id in [invitation.from_id(), invitation.to] {
+            let data = invitation.data(invitation_id, state, game_id);
+            self.connections
+                .send_message(id, ServerMessage::Invitation(data))
+                .await;
+        }
+    }
+}
@@ -0,0 +1,457 @@
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    api::{
+        game_ws::{ChessEnd, NewGameOptions, RematchAction, SingleplayerMultiplayer},
+        protocol::{ErrorCode, InvitationId, InvitationState, Responder, ServerMessage},
+    },
+    blocks::BlockList,
+    chat_moderation::{ChatLimiter, ChatText, WordFilter},
+    chess_logic::{GameResult, Position},
+    connections::Connections,
+    presence::Presence,
+    social_organizer::SocialRequest,
+    tournament::{
+        self,
+        arena::{Arena, ArenaId},
+        TournamentGame,
+    },
+    ConnectionId, GameId, PlayerId, WsMessageOutgoing,
+};
+use sqlx::{MySql, Pool};
+
+mod admin;
+mod arena;
+mod chat;
+mod game_actor;
+mod invitation;
+mod rematch;
+
+pub use admin::LiveGame;
+pub use game_actor::GameSnapshot;
+use game_actor::{GameActor, GameHandle, GameOutcome, GameRequest};
+use invitation::Invitation;
+use rematch::FinishedGame;
+
+/// Registry of running games. Every game runs in its own actor,
+/// the organizer only routes ws messages to them and pairs players into new games.
+#[derive(Debug)]
+pub struct GameOrganizer {
+    current_games: HashMap<GameId, GameHandle>,
+    finished_games: HashMap<GameId, FinishedGame>,
+    arenas: HashMap<ArenaId, Arena>,
+    /// matchmaking queue, longest waiting first
+    waiting_players: Vec<PlayerId>,
+    blocks: BlockList,
+    connections: Connections,
+    presence: Presence,
+
+    invitations: HashMap<InvitationId, Invitation>,
+
+    chat_limiter: ChatLimiter,
+    word_filter: WordFilter,
+    /// globally muted players, until when (unix timestamp)
+    chat_mutes: HashMap<PlayerId, i64>,
+
+    social: mpsc::Sender<SocialRequest>,
+    db_pool: Pool<MySql>,
+    /// given to game actors, so they can report back when they finish
+    tx: mpsc::Sender<GameOrganizerRequest>,
+}
+
+impl GameOrganizer {
+    pub fn new(
+        db_pool: Pool<MySql>,
+        connections: Connections,
+        presence: Presence,
+        social: mpsc::Sender<SocialRequest>,
+    ) -> mpsc::Sender<GameOrganizerRequest> {
+        let (tx, mut rx) = mpsc::channel::<GameOrganizerRequest>(32);
+
+        let mut instance = Self {
+            db_pool,
+            connections,
+            presence,
+            social,
+            current_games: Default::default(),
+            finished_games: Default::default(),
+            arenas: Default::default(),
+            waiting_players: Default::default(),
+            blocks: Default::default(),
+            invitations: Default::default(),
+            chat_limiter: Default::default(),
+            word_filter: WordFilter::from_env(),
+            chat_mutes: Default::default(),
+            tx: tx.clone(),
+        };
+
+        actix_rt::spawn(async move {
+            instance.load_invitations().await;
+            instance.load_chat_mutes().await;
+            instance.load_arenas().await;
+            match BlockList::load(&instance.db_pool).await {
+                Ok(blocks) => instance.blocks = blocks,
+                Err(e) => println!("Couldn't load blocks: {e}"),
+            }
+
+            while let Some(msg) = rx.recv().await {
+                use GameOrganizerRequest::*;
+                match msg {
+                    Move(p_id, g_id, from, to, responder) => {
+                        instance
+                            .route(p_id, g_id, responder, |r| {
+                                GameRequest::Move(p_id, from, to, r)
+                            })
+                            .await;
+                    }
+                    Chat(p_id, g_id, text, responder) => {
+                        instance.chat(p_id, g_id, text, responder).await
+                    }
+                    MuteOpponent(p_id, g_id, mute, responder) => {
+                        instance
+                            .route(p_id, g_id, responder, |r| GameRequest::Mute(p_id, mute, r))
+                            .await;
+                    }
+                    ChatMute(p_id, until) => instance.chat_mute(p_id, until),
+                    Block(blocker, blocked, block) => {
+                        instance.set_block(blocker, blocked, block).await
+                    }
+                    End(p_id, g_id, reason, responder) => {
+                        instance
+                            .route(p_id, g_id, responder, |r| GameRequest::End(p_id, reason, r))
+                            .await;
+                    }
+                    NewGame(p_id, options, responder) => {
+                        instance.new_game(p_id, options).await;
+                        responder.ack().await;
+                    }
+                    Connect(p_id, c_id, channel) => instance.connect(p_id, c_id, channel).await,
+                    Close(p_id, c_id) => instance.close(p_id, c_id).await,
+                    Idle(p_id, c_id, idle, responder) => {
+                        instance.presence.set_idle(p_id, c_id, idle);
+                        instance.presence_changed(&[p_id]);
+                        responder.ack().await;
+                    }
+                    GameFinished(g_id, outcome) => instance.game_finished(g_id, outcome).await,
+                    GameAborted(g_id) => instance.game_aborted(g_id).await,
+                    StartTournamentGames(games) => instance.start_tournament_games(games),
+                    StartGame(players, options) => {
+                        instance.start_game(players, options);
+                    }
+                    Rematch(p_id, g_id, action, responder) => {
+                        instance.rematch(p_id, g_id, action, responder).await
+                    }
+                    RematchExpired(g_id) => instance.rematch_expired(g_id).await,
+                    NewArena(a_id, name, duration) => instance.new_arena(a_id, name, duration),
+                    ArenaJoin(p_id, a_id, responder) => {
+                        instance.arena_join(p_id, a_id, responder).await
+                    }
+                    ArenaLeave(p_id, a_id, responder) => {
+                        instance.arena_leave(p_id, a_id, responder).await
+                    }
+                    ArenaEnded(a_id) => instance.arena_ended(a_id).await,
+                    AnswerInvitation(p_id, i_id, state, responder) => {
+                        instance
+                            .answer_invitation(p_id, i_id, state, responder)
+                            .await
+                    }
+                    InvitationSaved(i_id, invitation) => {
+                        instance.invitation_saved(i_id, invitation).await
+                    }
+                    InvitationExpired(i_id) => instance.invitation_expired(i_id).await,
+                    ListGames(reply) => {
+                        let _ = reply.send(instance.live_games());
+                    }
+                    InspectGame(g_id, reply) => instance.inspect_game(g_id, reply).await,
+                    ForceEnd(g_id, result, reason, reply) => {
+                        instance.force_end(g_id, result, reason, reply).await
+                    }
+                }
+            }
+        });
+
+        tx
+    }
+
+    /// forwards the request to the game's actor, if the player is playing in it
+    async fn route(
+        &self,
+        player_id: PlayerId,
+        game_id: GameId,
+        responder: Responder,
+        request: impl FnOnce(Responder) -> GameRequest,
+    ) {
+        let game = match self.current_games.get(&game_id) {
+            Some(game) if game.players.contains(&player_id) => game,
+            _ => {
+                responder
+                    .error(ErrorCode::UnknownGame, format!("No game {game_id} for you"))
+                    .await;
+                return;
+            }
+        };
+        // a game that's busy saving mustn't hold up every other game, so don't wait for room.
+        // the game could also have just finished, then its mailbox is already closed
+        let (request, code, message) = match game.tx.try_send(request(responder)) {
+            Ok(()) => return,
+            Err(mpsc::error::TrySendError::Full(request)) => {
+                (request, ErrorCode::GameBusy, "Game is busy, try again")
+            }
+            Err(mpsc::error::TrySendError::Closed(request)) => {
+                (request, ErrorCode::UnknownGame, "Game already finished")
+            }
+        };
+        if let Some(responder) = request.into_responder() {
+            responder.error(code, message).await;
+        }
+    }
+
+    /// white is the first player
+    fn start_game(&mut self, players: [PlayerId; 2], options: NewGameOptions) -> GameId {
+        // finished games keep their id while a rematch can be offered
+        let game_id = loop {
+            let game_id: GameId = rand::random();
+            if !self.current_games.contains_key(&game_id)
+                && !self.finished_games.contains_key(&game_id)
+            {
+                break game_id;
+            }
+        };
+        let handle = GameActor::spawn(
+            game_id,
+            players,
+            options,
+            self.connections.clone(),
+            self.tx.clone(),
+            self.db_pool.clone(),
+        );
+        self.current_games.insert(game_id, handle);
+
+        // there's nothing to watch in a game against yourself
+        let watchable = players[0] != players[1];
+        for player_id in players {
+            self.presence.game_started(player_id, game_id, watchable);
+        }
+        self.presence_changed(&players);
+        game_id
+    }
+
+    /// the social organizer pushes the new status to friends, if it changed
+    fn presence_changed(&self, players: &[PlayerId]) {
+        let social = self.social.clone();
+        let players = players.to_vec();
+        actix_rt::spawn(async move {
+            for player_id in players {
+                let _ = social.send(SocialRequest::PresenceChanged(player_id)).await;
+            }
+        });
+    }
+
+    /// tournament and arena games can't be rematched, their result goes to the tournament instead
+    async fn game_finished(&mut self, game_id: GameId, outcome: Option<GameOutcome>) {
+        let game = match self.current_games.remove(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        for player_id in game.players {
+            self.presence.game_finished(player_id, game_id);
+        }
+        self.presence_changed(&game.players);
+
+        let outcome = match (game.tournament_pairing, game.arena, outcome) {
+            (None, None, _) => return self.keep_for_rematch(game_id, game),
+            (_, _, Some(outcome)) => outcome,
+            (_, _, None) => return,
+        };
+        if let Some(arena_id) = game.arena {
+            self.arena_game_finished(arena_id, &game, outcome).await;
+            return;
+        }
+        let pairing = match game.tournament_pairing {
+            Some(pairing) => pairing,
+            None => return,
+        };
+
+        let db_pool = self.db_pool.clone();
+        let tx = self.tx.clone();
+        actix_rt::spawn(async move {
+            match tournament::record_result(&db_pool, pairing, outcome.db_id, outcome.result).await
+            {
+                Ok(games) if !games.is_empty() => {
+                    let _ = tx.send(GameOrganizerRequest::StartTournamentGames(games)).await;
+                }
+                Ok(_) => {}
+                Err(e) => println!("Couldn't record tournament result: {e}"),
+            }
+        });
+    }
+
+    /// nothing of the game is kept, the players are told it didn't start
+    async fn game_aborted(&mut self, game_id: GameId) {
+        let game = match self.current_games.remove(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        for player_id in game.players {
+            self.presence.game_finished(player_id, game_id);
+        }
+        self.presence_changed(&game.players);
+
+        let message = ServerMessage::Error {
+            code: ErrorCode::GameAborted,
+            message: "The game couldn't be started".into(),
+        }
+        .in_game(game_id)
+        .to_ws();
+        for player_id in game.players {
+            self.connections.send(player_id, message.clone()).await;
+        }
+    }
+
+    pub fn start_tournament_games(&mut self, games: Vec<TournamentGame>) {
+        let options = NewGameOptions {
+            prefered_color: None,
+            opponent: None,
+            game_type: SingleplayerMultiplayer::Multiplayer,
+        };
+        for game in games {
+            let game_id = self.start_game([game.white, game.black], options);
+            if let Some(handle) = self.current_games.get_mut(&game_id) {
+                handle.tournament_pairing = Some(game.pairing);
+            }
+        }
+    }
+
+    pub async fn connect(
+        &mut self,
+        player_id: PlayerId,
+        connection_id: ConnectionId,
+        channel: mpsc::Sender<WsMessageOutgoing>,
+    ) {
+        self.connections
+            .insert(player_id, connection_id, channel.clone());
+
+        // only the new socket needs the current state, the others already have it
+        let _ = self
+            .social
+            .send(SocialRequest::Connect(player_id, channel.clone()))
+            .await;
+        self.send_pending_invitations(player_id, &channel).await;
+
+        for game in self.current_games.values() {
+            if !game.players.contains(&player_id) {
+                continue;
+            }
+
+            // the init can't be dropped like a move, so wait for room without holding up the organizer
+            let tx = game.tx.clone();
+            let channel = channel.clone();
+            actix_rt::spawn(async move {
+                let _ = tx.send(GameRequest::Init(player_id, channel)).await;
+            });
+        }
+    }
+
+    pub async fn new_game(&mut self, player_id: PlayerId, options: NewGameOptions) {
+        match options.game_type {
+            SingleplayerMultiplayer::Singleplayer => {
+                self.start_game([player_id, player_id], options);
+            }
+            SingleplayerMultiplayer::Multiplayer => {
+                let op_id;
+                match options.opponent {
+                    // the opponent has to accept the invitation first
+                    Some(opponent_id) => {
+                        self.invite(player_id, opponent_id, options);
+                        return;
+                    }
+                    None => {
+                        // whoever waits the longest, blocked players are never paired
+                        let opponent = self.waiting_players.iter().position(|p| {
+                            *p != player_id && !self.blocks.between(*p, player_id)
+                        });
+                        match opponent {
+                            Some(index) => op_id = self.waiting_players.remove(index),
+                            None => {
+                                if !self.waiting_players.contains(&player_id) {
+                                    self.waiting_players.push(player_id);
+                                }
+                                return;
+                            }
+                        }
+                    }
+                }
+
+                self.start_game([op_id, player_id], options);
+            }
+        }
+    }
+
+    /// the db is already updated, pending invitations between them are dropped
+    async fn set_block(&mut self, blocker: PlayerId, blocked: PlayerId, block: bool) {
+        self.blocks.set(blocker, blocked, block);
+        if block {
+            self.expire_invitations_between(blocker, blocked).await;
+        }
+    }
+
+    pub async fn close(&mut self, player_id: PlayerId, connection_id: ConnectionId) {
+        self.connections.remove(player_id, connection_id);
+        self.presence.disconnected(player_id, connection_id);
+        self.presence_changed(&[player_id]);
+        if !self.connections.is_online(player_id) {
+            self.expire_rematches_of(player_id).await;
+            self.leave_arenas(player_id);
+            self.chat_limiter.forget(player_id);
+            self.waiting_players.retain(|p| *p != player_id);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GameOrganizerRequest {
+    Move(PlayerId, GameId, Position, Position, Responder),
+    Chat(PlayerId, GameId, ChatText, Responder),
+    MuteOpponent(PlayerId, GameId, bool, Responder),
+    /// a moderator muted the player until the timestamp, or lifted the mute
+    ChatMute(PlayerId, Option<i64>),
+    /// (blocker, blocked, whether it's a block or an unblock)
+    Block(PlayerId, PlayerId, bool),
+    End(PlayerId, GameId, ChessEnd, Responder),
+    NewGame(PlayerId, NewGameOptions, Responder),
+    Connect(PlayerId, ConnectionId, mpsc::Sender<WsMessageOutgoing>),
+    Close(PlayerId, ConnectionId),
+    /// the client of this socket went idle or came back
+    Idle(PlayerId, ConnectionId, bool, Responder),
+
+    Rematch(PlayerId, GameId, RematchAction, Responder),
+
+    /// sent by a game actor after its game was saved
+    GameFinished(GameId, Option<GameOutcome>),
+    /// sent by a game actor that couldn't start its game, e.g. the db was down
+    GameAborted(GameId),
+    /// games of a new tournament round
+    StartTournamentGames(Vec<TournamentGame>),
+    /// starts a game between already matched players (e.g. an accepted challenge), white first
+    StartGame([PlayerId; 2], NewGameOptions),
+
+    /// sent once the arena is stored in db, it runs for the given duration
+    NewArena(ArenaId, String, Duration),
+    ArenaJoin(PlayerId, ArenaId, Responder),
+    ArenaLeave(PlayerId, ArenaId, Responder),
+    ArenaEnded(ArenaId),
+
+    /// accept, decline or cancel
+    AnswerInvitation(PlayerId, InvitationId, InvitationState, Responder),
+    /// sent once the invitation is in the opponent's inbox
+    InvitationSaved(InvitationId, Invitation),
+    InvitationExpired(InvitationId),
+    RematchExpired(GameId),
+
+    /// for admins
+    ListGames(oneshot::Sender<Vec<LiveGame>>),
+    InspectGame(GameId, oneshot::Sender<Option<GameSnapshot>>),
+    /// replies false if there is no such game
+    ForceEnd(GameId, GameResult, String, oneshot::Sender<bool>),
+}
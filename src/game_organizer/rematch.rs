@@ -0,0 +1,186 @@
+use std::time::{Duration, Instant};
+
+use super::{game_actor::GameHandle, GameOrganizer, GameOrganizerRequest};
+use crate::{
+    api::{
+        game_ws::{NewGameOptions, RematchAction},
+        protocol::{ErrorCode, RematchData, Responder, ServerMessage},
+    },
+    GameId, PlayerId,
+};
+
+/// how long after the game (or after the offer) a rematch can still be accepted
+const REMATCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A finished game whose players can still ask for a rematch
+#[derive(Debug)]
+pub struct FinishedGame {
+    players: [PlayerId; 2],
+    options: NewGameOptions,
+    /// who offered the rematch
+    rematch_offer: Option<PlayerId>,
+    expires_at: Instant,
+}
+
+impl GameOrganizer {
+    /// keeps a finished casual game around, so its players can ask for a rematch
+    pub(super) fn keep_for_rematch(&mut self, game_id: GameId, game: GameHandle) {
+        // nobody to play the rematch with if one of the players already left
+        if !game.players.iter().all(|p| self.connections.is_online(*p)) {
+            return;
+        }
+        if self.blocks.between(game.players[0], game.players[1]) {
+            return;
+        }
+
+        self.finished_games.insert(
+            game_id,
+            FinishedGame {
+                players: game.players,
+                options: game.options,
+                rematch_offer: None,
+                expires_at: Instant::now() + REMATCH_TIMEOUT,
+            },
+        );
+        self.schedule_rematch_expiry(game_id);
+    }
+
+    fn schedule_rematch_expiry(&self, game_id: GameId) {
+        let tx = self.tx.clone();
+        actix_rt::spawn(async move {
+            tokio::time::sleep(REMATCH_TIMEOUT).await;
+            let _ = tx.send(GameOrganizerRequest::RematchExpired(game_id)).await;
+        });
+    }
+
+    pub async fn rematch(
+        &mut self,
+        player_id: PlayerId,
+        game_id: GameId,
+        action: RematchAction,
+        responder: Responder,
+    ) {
+        let (players, offer) = match self.finished_games.get(&game_id) {
+            Some(game) if game.players.contains(&player_id) => (game.players, game.rematch_offer),
+            _ => {
+                let msg = format!("No finished game {game_id} to rematch");
+                responder.error(ErrorCode::UnknownGame, msg).await;
+                return;
+            }
+        };
+        // blocked after the game, the offer never reaches the other player
+        if self.blocks.between(players[0], players[1]) {
+            responder.ack().await;
+            return;
+        }
+        let singleplayer = players[0] == players[1];
+        let offered_by_opponent = matches!(offer, Some(by) if by != player_id);
+
+        match action {
+            // both offering is the same as accepting
+            RematchAction::Offer if singleplayer || offered_by_opponent => {
+                self.start_rematch(game_id).await;
+            }
+            RematchAction::Offer => {
+                if offer.is_none() {
+                    let game = self
+                        .finished_games
+                        .get_mut(&game_id)
+                        .expect("finished game was just found");
+                    game.rematch_offer = Some(player_id);
+                    game.expires_at = Instant::now() + REMATCH_TIMEOUT;
+                    self.schedule_rematch_expiry(game_id);
+
+                    for id in players {
+                        self.connections
+                            .send_message(
+                                id,
+                                ServerMessage::Rematch(RematchData::Offer {
+                                    by_you: id == player_id,
+                                })
+                                .in_game(game_id),
+                            )
+                            .await;
+                    }
+                }
+            }
+            RematchAction::Accept if offered_by_opponent => {
+                self.start_rematch(game_id).await;
+            }
+            RematchAction::Decline if offered_by_opponent => {
+                self.finished_games.remove(&game_id);
+                for id in players {
+                    let declined = ServerMessage::Rematch(RematchData::Declined);
+                    self.connections
+                        .send_message(id, declined.in_game(game_id))
+                        .await;
+                }
+            }
+            RematchAction::Accept | RematchAction::Decline => {
+                responder
+                    .error(ErrorCode::NoRematchOffer, "Opponent didn't offer a rematch")
+                    .await;
+                return;
+            }
+        }
+        responder.ack().await;
+    }
+
+    /// starts the new game with swapped colours and the same options
+    async fn start_rematch(&mut self, game_id: GameId) {
+        let game = match self.finished_games.remove(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        let new_game_id = self.start_game([game.players[1], game.players[0]], game.options);
+
+        let mut players = game.players.to_vec();
+        players.dedup(); // singleplayer
+        for id in players {
+            self.connections
+                .send_message(
+                    id,
+                    ServerMessage::Rematch(RematchData::Accepted { new_game_id }).in_game(game_id),
+                )
+                .await;
+        }
+    }
+
+    pub(super) async fn rematch_expired(&mut self, game_id: GameId) {
+        match self.finished_games.get(&game_id) {
+            // the offer was renewed after this timer started
+            Some(game) if Instant::now() < game.expires_at => return,
+            Some(_) => {}
+            None => return,
+        }
+        self.expire_rematch(game_id).await;
+    }
+
+    /// a player who leaves can't play a rematch anymore
+    pub(super) async fn expire_rematches_of(&mut self, player_id: PlayerId) {
+        let game_ids: Vec<GameId> = self
+            .finished_games
+            .iter()
+            .filter(|(_, game)| game.players.contains(&player_id))
+            .map(|(game_id, _)| *game_id)
+            .collect();
+        for game_id in game_ids {
+            self.expire_rematch(game_id).await;
+        }
+    }
+
+    async fn expire_rematch(&mut self, game_id: GameId) {
+        let game = match self.finished_games.remove(&game_id) {
+            Some(game) => game,
+            None => return,
+        };
+        if game.rematch_offer.is_none() {
+            return;
+        }
+        for id in game.players {
+            self.connections
+                .send_message(id, ServerMessage::Rematch(RematchData::Expired).in_game(game_id))
+                .await;
+        }
+    }
+}
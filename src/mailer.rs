@@ -0,0 +1,122 @@
+//! Sending mail. SMTP when `SMTP_HOST` is set, otherwise mails are written to files
+//! in `MAIL_DIR` so links can be followed locally.
+
+use std::{path::PathBuf, sync::Arc};
+
+use actix_web::web;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, Message, SmtpTransport,
+    Transport,
+};
+
+const DEFAULT_MAIL_DIR: &str = "../mail/";
+const DEFAULT_APP_URL: &str = "http://localhost:3000";
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// blocking, handlers should use `send_in_background`
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> anyhow::Result<()>;
+}
+
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, credentials: Option<Credentials>, from: &str) -> anyhow::Result<Self> {
+        let mut transport = SmtpTransport::relay(host)?;
+        if let Some(credentials) = credentials {
+            transport = transport.credentials(credentials);
+        }
+        Ok(Self {
+            transport: transport.build(),
+            from: from.parse()?,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse()?)
+            .subject(mail.subject.clone())
+            .body(mail.body.clone())?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
+
+/// for local development and tests, one file per mail
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> anyhow::Result<()> {
+        let path = self.dir.join(format!(
+            "{}-{}.txt",
+            chrono::Utc::now().timestamp_millis(),
+            uuid::Uuid::new_v4()
+        ));
+        std::fs::write(
+            &path,
+            format!("To: {}\nSubject: {}\n\n{}\n", mail.to, mail.subject, mail.body),
+        )?;
+        println!("mail \"{}\" written to {}", mail.subject, path.display());
+        Ok(())
+    }
+}
+
+pub fn from_env() -> Arc<dyn Mailer> {
+    match std::env::var("SMTP_HOST") {
+        Ok(host) => {
+            let username = std::env::var("SMTP_USERNAME");
+            let password = std::env::var("SMTP_PASSWORD");
+            let credentials = match (username, password) {
+                (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+                _ => None,
+            };
+            let from = std::env::var("MAIL_FROM").expect("No MAIL_FROM found in .env");
+            Arc::new(SmtpMailer::new(&host, credentials, &from).expect("Couldnt set up smtp"))
+        }
+        Err(_) => {
+            let dir = std::env::var("MAIL_DIR").unwrap_or_else(|_| DEFAULT_MAIL_DIR.to_string());
+            println!("No SMTP_HOST, mails are written to {dir}");
+            Arc::new(FileMailer::new(dir).expect("Couldnt make the mail dir"))
+        }
+    }
+}
+
+/// link to a page of the frontend, `APP_URL` in .env
+pub fn link(path: &str) -> String {
+    let base = std::env::var("APP_URL").unwrap_or_else(|_| DEFAULT_APP_URL.to_string());
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
+
+/// the request doesn't wait for the mail server, failures are only logged
+pub fn send_in_background(mailer: Arc<dyn Mailer>, mail: Mail) {
+    actix_rt::spawn(async move {
+        let subject = mail.subject.clone();
+        match web::block(move || mailer.send(&mail)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("Couldn't send mail \"{subject}\": {e}"),
+            Err(e) => println!("Couldn't send mail \"{subject}\": {e}"),
+        }
+    });
+}
@@ -1,66 +1,119 @@
-use actix_cors::Cors;
-use actix_web::{
-    middleware::Logger,
-    web::{self, Data},
-    App, HttpServer,
-};
-use dotenv::dotenv;
-use sqlx::mysql::MySqlPoolOptions;
-
-mod api;
-use api::{auth, game_ws, healthcheck, social};
-
-// delet
-mod slike_za_word;
-
-mod chess_logic;
-mod extractors;
-mod game_organizer;
-mod sql;
-
-pub type PlayerId = usize;
-pub type GameId = u32;
-pub type WsMessageOutgoing = String;
-
-#[actix_rt::main]
-async fn main() -> std::io::Result<()> {
-    println!("server starting");
-    dotenv().ok();
-
-    std::fs::create_dir_all("../games/")?;
-
-    let db_pool = MySqlPoolOptions::new()
-        .max_connections(5)
-        .connect(&std::env::var("DATABASE_URL").expect("No DATABASE_URL found in .env"))
-        .await
-        .expect("Couldnt make db pool");
-
-    // let board: Board = Board::from_fen("8/8/8/4R3/8/8/8/8 w QKqk - 0 0").unwrap();
-    // let piece = board.get(Position::new(4, 3));
-    // println!("{:?}", piece);
-    // if let Some(piece) = piece {
-    //     let moves = piece.get_moves();
-    //     println!("moves: {:?}", moves);
-    // }
-
-    let game_organizer = Data::new(game_organizer::GameOrganizer::new(db_pool.clone()));
-
-    HttpServer::new(move || {
-        App::new()
-            .wrap(Cors::permissive())
-            .wrap(Logger::default())
-            .app_data(Data::new(
-                std::env::var("JWT_TOKEN_SECRET").expect("No JWT_TOKEN_SECRET found in .env"),
-            ))
-            .app_data(Data::new(db_pool.clone()))
-            .app_data(game_organizer.clone())
-            .service(auth::login_scope())
-            .service(social::social_scope())
-            .route("/healthcheck", web::get().to(healthcheck))
-            .route("/game/ws/{id}", web::get().to(game_ws::game_ws))
-    })
-    .bind(("0.0.0.0", 5678))?
-    .run()
-    .await?;
-    Ok(())
-}
+use actix_cors::Cors;
+use actix_web::{
+    middleware::Logger,
+    web::{self, Data},
+    App, HttpServer,
+};
+use dotenv::dotenv;
+use sqlx::mysql::MySqlPoolOptions;
+
+mod accounts;
+mod api;
+use api::{auth, game_ws, healthcheck, social};
+use connections::Connections;
+use presence::Presence;
+
+// delet
+mod slike_za_word;
+
+mod blocks;
+mod chat_moderation;
+mod chess_logic;
+mod connections;
+mod direct_messages;
+mod email_tokens;
+mod extractors;
+mod friends;
+mod game_chat;
+mod game_organizer;
+mod mailer;
+mod moderation;
+mod notifications;
+mod presence;
+mod rate_limit;
+mod roles;
+mod sessions;
+mod social_organizer;
+mod sql;
+mod totp;
+mod tournament;
+mod two_factor;
+
+pub type PlayerId = usize;
+pub type GameId = u32;
+pub type ConnectionId = u64;
+pub type WsMessageOutgoing = String;
+
+#[actix_rt::main]
+async fn main() -> std::io::Result<()> {
+    println!("server starting");
+    dotenv().ok();
+
+    std::fs::create_dir_all("../games/")?;
+
+    let db_pool = MySqlPoolOptions::new()
+        .max_connections(5)
+        .connect(&std::env::var("DATABASE_URL").expect("No DATABASE_URL found in .env"))
+        .await
+        .expect("Couldnt make db pool");
+
+    // let board: Board = Board::from_fen("8/8/8/4R3/8/8/8/8 w QKqk - 0 0").unwrap();
+    // let piece = board.get(Position::new(4, 3));
+    // println!("{:?}", piece);
+    // if let Some(piece) = piece {
+    //     let moves = piece.get_moves();
+    //     println!("moves: {:?}", moves);
+    // }
+
+    let connections = Connections::default();
+    let presence = Presence::new(connections.clone());
+    let social_organizer = social_organizer::SocialOrganizer::new(
+        db_pool.clone(),
+        connections.clone(),
+        presence.clone(),
+    );
+    let game_organizer = Data::new(game_organizer::GameOrganizer::new(
+        db_pool.clone(),
+        connections.clone(),
+        presence.clone(),
+        social_organizer.clone(),
+    ));
+    let connections = Data::new(connections);
+    let mailer = Data::from(mailer::from_env());
+    let auth_limiters = Data::new(auth::AuthLimiters::new());
+    let presence = Data::new(presence);
+    let social_organizer = Data::new(social_organizer);
+
+    HttpServer::new(move || {
+        App::new()
+            .wrap(Cors::permissive())
+            .wrap(Logger::default())
+            .app_data(Data::new(
+                std::env::var("JWT_TOKEN_SECRET").expect("No JWT_TOKEN_SECRET found in .env"),
+            ))
+            .app_data(Data::new(db_pool.clone()))
+            .app_data(game_organizer.clone())
+            .app_data(social_organizer.clone())
+            .app_data(presence.clone())
+            .app_data(connections.clone())
+            .app_data(mailer.clone())
+            .app_data(auth_limiters.clone())
+            .service(auth::login_scope())
+            .service(social::social_scope())
+            .service(api::tournament::tournament_scope())
+            .service(api::arena::arena_scope())
+            .service(api::challenge::challenge_scope())
+            .service(api::notifications::notifications_scope())
+            .service(api::messages::messages_scope())
+            .service(api::moderation::moderation_scope())
+            .service(api::reports::reports_scope())
+            .service(api::admin::admin_scope())
+            .route("/healthcheck", web::get().to(healthcheck))
+            .route("/game/protocol", web::get().to(game_ws::get_protocol_schema))
+            .route("/game/ws", web::get().to(game_ws::game_ws))
+    })
+    .bind(("0.0.0.0", 5678))?
+    .run()
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Reports from players and what moderators do about them.
+//! Every moderator action is recorded in ModerationActions, the audit trail.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, MySqlConnection, Pool};
+
+use crate::PlayerId;
+
+pub type ReportId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportReason {
+    Cheating,
+    Abuse,
+    Sandbagging,
+    Other,
+}
+
+impl ReportReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportReason::Cheating => "cheating",
+            ReportReason::Abuse => "abuse",
+            ReportReason::Sandbagging => "sandbagging",
+            ReportReason::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Open,
+    /// a moderator took it
+    Triaged,
+    Resolved,
+}
+
+impl ReportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Triaged => "triaged",
+            ReportStatus::Resolved => "resolved",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    Triage,
+    /// resolve a report without doing anything
+    Dismiss,
+    Warn,
+    Mute,
+    Unmute,
+    Ban,
+    Unban,
+    CloseAccount,
+    /// admins only, see the /admin scope
+    Disable,
+    Enable,
+    SetRole,
+    RequireTwoFactor,
+    ResetTwoFactor,
+}
+
+impl ModerationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationAction::Triage => "triage",
+            ModerationAction::Dismiss => "dismiss",
+            ModerationAction::Warn => "warn",
+            ModerationAction::Mute => "mute",
+            ModerationAction::Unmute => "unmute",
+            ModerationAction::Ban => "ban",
+            ModerationAction::Unban => "unban",
+            ModerationAction::CloseAccount => "close_account",
+            ModerationAction::Disable => "disable",
+            ModerationAction::Enable => "enable",
+            ModerationAction::SetRole => "set_role",
+            ModerationAction::RequireTwoFactor => "require_two_factor",
+            ModerationAction::ResetTwoFactor => "reset_two_factor",
+        }
+    }
+}
+
+/// adds the action to the audit trail
+pub async fn record_action(
+    conn: &mut MySqlConnection,
+    moderator: PlayerId,
+    player: PlayerId,
+    report: Option<ReportId>,
+    action: ModerationAction,
+    note: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO ModerationActions(moderator, player, report, action, note)
+        VALUES (?, ?, ?, ?, ?)",
+        moderator as u64,
+        player as u64,
+        report,
+        action.as_str(),
+        note,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// banned, disabled and closed accounts can't use the site, even with a valid token
+pub async fn is_refused(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id FROM User
+        WHERE id=? AND (
+            closed_at IS NOT NULL
+            OR disabled_at IS NOT NULL
+            OR (banned AND (banned_until IS NULL OR banned_until > CURRENT_TIMESTAMP))
+        )",
+        player_id as u64,
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .is_some())
+}
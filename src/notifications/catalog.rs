@@ -0,0 +1,61 @@
+//! Texts of notifications, for clients that don't render them themselves.
+//! Parameters are inserted as plain text, a client showing them as html has to escape them.
+
+use std::collections::HashMap;
+
+use super::NotificationPayload;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// (locale, kind, template), `{name}` is replaced with the parameter `name`
+const TEMPLATES: &[(&str, &str, &str)] = &[
+    ("en", "friend_request", "Friend request from {username}"),
+    ("en", "game_invitation", "{username} invited you to a game"),
+    ("en", "moderation_warning", "Warning from the moderators: {message}"),
+    ("sl", "friend_request", "Prošnja za prijateljstvo od {username}"),
+    ("sl", "game_invitation", "{username} te vabi na igro"),
+    ("sl", "moderation_warning", "Opozorilo moderatorjev: {message}"),
+];
+
+/// supported locale closest to the requested one, e.g. `sl-SI` is `sl`
+pub fn locale(requested: Option<&str>) -> &'static str {
+    let requested = match requested {
+        Some(requested) => requested.trim().to_lowercase(),
+        None => return DEFAULT_LOCALE,
+    };
+    let language = requested.split(['-', '_']).next().unwrap_or_default();
+    TEMPLATES
+        .iter()
+        .map(|(locale, _, _)| *locale)
+        .find(|locale| *locale == language)
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+/// kind -> template, so clients can render live notifications themselves
+pub fn templates(locale: &str) -> HashMap<&'static str, &'static str> {
+    TEMPLATES
+        .iter()
+        .filter(|(l, _, _)| *l == locale)
+        .map(|(_, kind, template)| (*kind, *template))
+        .collect()
+}
+
+pub fn render(payload: &NotificationPayload, locale: &str) -> String {
+    let template = TEMPLATES
+        .iter()
+        .find(|(l, kind, _)| *l == locale && *kind == payload.kind())
+        .or_else(|| {
+            TEMPLATES
+                .iter()
+                .find(|(l, kind, _)| *l == DEFAULT_LOCALE && *kind == payload.kind())
+        })
+        .map(|(_, _, template)| *template)
+        .unwrap_or_default();
+
+    payload
+        .params()
+        .into_iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), &value)
+        })
+}
@@ -0,0 +1,234 @@
+//! Players' inbox, kept in db so it survives restarts.
+//! The ws `request` message is only the live delivery on top of it.
+//! Notifications only carry their kind and parameters, `catalog` renders them as text.
+
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::{friends::FriendRequestId, sql::PlayerData, PlayerId};
+
+pub mod catalog;
+
+pub type NotificationId = u64;
+
+/// What the notification is about, stored as json next to its kind
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NotificationPayload {
+    FriendRequest {
+        from: PlayerData,
+        request_id: FriendRequestId,
+    },
+    /// the notification id is also the invitation id
+    GameInvitation { from: PlayerData },
+    /// sent by a moderator resolving a report
+    ModerationWarning { message: String },
+}
+
+impl NotificationPayload {
+    /// same as the `kind` column
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NotificationPayload::FriendRequest { .. } => "friend_request",
+            NotificationPayload::GameInvitation { .. } => "game_invitation",
+            NotificationPayload::ModerationWarning { .. } => "moderation_warning",
+        }
+    }
+
+    /// values for the placeholders of the catalog templates
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        match (self, self.sender()) {
+            (NotificationPayload::ModerationWarning { message }, _) => {
+                vec![("message", message.clone())]
+            }
+            (_, Some(sender)) => vec![("username", sender.username.clone())],
+            (_, None) => Vec::new(),
+        }
+    }
+
+    /// None for notifications from the server
+    pub fn sender(&self) -> Option<&PlayerData> {
+        match self {
+            NotificationPayload::FriendRequest { from, .. }
+            | NotificationPayload::GameInvitation { from } => Some(from),
+            NotificationPayload::ModerationWarning { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Notification {
+    pub id: NotificationId,
+    #[serde(flatten)]
+    pub payload: NotificationPayload,
+    pub read: bool,
+    /// unix timestamp
+    pub created_at: i64,
+}
+
+struct NotificationRow {
+    id: i32,
+    payload: serde_json::Value,
+    read: bool,
+    created_at: i64,
+}
+
+impl NotificationRow {
+    /// None if the payload doesn't match any known notification
+    fn parse(self) -> Option<Notification> {
+        Some(Notification {
+            id: self.id as NotificationId,
+            payload: serde_json::from_value(self.payload).ok()?,
+            read: self.read,
+            created_at: self.created_at,
+        })
+    }
+}
+
+/// saves the notification, the returned one can be pushed live
+pub async fn create(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    payload: NotificationPayload,
+) -> Result<Notification, sqlx::Error> {
+    let json = serde_json::to_value(&payload).expect("Payload serialization shouldn't fail");
+    let id = sqlx::query!(
+        "INSERT INTO Notifications(player, sender, kind, payload) VALUES (?, ?, ?, ?)",
+        player_id as u64,
+        payload.sender().map(|s| s.id),
+        payload.kind(),
+        json,
+    )
+    .execute(db_pool)
+    .await?
+    .last_insert_id();
+
+    Ok(Notification {
+        id,
+        payload,
+        read: false,
+        created_at: Utc::now().timestamp(),
+    })
+}
+
+/// newest first, `before` is the id of the last notification of the previous page
+pub async fn list(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    before: Option<NotificationId>,
+    limit: u32,
+) -> Result<Vec<Notification>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        NotificationRow,
+        "SELECT id, payload, read_at IS NOT NULL `read!: bool`,
+        UNIX_TIMESTAMP(created_at) `created_at!: i64`
+        FROM Notifications
+        WHERE player=? AND id < ?
+        ORDER BY id DESC
+        LIMIT ?",
+        player_id as u64,
+        before.unwrap_or(u64::MAX),
+        limit,
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(rows.into_iter().filter_map(NotificationRow::parse).collect())
+}
+
+/// all notifications of one kind, oldest first
+pub async fn of_kind(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    kind: &str,
+) -> Result<Vec<Notification>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        NotificationRow,
+        "SELECT id, payload, read_at IS NOT NULL `read!: bool`,
+        UNIX_TIMESTAMP(created_at) `created_at!: i64`
+        FROM Notifications
+        WHERE player=? AND kind=?
+        ORDER BY id",
+        player_id as u64,
+        kind,
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    Ok(rows.into_iter().filter_map(NotificationRow::parse).collect())
+}
+
+/// the notification, if it belongs to the player
+pub async fn get(
+    db_pool: &Pool<MySql>,
+    notification_id: NotificationId,
+    player_id: PlayerId,
+) -> Result<Option<NotificationPayload>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT payload FROM Notifications WHERE id=? AND player=?",
+        notification_id,
+        player_id as u64,
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .and_then(|row| serde_json::from_value(row.payload).ok()))
+}
+
+pub async fn unread_count(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<i64, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT COUNT(*) count FROM Notifications WHERE player=? AND read_at IS NULL",
+        player_id as u64,
+    )
+    .fetch_one(db_pool)
+    .await?
+    .count)
+}
+
+/// marks one notification, or all of them if `notification_id` is None
+pub async fn mark_read(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    notification_id: Option<NotificationId>,
+) -> Result<u64, sqlx::Error> {
+    let res = match notification_id {
+        Some(id) => {
+            sqlx::query!(
+                "UPDATE Notifications SET read_at=CURRENT_TIMESTAMP
+                WHERE id=? AND player=? AND read_at IS NULL",
+                id,
+                player_id as u64,
+            )
+            .execute(db_pool)
+            .await?
+        }
+        None => {
+            sqlx::query!(
+                "UPDATE Notifications SET read_at=CURRENT_TIMESTAMP
+                WHERE player=? AND read_at IS NULL",
+                player_id as u64,
+            )
+            .execute(db_pool)
+            .await?
+        }
+    };
+    Ok(res.rows_affected())
+}
+
+/// dismissing a friend request or an invitation is the same as ignoring it
+pub async fn delete(
+    db_pool: &Pool<MySql>,
+    notification_id: NotificationId,
+    player_id: PlayerId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "DELETE FROM Notifications WHERE id=? AND player=?",
+        notification_id,
+        player_id as u64,
+    )
+    .execute(db_pool)
+    .await?
+    .rows_affected()
+        == 1)
+}
@@ -0,0 +1,133 @@
+//! What friends see of each other: online, idle, playing or offline.
+//! The game organizer keeps it up to date, the social organizer pushes changes to friends.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use crate::{connections::Connections, ConnectionId, GameId, PlayerId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    /// every open socket reported the player as idle
+    Idle,
+    InGame {
+        /// the game friends can watch, none for singleplayer games
+        game_id: Option<GameId>,
+    },
+    Offline,
+}
+
+#[derive(Debug, Default)]
+struct PlayerPresence {
+    /// sockets whose client reported the player as idle
+    idle: HashSet<ConnectionId>,
+    /// running games, true if friends can watch them
+    games: HashMap<GameId, bool>,
+    appear_offline: bool,
+}
+
+impl PlayerPresence {
+    fn is_default(&self) -> bool {
+        self.idle.is_empty() && self.games.is_empty() && !self.appear_offline
+    }
+}
+
+/// Shared like [`Connections`], so http handlers can read it too
+#[derive(Debug, Clone)]
+pub struct Presence {
+    connections: Connections,
+    players: Arc<Mutex<HashMap<PlayerId, PlayerPresence>>>,
+}
+
+impl Presence {
+    pub fn new(connections: Connections) -> Self {
+        Self {
+            connections,
+            players: Default::default(),
+        }
+    }
+
+    /// entries without anything set are dropped, so offline players don't pile up
+    fn update(&self, player_id: PlayerId, f: impl FnOnce(&mut PlayerPresence)) {
+        let mut players = self.players.lock().expect("Presence lock poisoned");
+        let presence = players.entry(player_id).or_default();
+        f(presence);
+        if presence.is_default() {
+            players.remove(&player_id);
+        }
+    }
+
+    pub fn set_idle(&self, player_id: PlayerId, connection_id: ConnectionId, idle: bool) {
+        self.update(player_id, |presence| {
+            if idle {
+                presence.idle.insert(connection_id);
+            } else {
+                presence.idle.remove(&connection_id);
+            }
+        });
+    }
+
+    pub fn disconnected(&self, player_id: PlayerId, connection_id: ConnectionId) {
+        self.set_idle(player_id, connection_id, false);
+    }
+
+    pub fn game_started(&self, player_id: PlayerId, game_id: GameId, watchable: bool) {
+        self.update(player_id, |presence| {
+            presence.games.insert(game_id, watchable);
+        });
+    }
+
+    pub fn game_finished(&self, player_id: PlayerId, game_id: GameId) {
+        self.update(player_id, |presence| {
+            presence.games.remove(&game_id);
+        });
+    }
+
+    pub fn set_appear_offline(&self, player_id: PlayerId, appear_offline: bool) {
+        self.update(player_id, |presence| presence.appear_offline = appear_offline);
+    }
+
+    /// playing a running game, even while offline or appearing offline
+    pub fn in_game(&self, player_id: PlayerId) -> bool {
+        self.players
+            .lock()
+            .expect("Presence lock poisoned")
+            .get(&player_id)
+            .map(|presence| !presence.games.is_empty())
+            .unwrap_or(false)
+    }
+
+    /// what friends of the player see
+    pub fn status(&self, player_id: PlayerId) -> PresenceStatus {
+        // counted before locking, the two locks are never held together
+        let connections = self.connections.count(player_id);
+        let players = self.players.lock().expect("Presence lock poisoned");
+        let presence = match players.get(&player_id) {
+            _ if connections == 0 => return PresenceStatus::Offline,
+            Some(presence) if presence.appear_offline => return PresenceStatus::Offline,
+            Some(presence) => presence,
+            None => return PresenceStatus::Online,
+        };
+
+        if !presence.games.is_empty() {
+            let game_id = presence
+                .games
+                .iter()
+                .filter(|(_, watchable)| **watchable)
+                .map(|(game_id, _)| *game_id)
+                .min();
+            return PresenceStatus::InGame { game_id };
+        }
+        if presence.idle.len() >= connections {
+            PresenceStatus::Idle
+        } else {
+            PresenceStatus::Online
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! In-process attempt limiting, e.g. for logins.
+//! Every failed attempt makes the next one wait twice as long, too many lock the key out
+//! for a while. Keys are forgotten after a quiet period, and on restart.
+//! An attempt is counted as failed when it's checked and given back if it wasn't.
+
+use actix_web::{http::header::RETRY_AFTER, HttpRequest, HttpResponse};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// entries are only swept when there are this many
+const SWEEP_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LimiterConfig {
+    /// attempts before the backoff starts
+    pub free_attempts: u32,
+    /// wait after the first attempt over the free ones, doubled with every further one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// attempts that lock the key out
+    pub lockout_after: u32,
+    pub lockout: Duration,
+    /// a key without attempts for this long starts over
+    pub forget_after: Duration,
+}
+
+#[derive(Debug)]
+struct Entry {
+    attempts: u32,
+    blocked_until: Instant,
+    last_attempt: Instant,
+}
+
+/// Shared like [`crate::connections::Connections`], clones use the same counters
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    name: &'static str,
+    config: LimiterConfig,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl Limiter {
+    /// the name is only for the logs
+    pub fn new(name: &'static str, config: LimiterConfig) -> Self {
+        Self {
+            name,
+            config,
+            entries: Default::default(),
+        }
+    }
+
+    /// reserves an attempt, it counts as failed until it's cleared or refunded.
+    /// Checking and counting under one lock means parallel requests can't all pass
+    /// before the first is counted. Err is how long the key still has to wait, nothing is counted
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let config = &self.config;
+        let mut entries = self.entries.lock().expect("Limiter lock poisoned");
+        if entries.len() >= SWEEP_THRESHOLD {
+            entries.retain(|_, entry| {
+                entry.blocked_until > now || now - entry.last_attempt < config.forget_after
+            });
+        }
+
+        let entry = entries.entry(key.to_string()).or_insert(Entry {
+            attempts: 0,
+            blocked_until: now,
+            last_attempt: now,
+        });
+        if entry.blocked_until > now {
+            return Err(entry.blocked_until - now);
+        }
+        if now - entry.last_attempt >= config.forget_after {
+            entry.attempts = 0;
+        }
+        entry.attempts += 1;
+        entry.last_attempt = now;
+        if entry.attempts == config.lockout_after {
+            println!("{}: {key} locked out", self.name);
+        }
+        entry.blocked_until = now + self.delay(entry.attempts);
+        Ok(())
+    }
+
+    /// gives back the attempt reserved by `check` when it didn't fail,
+    /// without forgetting the earlier failures like `clear`
+    pub fn refund(&self, key: &str) {
+        let mut entries = self.entries.lock().expect("Limiter lock poisoned");
+        let entry = match entries.get_mut(key) {
+            Some(entry) => entry,
+            None => return,
+        };
+        entry.attempts = entry.attempts.saturating_sub(1);
+        if entry.attempts == 0 {
+            entries.remove(key);
+        } else {
+            entry.blocked_until = entry.last_attempt + self.delay(entry.attempts);
+        }
+    }
+
+    /// the wait after this many failed attempts
+    fn delay(&self, attempts: u32) -> Duration {
+        let config = &self.config;
+        if attempts >= config.lockout_after {
+            config.lockout
+        } else if attempts > config.free_attempts {
+            let doublings = (attempts - config.free_attempts - 1).min(16);
+            (config.base_delay * 2u32.pow(doublings)).min(config.max_delay)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// e.g. after a correct password
+    pub fn clear(&self, key: &str) {
+        self.entries
+            .lock()
+            .expect("Limiter lock poisoned")
+            .remove(key);
+    }
+}
+
+/// the address of the connection, a reverse proxy in front makes every client look the same
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+/// 429 with `Retry-After`, in whole seconds rounded up
+pub fn too_many_attempts(wait: Duration) -> HttpResponse {
+    let seconds = wait.as_secs() + (wait.subsec_nanos() > 0) as u64;
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, seconds.to_string()))
+        .json(json!({"reason": "Too many attempts", "retry_after": seconds}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const CONFIG: LimiterConfig = LimiterConfig {
+        free_attempts: 2,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(6),
+        lockout_after: 8,
+        lockout: Duration::from_secs(600),
+        forget_after: Duration::from_secs(3600),
+    };
+
+    fn limiter() -> Limiter {
+        Limiter::new("test", CONFIG)
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let limiter = limiter();
+        let delays: Vec<u64> = (0..=7).map(|a| limiter.delay(a).as_secs()).collect();
+        assert_eq!(delays, vec![0, 0, 0, 1, 2, 4, 6, 6]);
+    }
+
+    #[test]
+    fn lockout_after_too_many_attempts() {
+        let limiter = limiter();
+        assert_eq!(limiter.delay(8), CONFIG.lockout);
+        assert_eq!(limiter.delay(1000), CONFIG.lockout);
+    }
+
+    #[test]
+    fn many_attempts_dont_overflow() {
+        let limiter = Limiter::new(
+            "test",
+            LimiterConfig {
+                lockout_after: u32::MAX,
+                ..CONFIG
+            },
+        );
+        assert_eq!(limiter.delay(u32::MAX - 1), CONFIG.max_delay);
+    }
+
+    #[test]
+    fn free_attempts_then_waiting() {
+        let limiter = limiter();
+        // the third attempt is allowed but makes the next one wait
+        for _ in 0..=CONFIG.free_attempts {
+            assert_eq!(limiter.check("key"), Ok(()));
+        }
+        let wait = limiter.check("key").unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= CONFIG.base_delay);
+        // other keys aren't affected
+        assert_eq!(limiter.check("other"), Ok(()));
+    }
+
+    #[test]
+    fn parallel_checks_cant_skip_the_count() {
+        let limiter = limiter();
+        let allowed: u32 = (0..32)
+            .map(|_| {
+                let limiter = limiter.clone();
+                thread::spawn(move || limiter.check("key").is_ok() as u32)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(allowed, CONFIG.free_attempts + 1);
+    }
+
+    #[test]
+    fn refund_gives_back_the_reserved_attempt() {
+        let limiter = limiter();
+        for _ in 0..=CONFIG.free_attempts {
+            limiter.check("key").unwrap();
+        }
+        limiter.refund("key");
+        // back to the free attempts, the failures before are still counted
+        assert_eq!(limiter.check("key"), Ok(()));
+        assert!(limiter.check("key").is_err());
+
+        limiter.check("once").unwrap();
+        limiter.refund("once");
+        assert!(limiter.entries.lock().unwrap().get("once").is_none());
+        // nothing to give back
+        limiter.refund("never");
+    }
+
+    #[test]
+    fn clear_forgets_the_failures() {
+        let limiter = limiter();
+        for _ in 0..=CONFIG.free_attempts {
+            limiter.check("key").unwrap();
+        }
+        limiter.clear("key");
+        for _ in 0..=CONFIG.free_attempts {
+            assert_eq!(limiter.check("key"), Ok(()));
+        }
+    }
+
+    #[test]
+    fn quiet_keys_start_over() {
+        let limiter = Limiter::new(
+            "test",
+            LimiterConfig {
+                free_attempts: 1,
+                forget_after: Duration::ZERO,
+                ..CONFIG
+            },
+        );
+        for _ in 0..10 {
+            assert_eq!(limiter.check("key"), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! What a player is allowed to do, every role can do everything the ones before it can.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, Pool};
+
+use crate::PlayerId;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    /// handles reports and chat
+    Moderator,
+    /// runs the server, see the /admin scope
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// same as the `role` column of User
+    pub fn from_db(role: &str) -> Self {
+        match role {
+            "moderator" => Role::Moderator,
+            "admin" => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+/// the role as it is now, the one in the token could be outdated
+pub async fn of_player(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<Role, sqlx::Error> {
+    let user = sqlx::query!("SELECT role FROM User WHERE id=?", player_id as u64)
+        .fetch_optional(db_pool)
+        .await?;
+    Ok(user.map(|user| Role::from_db(&user.role)).unwrap_or_default())
+}
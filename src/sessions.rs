@@ -0,0 +1,324 @@
+//! Login sessions. The access token in the cookie is short lived and names its session,
+//! the refresh token renews it and is replaced every time it's used.
+//! Only a hash of the refresh token is stored.
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, MySqlConnection, Pool};
+
+use crate::PlayerId;
+
+pub type SessionId = u64;
+
+pub const ACCESS_TOKEN_MINUTES: i64 = 15;
+/// since the last refresh
+pub const SESSION_DAYS: i64 = 30;
+const SECRET_LENGTH: usize = 43;
+/// tabs share the cookie, two of them can refresh at once with the same token
+const REUSE_GRACE_SECONDS: i64 = 30;
+
+/// `<session id>.<secret>` in the refresh cookie
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub session_id: SessionId,
+    secret: String,
+}
+
+impl RefreshToken {
+    fn generate(session_id: SessionId) -> Self {
+        let secret = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SECRET_LENGTH)
+            .map(char::from)
+            .collect();
+        Self { session_id, secret }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let (session_id, secret) = value.split_once('.')?;
+        Some(Self {
+            session_id: session_id.parse().ok()?,
+            secret: secret.to_string(),
+        })
+    }
+
+    pub fn to_cookie_value(&self) -> String {
+        format!("{}.{}", self.session_id, self.secret)
+    }
+
+    fn hash(&self) -> String {
+        format!("{:x}", Sha256::digest(self.secret.as_bytes()))
+    }
+}
+
+#[derive(Debug)]
+pub enum Refreshed {
+    /// the new refresh token and whose session it is
+    Renewed(PlayerId, RefreshToken),
+    /// the previous token came again right after it was replaced, e.g. from another tab.
+    /// The cookie already has the new one, nothing changes
+    AlreadyRenewed,
+    /// the token isn't valid (anymore)
+    Invalid,
+}
+
+/// the previous token right after it was replaced is a race between tabs, later it was stolen
+fn reuse_is_benign(seconds_since_rotation: Option<i64>) -> bool {
+    seconds_since_rotation.is_some_and(|seconds| seconds <= REUSE_GRACE_SECONDS)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: SessionId,
+    pub user_agent: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+}
+
+pub async fn create(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    user_agent: &str,
+) -> Result<RefreshToken, sqlx::Error> {
+    // the id isn't known yet, the secret doesn't depend on it
+    let mut token = RefreshToken::generate(0);
+    let user_agent: String = user_agent.chars().take(255).collect();
+    let res = sqlx::query!(
+        "INSERT INTO Sessions(player, refresh_hash, user_agent, expires_at)
+        VALUES (?, ?, ?, CURRENT_TIMESTAMP + INTERVAL ? DAY)",
+        player_id as u64,
+        token.hash(),
+        user_agent,
+        SESSION_DAYS,
+    )
+    .execute(db_pool)
+    .await?;
+    token.session_id = res.last_insert_id();
+    Ok(token)
+}
+
+/// replaces the refresh token. The token that was replaced last means it was stolen,
+/// so the whole session is revoked, unless it was replaced only moments ago.
+/// Any other wrong token changes nothing, otherwise guessing could end anyone's session
+pub async fn refresh(
+    db_pool: &Pool<MySql>,
+    token: &RefreshToken,
+) -> Result<Refreshed, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    let session = sqlx::query!(
+        "SELECT player, refresh_hash, previous_refresh_hash,
+        TIMESTAMPDIFF(SECOND, rotated_at, CURRENT_TIMESTAMP) seconds_since_rotation
+        FROM Sessions
+        WHERE id=? AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        FOR UPDATE",
+        token.session_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let session = match session {
+        Some(session) => session,
+        None => return Ok(Refreshed::Invalid),
+    };
+
+    let hash = token.hash();
+    if session.refresh_hash != hash {
+        if session.previous_refresh_hash.as_deref() != Some(hash.as_str()) {
+            return Ok(Refreshed::Invalid);
+        }
+        if reuse_is_benign(session.seconds_since_rotation) {
+            return Ok(Refreshed::AlreadyRenewed);
+        }
+        println!(
+            "refresh token of session {} reused, revoking",
+            token.session_id
+        );
+        sqlx::query!(
+            "UPDATE Sessions SET revoked_at=CURRENT_TIMESTAMP WHERE id=?",
+            token.session_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        return Ok(Refreshed::Invalid);
+    }
+
+    let new_token = RefreshToken::generate(token.session_id);
+    sqlx::query!(
+        "UPDATE Sessions
+        SET refresh_hash=?, previous_refresh_hash=?, rotated_at=CURRENT_TIMESTAMP,
+            last_used_at=CURRENT_TIMESTAMP, expires_at=CURRENT_TIMESTAMP + INTERVAL ? DAY
+        WHERE id=?",
+        new_token.hash(),
+        hash,
+        SESSION_DAYS,
+        token.session_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Refreshed::Renewed(session.player as PlayerId, new_token))
+}
+
+/// logout, only the holder of the current refresh token can end the session this way
+pub async fn end(db_pool: &Pool<MySql>, token: &RefreshToken) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "UPDATE Sessions SET revoked_at=CURRENT_TIMESTAMP
+        WHERE id=? AND refresh_hash=? AND revoked_at IS NULL",
+        token.session_id,
+        token.hash(),
+    )
+    .execute(db_pool)
+    .await?
+    .rows_affected()
+        == 1)
+}
+
+/// checked on every request, so a revoked session stops working before its access token expires
+pub async fn is_active(
+    db_pool: &Pool<MySql>,
+    session_id: SessionId,
+    player_id: PlayerId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id FROM Sessions
+        WHERE id=? AND player=? AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP",
+        session_id,
+        player_id as u64,
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .is_some())
+}
+
+/// most recently used first
+pub async fn active(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+) -> Result<Vec<Session>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT id, user_agent,
+        UNIX_TIMESTAMP(created_at) `created_at!: i64`,
+        UNIX_TIMESTAMP(last_used_at) `last_used_at!: i64`,
+        UNIX_TIMESTAMP(expires_at) `expires_at!: i64`
+        FROM Sessions
+        WHERE player=? AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        ORDER BY last_used_at DESC",
+        player_id as u64,
+    )
+    .fetch_all(db_pool)
+    .await?
+    .into_iter()
+    .map(|s| Session {
+        id: s.id as SessionId,
+        user_agent: s.user_agent,
+        created_at: s.created_at,
+        last_used_at: s.last_used_at,
+        expires_at: s.expires_at,
+    })
+    .collect())
+}
+
+/// true if there was an active session to revoke
+pub async fn revoke(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    session_id: SessionId,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        "UPDATE Sessions SET revoked_at=CURRENT_TIMESTAMP
+        WHERE id=? AND player=? AND revoked_at IS NULL",
+        session_id,
+        player_id as u64,
+    )
+    .execute(db_pool)
+    .await?
+    .rows_affected()
+        == 1)
+}
+
+/// logs out everywhere else, returns how many sessions were revoked
+pub async fn revoke_others(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    keep: SessionId,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query!(
+        "UPDATE Sessions SET revoked_at=CURRENT_TIMESTAMP
+        WHERE player=? AND id != ? AND revoked_at IS NULL",
+        player_id as u64,
+        keep,
+    )
+    .execute(db_pool)
+    .await?
+    .rows_affected())
+}
+
+/// e.g. when the password changes, in the same transaction
+pub async fn revoke_all(
+    conn: &mut MySqlConnection,
+    player_id: PlayerId,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query!(
+        "UPDATE Sessions SET revoked_at=CURRENT_TIMESTAMP WHERE player=? AND revoked_at IS NULL",
+        player_id as u64,
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie_value_round_trip() {
+        let token = RefreshToken::generate(42);
+        let parsed = RefreshToken::parse(&token.to_cookie_value()).unwrap();
+        assert_eq!(parsed.session_id, 42);
+        assert_eq!(parsed.secret, token.secret);
+    }
+
+    #[test]
+    fn generated_secrets_are_long_and_unique() {
+        let first = RefreshToken::generate(1);
+        let second = RefreshToken::generate(1);
+        assert_eq!(first.secret.len(), SECRET_LENGTH);
+        assert!(first.secret.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(first.secret, second.secret);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_values() {
+        assert!(RefreshToken::parse("").is_none());
+        assert!(RefreshToken::parse("no-dot").is_none());
+        assert!(RefreshToken::parse("abc.secret").is_none());
+        assert!(RefreshToken::parse("-1.secret").is_none());
+        // only the first dot separates, the rest belongs to the secret
+        let token = RefreshToken::parse("7.a.b").unwrap();
+        assert_eq!((token.session_id, token.secret.as_str()), (7, "a.b"));
+    }
+
+    #[test]
+    fn reuse_is_benign_only_right_after_rotation() {
+        assert!(reuse_is_benign(Some(0)));
+        assert!(reuse_is_benign(Some(REUSE_GRACE_SECONDS)));
+        assert!(!reuse_is_benign(Some(REUSE_GRACE_SECONDS + 1)));
+        // never rotated, or rotated before the column existed
+        assert!(!reuse_is_benign(None));
+    }
+
+    #[test]
+    fn hash_is_sha256_of_the_secret_only() {
+        let token = RefreshToken::parse("1.abc").unwrap();
+        assert_eq!(
+            token.hash(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // the session id isn't part of it
+        assert_eq!(RefreshToken::parse("2.abc").unwrap().hash(), token.hash());
+        assert_ne!(RefreshToken::parse("1.abd").unwrap().hash(), token.hash());
+    }
+}
@@ -0,0 +1,201 @@
+use sqlx::{MySql, Pool};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+use crate::{
+    api::protocol::{PresenceData, ServerEnvelope, ServerMessage},
+    connections::Connections,
+    direct_messages::{DirectMessage, MessageId},
+    notifications::{self, Notification, NotificationPayload},
+    presence::{Presence, PresenceStatus},
+    sql, PlayerId, WsMessageOutgoing,
+};
+
+/// Delivers notifications and friends' presence live, separate from the game organizer,
+/// so db queries for social stuff don't hold up any games
+#[derive(Debug)]
+pub struct SocialOrganizer {
+    connections: Connections,
+    presence: Presence,
+    /// last status friends were told about, offline players aren't in it
+    last_status: HashMap<PlayerId, PresenceStatus>,
+    db_pool: Pool<MySql>,
+}
+
+impl SocialOrganizer {
+    pub fn new(
+        db_pool: Pool<MySql>,
+        connections: Connections,
+        presence: Presence,
+    ) -> mpsc::Sender<SocialRequest> {
+        let mut instance = Self {
+            db_pool,
+            connections,
+            presence,
+            last_status: Default::default(),
+        };
+
+        let (tx, mut rx) = mpsc::channel::<SocialRequest>(32);
+
+        actix_rt::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                dbg!(&msg);
+                use SocialRequest::*;
+                match msg {
+                    Connect(p_id, channel) => instance.connect(p_id, channel).await,
+                    Deliver(p_id, notification) => instance.deliver(p_id, notification).await,
+                    PresenceChanged(p_id) => instance.presence_changed(p_id).await,
+                    SocialRequest::DirectMessage(message) => {
+                        instance.direct_message(message).await
+                    }
+                    MessagesRead(p_id, f_id, up_to) => {
+                        let read = ServerMessage::MessagesRead { by: p_id, up_to };
+                        instance.connections.send_message(f_id, read).await;
+                    }
+                }
+            }
+        });
+
+        tx
+    }
+
+    /// sends pending friend requests and friends' presence to a newly opened socket
+    pub async fn connect(
+        &mut self,
+        player_id: PlayerId,
+        channel: mpsc::Sender<WsMessageOutgoing>,
+    ) {
+        self.send_friends_presence(player_id, &channel).await;
+
+        match sqlx::query!(
+            "SELECT appear_offline `appear_offline!: bool` FROM User WHERE id=?",
+            player_id as u64,
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        {
+            Ok(user) => self
+                .presence
+                .set_appear_offline(player_id, user.appear_offline),
+            Err(e) => println!("Couldn't load appear_offline: {e}"),
+        }
+        self.presence_changed(player_id).await;
+
+        let pending = notifications::of_kind(&self.db_pool, player_id, "friend_request").await;
+        let pending = match pending {
+            Ok(pending) => pending,
+            Err(e) => {
+                println!("Couldn't load notifications: {e}");
+                return;
+            }
+        };
+        for notification in pending {
+            if let Some(msg) = request_message(&notification) {
+                let _ = channel.send(msg).await;
+            }
+        }
+    }
+
+    /// the notification is already saved, this is only the live push
+    pub async fn deliver(&self, player_id: PlayerId, notification: Notification) {
+        if let Some(msg) = request_message(&notification) {
+            self.connections.send(player_id, msg).await;
+        }
+    }
+
+    /// the sender's other tabs get it too, so the conversation stays in sync
+    pub async fn direct_message(&self, message: DirectMessage) {
+        let (from, to) = (message.from, message.to);
+        let msg = ServerEnvelope::from(ServerMessage::DirectMessage(message)).to_ws();
+        self.connections.send(to, msg.clone()).await;
+        self.connections.send(from, msg).await;
+    }
+
+    async fn send_friends_presence(
+        &self,
+        player_id: PlayerId,
+        channel: &mpsc::Sender<WsMessageOutgoing>,
+    ) {
+        let friends = match sql::get_friends(&self.db_pool, player_id as u64).await {
+            Ok(friends) => friends,
+            Err(e) => {
+                println!("Couldn't load friends: {e}");
+                return;
+            }
+        };
+        for friend in friends {
+            let friend_id = friend.id as PlayerId;
+            let status = self.presence.status(friend_id);
+            if status == PresenceStatus::Offline {
+                continue;
+            }
+            let _ = channel.send(presence_message(friend_id, status)).await;
+        }
+    }
+
+    /// tells online friends about the player's new status, if it changed
+    pub async fn presence_changed(&mut self, player_id: PlayerId) {
+        let status = self.presence.status(player_id);
+        let last = self
+            .last_status
+            .get(&player_id)
+            .copied()
+            .unwrap_or(PresenceStatus::Offline);
+        if status == last {
+            return;
+        }
+        if status == PresenceStatus::Offline {
+            self.last_status.remove(&player_id);
+        } else {
+            self.last_status.insert(player_id, status);
+        }
+
+        let friends = match sql::get_friends(&self.db_pool, player_id as u64).await {
+            Ok(friends) => friends,
+            Err(e) => {
+                println!("Couldn't load friends: {e}");
+                return;
+            }
+        };
+        let msg = presence_message(player_id, status);
+        for friend in friends {
+            self.connections.send(friend.id as PlayerId, msg.clone()).await;
+        }
+    }
+}
+
+fn presence_message(player_id: PlayerId, status: PresenceStatus) -> WsMessageOutgoing {
+    let presence = PresenceData {
+        player: player_id,
+        status,
+    };
+    ServerEnvelope::from(ServerMessage::Presence(presence)).to_ws()
+}
+
+/// friend requests and warnings are pushed as `request`, invitations have their own message
+fn request_message(notification: &Notification) -> Option<WsMessageOutgoing> {
+    match notification.payload {
+        NotificationPayload::FriendRequest { .. }
+        | NotificationPayload::ModerationWarning { .. } => {}
+        NotificationPayload::GameInvitation { .. } => return None,
+    }
+    let request = ServerMessage::Request(notification.clone());
+    Some(ServerEnvelope::from(request).to_ws())
+}
+
+#[derive(Debug)]
+pub enum SocialRequest {
+    /// a new socket was opened
+    Connect(PlayerId, mpsc::Sender<WsMessageOutgoing>),
+
+    /// live push of a notification that was just saved
+    Deliver(PlayerId, Notification),
+
+    /// the player's presence may have changed
+    PresenceChanged(PlayerId),
+
+    /// live push of a direct message that was just saved
+    DirectMessage(DirectMessage),
+    /// the player read the friend's messages up to the id
+    MessagesRead(PlayerId, PlayerId, MessageId),
+}
@@ -1,10 +1,11 @@
 use futures::future::Future;
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use sqlx::{MySql, Pool};
 
 use crate::PlayerId;
 
-#[derive(Debug, Clone, Serialize, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq, JsonSchema)]
 pub struct PlayerData {
     pub id: i32,
     pub username: String,
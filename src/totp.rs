@@ -0,0 +1,203 @@
+//! Time based one time passwords (RFC 6238), the codes authenticator apps show.
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+pub const DIGITS: u32 = 6;
+pub const STEP_SECONDS: i64 = 30;
+/// codes of the steps next to the current one work too, phone clocks drift
+const ALLOWED_DRIFT: i64 = 1;
+const SECRET_BYTES: usize = 20;
+const ISSUER: &str = "Chezz";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// base32, how authenticator apps take it
+pub fn generate_secret() -> String {
+    let bytes: [u8; SECRET_BYTES] = rand::random();
+    base32_encode(&bytes)
+}
+
+/// for a QR code, or typed into the app by hand
+pub fn uri(secret: &str, username: &str) -> String {
+    format!(
+        "otpauth://totp/{ISSUER}:{}?secret={secret}&issuer={ISSUER}\
+        &algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        percent_encode(username)
+    )
+}
+
+/// the time step the code belongs to.
+/// None if it's wrong, or from a step not after `last_step` so a code can't be replayed
+pub fn verify(secret: &str, code: &str, last_step: Option<i64>) -> Option<i64> {
+    verify_at(
+        secret,
+        code,
+        last_step,
+        Utc::now().timestamp() / STEP_SECONDS,
+    )
+}
+
+fn verify_at(secret: &str, code: &str, last_step: Option<i64>, now: i64) -> Option<i64> {
+    if !looks_like_code(code) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = base32_decode(secret)?;
+    (now - ALLOWED_DRIFT..=now + ALLOWED_DRIFT)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| code_at(&secret, *step) == code)
+}
+
+pub fn looks_like_code(code: &str) -> bool {
+    code.len() == DIGITS as usize && code.chars().all(|c| c.is_ascii_digit())
+}
+
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac takes keys of any length");
+    mac.update(&(step as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    // dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the secret of the RFC 6238 test vectors, "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const NOW: i64 = 1_111_111_109 / STEP_SECONDS;
+
+    fn code(step: i64) -> String {
+        let secret = base32_decode(RFC_SECRET).unwrap();
+        format!(
+            "{:0width$}",
+            code_at(&secret, step),
+            width = DIGITS as usize
+        )
+    }
+
+    #[test]
+    fn rfc_test_vectors() {
+        // the RFC has 8 digits, these are the last 6
+        let secret = base32_decode(RFC_SECRET).unwrap();
+        assert_eq!(secret, b"12345678901234567890");
+        assert_eq!(code_at(&secret, 59 / STEP_SECONDS), 287082);
+        assert_eq!(code_at(&secret, NOW), 81804);
+        assert_eq!(code_at(&secret, 1_234_567_890 / STEP_SECONDS), 5924);
+        assert_eq!(code(NOW), "081804");
+    }
+
+    #[test]
+    fn codes_next_to_now_work() {
+        for step in NOW - ALLOWED_DRIFT..=NOW + ALLOWED_DRIFT {
+            assert_eq!(verify_at(RFC_SECRET, &code(step), None, NOW), Some(step));
+        }
+        let too_old = NOW - ALLOWED_DRIFT - 1;
+        let too_new = NOW + ALLOWED_DRIFT + 1;
+        assert_eq!(verify_at(RFC_SECRET, &code(too_old), None, NOW), None);
+        assert_eq!(verify_at(RFC_SECRET, &code(too_new), None, NOW), None);
+    }
+
+    #[test]
+    fn used_codes_cant_be_replayed() {
+        assert_eq!(verify_at(RFC_SECRET, &code(NOW), Some(NOW), NOW), None);
+        // nor an older one still in the window
+        assert_eq!(verify_at(RFC_SECRET, &code(NOW - 1), Some(NOW), NOW), None);
+        assert_eq!(
+            verify_at(RFC_SECRET, &code(NOW + 1), Some(NOW), NOW),
+            Some(NOW + 1)
+        );
+        assert_eq!(
+            verify_at(RFC_SECRET, &code(NOW), Some(NOW - 1), NOW),
+            Some(NOW)
+        );
+    }
+
+    #[test]
+    fn malformed_codes_and_secrets() {
+        assert_eq!(verify_at(RFC_SECRET, "81804", None, NOW), None);
+        assert_eq!(verify_at(RFC_SECRET, "0818040", None, NOW), None);
+        assert_eq!(verify_at(RFC_SECRET, "08180a", None, NOW), None);
+        assert_eq!(verify_at("not base32!", &code(NOW), None, NOW), None);
+        assert!(looks_like_code("000000"));
+        assert!(!looks_like_code("+12345"));
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        let secret = generate_secret();
+        assert_eq!(secret.len(), 32);
+        let bytes = base32_decode(&secret).unwrap();
+        assert_eq!(bytes.len(), SECRET_BYTES);
+        assert_eq!(base32_encode(&bytes), secret);
+
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+    }
+
+    #[test]
+    fn uri_escapes_the_username() {
+        assert_eq!(
+            uri("ABC", "a b&c"),
+            "otpauth://totp/Chezz:a%20b%26c?secret=ABC&issuer=Chezz\
+            &algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
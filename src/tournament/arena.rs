@@ -0,0 +1,378 @@
+//! Arena tournaments: time-boxed, players are paired again as soon as their game ends,
+//! against whoever with a similar score is waiting.
+//! A win is 2 points and a draw 1. After two wins in a row a player is on fire and their
+//! results count double until they don't win.
+//! There's no berserk, halving your clock needs games with a clock first.
+
+use sqlx::{MySql, Pool};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api::protocol::{ArenaLeaderboard, ArenaStanding},
+    chess_logic::{GameResult, Player},
+    PlayerId,
+};
+
+pub type ArenaId = u64;
+
+#[derive(Debug)]
+pub struct Arena {
+    pub name: String,
+    pub ends_at: Instant,
+    players: HashMap<PlayerId, ArenaPlayer>,
+    /// players waiting for their next game, in the order they started waiting
+    waiting: Vec<PlayerId>,
+}
+
+#[derive(Debug, Default)]
+struct ArenaPlayer {
+    score: u32,
+    games: u32,
+    wins_in_row: u32,
+    last_opponent: Option<PlayerId>,
+    /// games with white minus games with black
+    colour_difference: i32,
+    /// false after leaving, they stay on the leaderboard but aren't paired
+    active: bool,
+}
+
+impl ArenaPlayer {
+    fn on_fire(&self) -> bool {
+        self.wins_in_row >= 2
+    }
+}
+
+impl Arena {
+    pub fn new(name: String, ends_at: Instant) -> Self {
+        Self {
+            name,
+            ends_at,
+            players: HashMap::new(),
+            waiting: Vec::new(),
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        Instant::now() >= self.ends_at
+    }
+
+    /// everybody who ever joined, they all get the leaderboard
+    pub fn players(&self) -> impl Iterator<Item = PlayerId> + '_ {
+        self.players.keys().copied()
+    }
+
+    pub fn join(&mut self, player_id: PlayerId) {
+        self.players.entry(player_id).or_default().active = true;
+        if !self.waiting.contains(&player_id) {
+            self.waiting.push(player_id);
+        }
+    }
+
+    /// a game that is already running still counts
+    pub fn leave(&mut self, player_id: PlayerId) {
+        if let Some(player) = self.players.get_mut(&player_id) {
+            player.active = false;
+        }
+        self.waiting.retain(|p| *p != player_id);
+    }
+
+    /// a score saved before a restart, the player has to join again to be paired
+    pub fn restore_score(&mut self, player_id: PlayerId, score: u32, games: u32) {
+        let player = self.players.entry(player_id).or_default();
+        player.score = score;
+        player.games = games;
+    }
+
+    pub fn score(&self, player_id: PlayerId) -> Option<(u32, u32)> {
+        self.players
+            .get(&player_id)
+            .map(|player| (player.score, player.games))
+    }
+
+    /// scores the game and puts both players back in the queue
+    pub fn record_result(&mut self, players: [PlayerId; 2], result: GameResult) {
+        for (i, colour) in [Player::White, Player::Black].iter().copied().enumerate() {
+            let player = self.players.entry(players[i]).or_default();
+            let half_points = result.half_points(colour);
+            let won = half_points == 2;
+
+            let mut points = half_points;
+            if player.on_fire() {
+                points *= 2;
+            }
+
+            player.score += points;
+            player.games += 1;
+            player.wins_in_row = if won { player.wins_in_row + 1 } else { 0 };
+            player.last_opponent = Some(players[1 - i]);
+            player.colour_difference += match colour {
+                Player::White => 1,
+                Player::Black => -1,
+            };
+
+            if player.active && !self.waiting.contains(&players[i]) {
+                self.waiting.push(players[i]);
+            }
+        }
+    }
+
+    /// pairs waiting players with similar scores, returns (white, black) of the new games.
+    /// If one player is left they keep waiting for the next game to end.
+    /// players for whom `blocked` holds are never paired, they keep waiting instead
+    pub fn pair_waiting(
+        &mut self,
+        blocked: impl Fn(PlayerId, PlayerId) -> bool,
+    ) -> Vec<(PlayerId, PlayerId)> {
+        if self.is_over() {
+            return Vec::new();
+        }
+
+        let mut waiting = std::mem::take(&mut self.waiting);
+        // stable, so with the same score whoever waits longer is paired first
+        waiting.sort_by(|a, b| self.players[b].score.cmp(&self.players[a].score));
+
+        let mut games = Vec::new();
+        let mut unpaired = Vec::new();
+        while !waiting.is_empty() {
+            let first = waiting.remove(0);
+            // don't play the same opponent twice in a row if there is somebody else
+            let opponent_index = waiting
+                .iter()
+                .position(|p| {
+                    !blocked(first, *p)
+                        && self.players[&first].last_opponent != Some(*p)
+                        && self.players[p].last_opponent != Some(first)
+                })
+                .or_else(|| waiting.iter().position(|p| !blocked(first, *p)));
+            let second = match opponent_index {
+                Some(index) => waiting.remove(index),
+                None => {
+                    unpaired.push(first);
+                    continue;
+                }
+            };
+
+            if self.players[&first].colour_difference <= self.players[&second].colour_difference {
+                games.push((first, second));
+            } else {
+                games.push((second, first));
+            }
+        }
+        self.waiting = unpaired;
+        games
+    }
+
+    pub fn leaderboard(&self, arena_id: ArenaId) -> ArenaLeaderboard {
+        let mut standings: Vec<ArenaStanding> = self
+            .players
+            .iter()
+            .map(|(id, player)| ArenaStanding {
+                player: *id,
+                score: player.score,
+                games: player.games,
+                on_fire: player.on_fire(),
+            })
+            .collect();
+        standings.sort_by(|a, b| b.score.cmp(&a.score).then(a.games.cmp(&b.games)));
+
+        ArenaLeaderboard {
+            arena_id,
+            name: self.name.clone(),
+            seconds_left: self
+                .ends_at
+                .saturating_duration_since(Instant::now())
+                .as_secs(),
+            finished: self.is_over(),
+            standings,
+        }
+    }
+}
+
+/// arenas that didn't end yet, with the scores saved so far.
+/// Streaks and last opponents aren't saved, they start over
+pub async fn load_running(db_pool: &Pool<MySql>) -> Result<Vec<(ArenaId, Arena)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT id, name, TIMESTAMPDIFF(SECOND, CURRENT_TIMESTAMP, ends_at) `seconds_left!: i64`
+        FROM Arenas WHERE ends_at > CURRENT_TIMESTAMP"
+    )
+    .fetch_all(db_pool)
+    .await?;
+
+    let mut arenas = Vec::new();
+    for row in rows {
+        let ends_at = Instant::now() + Duration::from_secs(row.seconds_left.max(0) as u64);
+        let mut arena = Arena::new(row.name, ends_at);
+        let scores = sqlx::query!(
+            "SELECT player, score, games FROM ArenaScores WHERE arena=?",
+            row.id,
+        )
+        .fetch_all(db_pool)
+        .await?;
+        for s in scores {
+            arena.restore_score(s.player as PlayerId, s.score as u32, s.games as u32);
+        }
+        arenas.push((row.id as ArenaId, arena));
+    }
+    Ok(arenas)
+}
+
+/// keeps ArenaScores up to date, so the leaderboard survives the arena
+pub async fn save_score(
+    db_pool: &Pool<MySql>,
+    arena_id: ArenaId,
+    player_id: PlayerId,
+    score: u32,
+    games: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO ArenaScores(arena, player, score, games) VALUES (?, ?, ?, ?)
+        ON DUPLICATE KEY UPDATE score=VALUES(score), games=VALUES(games)",
+        arena_id,
+        player_id as u64,
+        score,
+        games,
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arena(players: &[PlayerId]) -> Arena {
+        let mut arena = Arena::new("test".into(), Instant::now() + Duration::from_secs(60));
+        for player in players {
+            arena.join(*player);
+        }
+        arena
+    }
+
+    fn nobody_blocked(_: PlayerId, _: PlayerId) -> bool {
+        false
+    }
+
+    fn on_fire(arena: &Arena, player: PlayerId) -> bool {
+        let leaderboard = arena.leaderboard(1);
+        leaderboard
+            .standings
+            .iter()
+            .find(|s| s.player == player)
+            .unwrap()
+            .on_fire
+    }
+
+    #[test]
+    fn pairs_in_the_order_players_wait() {
+        let mut arena = arena(&[1, 2, 3, 4, 5]);
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(1, 2), (3, 4)]);
+        assert_eq!(arena.waiting, vec![5]);
+    }
+
+    #[test]
+    fn winners_play_winners() {
+        let mut arena = arena(&[1, 2, 3, 4]);
+        arena.pair_waiting(nobody_blocked);
+        arena.record_result([1, 2], GameResult::White);
+        arena.record_result([3, 4], GameResult::White);
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(1, 3), (2, 4)]);
+    }
+
+    #[test]
+    fn no_immediate_rematch_if_somebody_else_waits() {
+        let mut arena = arena(&[1, 2, 3]);
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(1, 2)]);
+        arena.record_result([1, 2], GameResult::Draw);
+        // 1 had white, so 3 gets it
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(3, 1)]);
+        assert_eq!(arena.waiting, vec![2]);
+    }
+
+    #[test]
+    fn rematch_when_nobody_else_waits() {
+        let mut arena = arena(&[1, 2]);
+        arena.pair_waiting(nobody_blocked);
+        arena.record_result([1, 2], GameResult::Draw);
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(2, 1)]);
+    }
+
+    #[test]
+    fn blocked_players_keep_waiting() {
+        let mut arena = arena(&[1, 2]);
+        let blocked = |a: PlayerId, b: PlayerId| (a, b) == (1, 2) || (a, b) == (2, 1);
+        assert!(arena.pair_waiting(blocked).is_empty());
+        assert_eq!(arena.waiting, vec![1, 2]);
+
+        arena.join(3);
+        assert_eq!(arena.pair_waiting(blocked), vec![(1, 3)]);
+        assert_eq!(arena.waiting, vec![2]);
+    }
+
+    #[test]
+    fn players_who_left_score_but_are_not_paired() {
+        let mut arena = arena(&[1, 2, 3]);
+        arena.pair_waiting(nobody_blocked);
+        arena.leave(1);
+        arena.record_result([1, 2], GameResult::White);
+        assert_eq!(arena.score(1), Some((2, 1)));
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn nobody_is_paired_after_the_end() {
+        let mut arena = Arena::new("test".into(), Instant::now());
+        arena.join(1);
+        arena.join(2);
+        assert!(arena.pair_waiting(nobody_blocked).is_empty());
+    }
+
+    #[test]
+    fn two_wins_in_a_row_double_the_next_results() {
+        let mut arena = arena(&[1, 2]);
+        arena.record_result([1, 2], GameResult::White);
+        arena.record_result([2, 1], GameResult::Black);
+        assert_eq!(arena.score(1), Some((4, 2)));
+        assert!(on_fire(&arena, 1));
+
+        arena.record_result([1, 2], GameResult::White);
+        assert_eq!(arena.score(1), Some((8, 3)));
+        // a draw still counts double, but ends the streak
+        arena.record_result([1, 2], GameResult::Draw);
+        assert_eq!(arena.score(1), Some((10, 4)));
+        assert!(!on_fire(&arena, 1));
+
+        arena.record_result([1, 2], GameResult::White);
+        assert_eq!(arena.score(1), Some((12, 5)));
+        assert_eq!(arena.score(2), Some((1, 5)));
+    }
+
+    #[test]
+    fn a_loss_ends_the_streak() {
+        let mut arena = arena(&[1, 2]);
+        arena.record_result([1, 2], GameResult::White);
+        arena.record_result([1, 2], GameResult::White);
+        arena.record_result([1, 2], GameResult::Black);
+        assert_eq!(arena.score(1), Some((4, 3)));
+        assert!(!on_fire(&arena, 1));
+        assert!(!on_fire(&arena, 2));
+    }
+
+    #[test]
+    fn restored_scores_count_but_need_a_new_join() {
+        let mut arena = Arena::new("test".into(), Instant::now() + Duration::from_secs(60));
+        arena.restore_score(1, 6, 3);
+        arena.restore_score(2, 2, 3);
+        assert!(arena.pair_waiting(nobody_blocked).is_empty());
+
+        arena.join(1);
+        arena.join(2);
+        arena.join(3);
+        // by score, 1 leads
+        assert_eq!(arena.pair_waiting(nobody_blocked), vec![(1, 2)]);
+        assert_eq!(arena.score(1), Some((6, 3)));
+    }
+}
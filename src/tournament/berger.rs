@@ -0,0 +1,133 @@
+//! Round-robin pairings by Berger tables
+
+use super::Pairing;
+use crate::PlayerId;
+
+/// how many rounds a round-robin of `players` takes
+pub fn rounds(players: usize) -> usize {
+    if players % 2 == 0 {
+        players.saturating_sub(1)
+    } else {
+        players
+    }
+}
+
+/// pairings of `round` (0 based), players are in seeding order.
+/// With an odd number of players a dummy is added and whoever plays it gets a bye.
+pub fn round_pairings(players: &[PlayerId], round: usize) -> Vec<Pairing> {
+    let mut seeds: Vec<Option<PlayerId>> = players.iter().copied().map(Some).collect();
+    if seeds.len() % 2 == 1 {
+        seeds.push(None);
+    }
+    let n = seeds.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let last = n - 1;
+    // every round the table rotates by n / 2, the last seed stays in place
+    let offset = (round * (n / 2)) % last;
+
+    let mut boards = Vec::with_capacity(n / 2);
+    // the last seed alternates colours every round
+    boards.push(if round % 2 == 0 {
+        (seeds[offset], seeds[last])
+    } else {
+        (seeds[last], seeds[offset])
+    });
+    for i in 1..n / 2 {
+        let white = (offset + i) % last;
+        let black = (offset + last - i) % last;
+        boards.push((seeds[white], seeds[black]));
+    }
+
+    boards
+        .into_iter()
+        .filter_map(|board| match board {
+            (Some(white), Some(black)) => Some(Pairing::Game { white, black }),
+            (Some(player), None) | (None, Some(player)) => Some(Pairing::Bye(player)),
+            (None, None) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn play_all_rounds(players: usize) -> Vec<Vec<Pairing>> {
+        let ids: Vec<PlayerId> = (1..=players).collect();
+        (0..rounds(players))
+            .map(|round| round_pairings(&ids, round))
+            .collect()
+    }
+
+    fn everybody_meets_once(players: usize) {
+        let mut met = HashSet::new();
+        let mut byes = HashSet::new();
+        for pairings in play_all_rounds(players) {
+            let mut seen = HashSet::new();
+            for pairing in pairings {
+                match pairing {
+                    Pairing::Game { white, black } => {
+                        assert!(seen.insert(white) && seen.insert(black));
+                        assert!(
+                            met.insert((white.min(black), white.max(black))),
+                            "{white} and {black} met twice"
+                        );
+                    }
+                    Pairing::Bye(player) => {
+                        assert!(seen.insert(player));
+                        assert!(byes.insert(player), "{player} had two byes");
+                    }
+                }
+            }
+            assert_eq!(seen.len(), players);
+        }
+        assert_eq!(met.len(), players * (players - 1) / 2);
+        assert_eq!(byes.len(), players % 2 * players);
+    }
+
+    #[test]
+    fn rounds_per_player_count() {
+        assert_eq!(rounds(0), 0);
+        assert_eq!(rounds(1), 1);
+        assert_eq!(rounds(4), 3);
+        assert_eq!(rounds(5), 5);
+    }
+
+    #[test]
+    fn even_round_robin() {
+        for players in [2, 4, 6, 10] {
+            everybody_meets_once(players);
+        }
+    }
+
+    #[test]
+    fn odd_round_robin_gives_everybody_one_bye() {
+        for players in [3, 5, 9] {
+            everybody_meets_once(players);
+        }
+    }
+
+    #[test]
+    fn colours_are_balanced() {
+        let players = 8;
+        let mut whites = vec![0i32; players + 1];
+        for pairings in play_all_rounds(players) {
+            for pairing in pairings {
+                if let Pairing::Game { white, black } = pairing {
+                    whites[white] += 1;
+                    whites[black] -= 1;
+                }
+            }
+        }
+        assert!(whites.iter().all(|difference| difference.abs() <= 1));
+    }
+
+    #[test]
+    fn too_few_players() {
+        assert!(round_pairings(&[], 0).is_empty());
+        assert_eq!(round_pairings(&[7], 0), vec![Pairing::Bye(7)]);
+    }
+}
@@ -0,0 +1,368 @@
+//! Round-robin and Swiss tournaments.
+//! Pairing and tie-break logic doesn't touch the db, the functions here load
+//! the tournament, run it and store the new round.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{MySql, MySqlConnection, Pool};
+
+use crate::{
+    chess_logic::{GameResult, Player},
+    PlayerId,
+};
+
+pub mod arena;
+pub mod berger;
+pub mod swiss;
+mod tiebreak;
+
+pub use tiebreak::{standings, Standing};
+
+pub type TournamentId = u64;
+pub type PairingId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PairingSystem {
+    RoundRobin,
+    Swiss,
+}
+
+impl PairingSystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PairingSystem::RoundRobin => "round_robin",
+            PairingSystem::Swiss => "swiss",
+        }
+    }
+
+    pub fn parse(system: &str) -> Option<Self> {
+        match system {
+            "round_robin" => Some(PairingSystem::RoundRobin),
+            "swiss" => Some(PairingSystem::Swiss),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pairing {
+    Game { white: PlayerId, black: PlayerId },
+    Bye(PlayerId),
+}
+
+/// A paired game that the game organizer has to start
+#[derive(Debug, Clone, Copy)]
+pub struct TournamentGame {
+    pub pairing: PairingId,
+    pub white: PlayerId,
+    pub black: PlayerId,
+}
+
+/// One row of TournamentPairings
+#[derive(Debug, Clone, Copy)]
+pub struct PairingRecord {
+    pub white: PlayerId,
+    /// None for a bye
+    pub black: Option<PlayerId>,
+    /// None while the game is still being played
+    pub result: Option<GameResult>,
+}
+
+/// Everything the pairing needs to know about a player's previous rounds
+#[derive(Debug, Clone)]
+pub struct PlayerHistory {
+    pub id: PlayerId,
+    /// in half points, so a draw is 1
+    pub score: u32,
+    pub opponents: Vec<PlayerId>,
+    /// colours in the order they were played
+    pub colours: Vec<Player>,
+    pub had_bye: bool,
+}
+
+impl PlayerHistory {
+    /// number of games with white minus games with black
+    pub fn colour_difference(&self) -> i32 {
+        self.colours
+            .iter()
+            .map(|c| match c {
+                Player::White => 1,
+                Player::Black => -1,
+            })
+            .sum()
+    }
+
+    /// colour the player should get next, None if he has no preference
+    pub fn colour_preference(&self) -> Option<Player> {
+        match self.colour_difference() {
+            d if d > 0 => Some(Player::Black),
+            d if d < 0 => Some(Player::White),
+            _ => self.colours.last().map(|c| c.opponent()),
+        }
+    }
+
+    /// nobody gets the same colour three times in a row or two more games with one colour
+    pub fn can_play(&self, colour: Player) -> bool {
+        let difference = self.colour_difference()
+            + match colour {
+                Player::White => 1,
+                Player::Black => -1,
+            };
+        if difference.abs() > 2 {
+            return false;
+        }
+        !self.colours.ends_with(&[colour, colour])
+    }
+}
+
+/// history of every entrant, entrants are in seeding order
+pub fn histories(entrants: &[PlayerId], records: &[PairingRecord]) -> Vec<PlayerHistory> {
+    let mut histories: Vec<PlayerHistory> = entrants
+        .iter()
+        .map(|id| PlayerHistory {
+            id: *id,
+            score: 0,
+            opponents: Vec::new(),
+            colours: Vec::new(),
+            had_bye: false,
+        })
+        .collect();
+
+    for record in records {
+        for history in histories.iter_mut() {
+            match record.black {
+                None if record.white == history.id => {
+                    history.had_bye = true;
+                    history.score += 2;
+                }
+                Some(black) if record.white == history.id || black == history.id => {
+                    let (colour, opponent) = if record.white == history.id {
+                        (Player::White, black)
+                    } else {
+                        (Player::Black, record.white)
+                    };
+                    history.colours.push(colour);
+                    history.opponents.push(opponent);
+                    if let Some(result) = record.result {
+                        history.score += result.half_points(colour);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    histories
+}
+
+/// entrants in seeding (joining) order
+pub async fn entrants(
+    conn: &mut MySqlConnection,
+    tournament_id: TournamentId,
+) -> Result<Vec<PlayerId>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT player FROM TournamentEntrants
+        WHERE tournament=?
+        ORDER BY joined_at, player",
+        tournament_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| row.player as PlayerId)
+    .collect())
+}
+
+/// all pairings of the tournament, in the order of the rounds
+pub async fn pairing_records(
+    conn: &mut MySqlConnection,
+    tournament_id: TournamentId,
+) -> Result<Vec<PairingRecord>, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT p.white, p.black, p.result FROM TournamentPairings p
+        JOIN TournamentRounds r ON p.round = r.id
+        WHERE r.tournament=?
+        ORDER BY r.number, p.id",
+        tournament_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|row| PairingRecord {
+        white: row.white as PlayerId,
+        black: row.black.map(|b| b as PlayerId),
+        result: row.result.as_deref().and_then(GameResult::parse),
+    })
+    .collect())
+}
+
+pub async fn get_standings(
+    db_pool: &Pool<MySql>,
+    tournament_id: TournamentId,
+) -> Result<Vec<Standing>, sqlx::Error> {
+    let mut conn = db_pool.acquire().await?;
+    let entrants = entrants(&mut conn, tournament_id).await?;
+    let records = pairing_records(&mut conn, tournament_id).await?;
+    Ok(standings(&entrants, &records))
+}
+
+/// pairs and stores the next round, returns the games that have to be started.
+/// If it was the last round the tournament is finished instead.
+pub async fn start_next_round(
+    conn: &mut MySqlConnection,
+    tournament_id: TournamentId,
+) -> Result<Vec<TournamentGame>, sqlx::Error> {
+    let tournament = sqlx::query!(
+        "SELECT pairing_system, num_of_rounds, current_round FROM Tournaments
+        WHERE id=? FOR UPDATE",
+        tournament_id,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if tournament.current_round >= tournament.num_of_rounds {
+        finish(conn, tournament_id).await?;
+        return Ok(Vec::new());
+    }
+
+    let entrants = entrants(conn, tournament_id).await?;
+    let records = pairing_records(conn, tournament_id).await?;
+    let pairings = match PairingSystem::parse(&tournament.pairing_system) {
+        Some(PairingSystem::RoundRobin) => {
+            berger::round_pairings(&entrants, tournament.current_round as usize)
+        }
+        Some(PairingSystem::Swiss) => {
+            match swiss::round_pairings(&histories(&entrants, &records)) {
+                Some(pairings) => pairings,
+                // everybody already met everybody they could
+                None => {
+                    finish(conn, tournament_id).await?;
+                    return Ok(Vec::new());
+                }
+            }
+        }
+        None => unreachable!("pairing_system column is an enum of the known systems"),
+    };
+
+    let round_number = tournament.current_round + 1;
+    let round_id = sqlx::query!(
+        "INSERT INTO TournamentRounds(tournament, number) VALUES (?, ?)",
+        tournament_id,
+        round_number,
+    )
+    .execute(&mut *conn)
+    .await?
+    .last_insert_id();
+
+    sqlx::query!(
+        "UPDATE Tournaments SET current_round=?, status='running' WHERE id=?",
+        round_number,
+        tournament_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let mut games = Vec::new();
+    for pairing in pairings {
+        match pairing {
+            Pairing::Game { white, black } => {
+                let pairing_id = sqlx::query!(
+                    "INSERT INTO TournamentPairings(round, white, black) VALUES (?, ?, ?)",
+                    round_id,
+                    white as u64,
+                    black as u64,
+                )
+                .execute(&mut *conn)
+                .await?
+                .last_insert_id();
+                games.push(TournamentGame {
+                    pairing: pairing_id,
+                    white,
+                    black,
+                });
+            }
+            // a bye is a won game without an opponent
+            Pairing::Bye(player) => {
+                sqlx::query!(
+                    "INSERT INTO TournamentPairings(round, white, result) VALUES (?, ?, 'white')",
+                    round_id,
+                    player as u64,
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+    }
+
+    Ok(games)
+}
+
+async fn finish(conn: &mut MySqlConnection, tournament_id: TournamentId) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE Tournaments SET status='finished' WHERE id=?",
+        tournament_id,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// stores the result of a tournament game, if it was the last game of the round
+/// the next round is paired and its games are returned
+pub async fn record_result(
+    db_pool: &Pool<MySql>,
+    pairing_id: PairingId,
+    game_db_id: Option<u64>,
+    result: GameResult,
+) -> Result<Vec<TournamentGame>, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    let pairing = sqlx::query!(
+        "SELECT r.id round_id, r.tournament FROM TournamentPairings p
+        JOIN TournamentRounds r ON p.round = r.id
+        WHERE p.id=?",
+        pairing_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // lock the tournament, so two games finishing at once can't both start the next round
+    sqlx::query!(
+        "SELECT id FROM Tournaments WHERE id=? FOR UPDATE",
+        pairing.tournament,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE TournamentPairings SET result=?, game=? WHERE id=?",
+        result.as_str(),
+        game_db_id,
+        pairing_id,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let pending = sqlx::query!(
+        "SELECT COUNT(*) pending FROM TournamentPairings WHERE round=? AND result IS NULL",
+        pairing.round_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .pending;
+
+    let games = if pending == 0 {
+        sqlx::query!(
+            "UPDATE TournamentRounds SET finished_at=CURRENT_TIMESTAMP WHERE id=?",
+            pairing.round_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        start_next_round(&mut tx, pairing.tournament as TournamentId).await?
+    } else {
+        Vec::new()
+    };
+
+    tx.commit().await?;
+    Ok(games)
+}
@@ -0,0 +1,235 @@
+//! Swiss pairings, following the Dutch system:
+//! players are ranked by score, every score group is split in half and the top half
+//! plays the bottom half. Nobody meets the same opponent twice and colours are kept balanced.
+//! When a group can't be paired, players float down to the next group.
+
+use super::{Pairing, PlayerHistory};
+use crate::{chess_logic::Player, PlayerId};
+
+/// pairings for the next round, None if there is no valid pairing (e.g. everybody already met)
+pub fn round_pairings(players: &[PlayerHistory]) -> Option<Vec<Pairing>> {
+    let mut ranked: Vec<&PlayerHistory> = players.iter().collect();
+    // stable sort, so players with the same score stay in seeding order
+    ranked.sort_by(|a, b| b.score.cmp(&a.score));
+
+    if ranked.len() % 2 == 0 {
+        let mut games = Vec::new();
+        return pair_remaining(&ranked, &mut games).then_some(games);
+    }
+
+    // the lowest ranked player who didn't have a bye yet gets it
+    let mut bye_candidates: Vec<usize> = (0..ranked.len())
+        .rev()
+        .filter(|i| !ranked[*i].had_bye)
+        .collect();
+    if bye_candidates.is_empty() {
+        bye_candidates = (0..ranked.len()).rev().collect();
+    }
+    for bye in bye_candidates {
+        let mut remaining = ranked.clone();
+        let bye_player = remaining.remove(bye);
+
+        let mut games = vec![Pairing::Bye(bye_player.id)];
+        if pair_remaining(&remaining, &mut games) {
+            return Some(games);
+        }
+    }
+    None
+}
+
+/// pairs the highest ranked player first and backtracks if the rest can't be paired
+fn pair_remaining(players: &[&PlayerHistory], pairings: &mut Vec<Pairing>) -> bool {
+    let (first, rest) = match players.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+
+    for index in candidate_order(first, rest) {
+        let opponent = rest[index];
+        if first.opponents.contains(&opponent.id) {
+            continue;
+        }
+        let (white, black) = match assign_colours(first, opponent) {
+            Some(colours) => colours,
+            None => continue,
+        };
+
+        let remaining: Vec<&PlayerHistory> = rest
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, p)| *p)
+            .collect();
+
+        pairings.push(Pairing::Game { white, black });
+        if pair_remaining(&remaining, pairings) {
+            return true;
+        }
+        pairings.pop();
+    }
+    false
+}
+
+/// order in which opponents for `first` are tried (indexes into `rest`):
+/// his counterpart in the bottom half of the score group, the rest of the bottom half,
+/// the top half and at last lower score groups
+fn candidate_order(first: &PlayerHistory, rest: &[&PlayerHistory]) -> Vec<usize> {
+    let group_len = rest.iter().take_while(|p| p.score == first.score).count();
+    // first is on top of the group, so his counterpart is half of the group below him
+    let counterpart = group_len.div_ceil(2);
+
+    let bottom_half = counterpart.saturating_sub(1)..group_len;
+    let top_half = (0..counterpart.saturating_sub(1)).rev();
+    let lower_groups = group_len..rest.len();
+
+    bottom_half.chain(top_half).chain(lower_groups).collect()
+}
+
+/// white and black for the pair, None if one of them can't get the colour he would need
+fn assign_colours(a: &PlayerHistory, b: &PlayerHistory) -> Option<(PlayerId, PlayerId)> {
+    let a_white_ok = a.can_play(Player::White) && b.can_play(Player::Black);
+    let b_white_ok = b.can_play(Player::White) && a.can_play(Player::Black);
+
+    let a_wants_white = match (a.colour_preference(), b.colour_preference()) {
+        // same preference, the one with bigger imbalance gets it, otherwise the higher ranked
+        (Some(a_pref), Some(b_pref)) if a_pref == b_pref => {
+            let a_wants = a.colour_difference().abs() >= b.colour_difference().abs();
+            (a_pref == Player::White) == a_wants
+        }
+        (Some(a_pref), _) => a_pref == Player::White,
+        (None, Some(b_pref)) => b_pref == Player::Black,
+        (None, None) => true,
+    };
+
+    match (a_wants_white, a_white_ok, b_white_ok) {
+        (true, true, _) => Some((a.id, b.id)),
+        (false, _, true) => Some((b.id, a.id)),
+        (_, true, _) => Some((a.id, b.id)),
+        (_, _, true) => Some((b.id, a.id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess_logic::GameResult,
+        tournament::{histories, PairingRecord},
+    };
+    use std::collections::HashSet;
+
+    fn game(white: PlayerId, black: PlayerId, result: GameResult) -> PairingRecord {
+        PairingRecord {
+            white,
+            black: Some(black),
+            result: Some(result),
+        }
+    }
+
+    fn bye(player: PlayerId) -> PairingRecord {
+        PairingRecord {
+            white: player,
+            black: None,
+            // stored as a win
+            result: Some(GameResult::White),
+        }
+    }
+
+    /// every player exactly once, and no game between players who already met
+    fn assert_valid(players: &[PlayerHistory], pairings: &[Pairing]) {
+        let mut seen = HashSet::new();
+        for pairing in pairings {
+            match *pairing {
+                Pairing::Game { white, black } => {
+                    assert!(seen.insert(white) && seen.insert(black));
+                    let history = players.iter().find(|p| p.id == white).unwrap();
+                    assert!(
+                        !history.opponents.contains(&black),
+                        "{white} and {black} met again"
+                    );
+                }
+                Pairing::Bye(player) => assert!(seen.insert(player)),
+            }
+        }
+        assert_eq!(seen.len(), players.len());
+    }
+
+    #[test]
+    fn first_round_top_half_plays_bottom_half() {
+        let players = histories(&[1, 2, 3, 4, 5, 6], &[]);
+        assert_eq!(
+            round_pairings(&players).unwrap(),
+            vec![
+                Pairing::Game { white: 1, black: 4 },
+                Pairing::Game { white: 2, black: 5 },
+                Pairing::Game { white: 3, black: 6 },
+            ]
+        );
+    }
+
+    #[test]
+    fn winners_play_winners_with_alternating_colours() {
+        let records = [game(1, 3, GameResult::White), game(2, 4, GameResult::White)];
+        let players = histories(&[1, 2, 3, 4], &records);
+        assert_eq!(
+            round_pairings(&players).unwrap(),
+            vec![
+                Pairing::Game { white: 2, black: 1 },
+                Pairing::Game { white: 3, black: 4 },
+            ]
+        );
+    }
+
+    #[test]
+    fn nobody_meets_twice() {
+        let records = [
+            game(1, 3, GameResult::White),
+            game(2, 4, GameResult::White),
+            game(2, 1, GameResult::Draw),
+            game(3, 4, GameResult::Draw),
+        ];
+        let players = histories(&[1, 2, 3, 4], &records);
+        let pairings = round_pairings(&players).unwrap();
+        assert_valid(&players, &pairings);
+        assert!(pairings.contains(&Pairing::Game { white: 4, black: 1 }));
+    }
+
+    #[test]
+    fn lowest_ranked_without_a_bye_gets_it() {
+        let players = histories(&[1, 2, 3], &[]);
+        let pairings = round_pairings(&players).unwrap();
+        assert_valid(&players, &pairings);
+        assert_eq!(pairings[0], Pairing::Bye(3));
+
+        let records = [bye(3), game(1, 2, GameResult::White)];
+        let players = histories(&[1, 2, 3], &records);
+        let pairings = round_pairings(&players).unwrap();
+        assert_valid(&players, &pairings);
+        assert_eq!(pairings[0], Pairing::Bye(2));
+    }
+
+    #[test]
+    fn no_pairing_when_everybody_met() {
+        let records = [game(1, 2, GameResult::Draw)];
+        let players = histories(&[1, 2], &records);
+        assert_eq!(round_pairings(&players), None);
+    }
+
+    #[test]
+    fn nobody_gets_a_colour_three_times_in_a_row() {
+        let records = [
+            game(1, 3, GameResult::Draw),
+            game(4, 2, GameResult::Draw),
+            game(1, 2, GameResult::Draw),
+        ];
+        let players = histories(&[1, 2, 3, 4], &records);
+        assert_eq!(
+            round_pairings(&players).unwrap(),
+            vec![
+                Pairing::Game { white: 4, black: 1 },
+                Pairing::Game { white: 2, black: 3 },
+            ]
+        );
+    }
+}
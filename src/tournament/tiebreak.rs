@@ -0,0 +1,154 @@
+//! Standings with Buchholz and Sonneborn-Berger tie-breaks
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::PairingRecord;
+use crate::{chess_logic::Player, PlayerId};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Standing {
+    pub player: PlayerId,
+    pub score: f32,
+    /// sum of opponents' scores
+    pub buchholz: f32,
+    /// sum of scores of beaten opponents and half of the drawn ones
+    pub sonneborn_berger: f32,
+    pub games: usize,
+}
+
+/// standings of all entrants, best first
+pub fn standings(entrants: &[PlayerId], records: &[PairingRecord]) -> Vec<Standing> {
+    // half points, so draws stay whole numbers
+    let mut scores: HashMap<PlayerId, u32> = entrants.iter().map(|p| (*p, 0)).collect();
+    for record in records {
+        let result = match record.result {
+            Some(result) => result,
+            None => continue, // still playing
+        };
+        match record.black {
+            Some(black) => {
+                *scores.entry(record.white).or_default() += result.half_points(Player::White);
+                *scores.entry(black).or_default() += result.half_points(Player::Black);
+            }
+            // bye
+            None => *scores.entry(record.white).or_default() += 2,
+        }
+    }
+
+    let score_of = |player: &PlayerId| *scores.get(player).unwrap_or(&0) as f32 / 2.0;
+
+    let mut standings: Vec<Standing> = entrants
+        .iter()
+        .map(|player| Standing {
+            player: *player,
+            score: score_of(player),
+            buchholz: 0.0,
+            sonneborn_berger: 0.0,
+            games: 0,
+        })
+        .collect();
+
+    for standing in standings.iter_mut() {
+        for record in records {
+            let (result, black) = match (record.result, record.black) {
+                (Some(result), Some(black)) => (result, black),
+                _ => continue,
+            };
+            let (opponent, colour) = if record.white == standing.player {
+                (black, Player::White)
+            } else if black == standing.player {
+                (record.white, Player::Black)
+            } else {
+                continue;
+            };
+
+            let opponent_score = score_of(&opponent);
+            standing.games += 1;
+            standing.buchholz += opponent_score;
+            standing.sonneborn_berger += opponent_score * result.half_points(colour) as f32 / 2.0;
+        }
+    }
+
+    standings.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.buchholz.total_cmp(&a.buchholz))
+            .then(b.sonneborn_berger.total_cmp(&a.sonneborn_berger))
+    });
+    standings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess_logic::GameResult;
+
+    fn game(white: PlayerId, black: PlayerId, result: Option<GameResult>) -> PairingRecord {
+        PairingRecord {
+            white,
+            black: Some(black),
+            result,
+        }
+    }
+
+    fn order(standings: &[Standing]) -> Vec<PlayerId> {
+        standings.iter().map(|s| s.player).collect()
+    }
+
+    #[test]
+    fn score_then_buchholz_then_sonneborn_berger() {
+        let records = [
+            game(1, 3, Some(GameResult::White)),
+            game(2, 4, Some(GameResult::White)),
+            game(3, 4, Some(GameResult::White)),
+        ];
+        let standings = standings(&[1, 2, 3, 4], &records);
+        // 1, 2 and 3 have a point, 1 and 3 have the same buchholz but 1 beat a stronger player
+        assert_eq!(order(&standings), vec![1, 3, 2, 4]);
+
+        let third = &standings[1];
+        assert_eq!(third.score, 1.0);
+        assert_eq!(third.games, 2);
+        assert_eq!(third.buchholz, 1.0);
+        assert_eq!(third.sonneborn_berger, 0.0);
+        assert_eq!(standings[0].sonneborn_berger, 1.0);
+    }
+
+    #[test]
+    fn draws_count_half() {
+        let records = [
+            game(1, 2, Some(GameResult::Draw)),
+            game(3, 1, Some(GameResult::Black)),
+        ];
+        let standings = standings(&[1, 2, 3], &records);
+        assert_eq!(order(&standings), vec![1, 2, 3]);
+        assert_eq!(standings[0].score, 1.5);
+        assert_eq!(standings[0].buchholz, 0.5);
+        assert_eq!(standings[0].sonneborn_berger, 0.25);
+    }
+
+    #[test]
+    fn bye_is_a_point_without_an_opponent() {
+        let records = [
+            PairingRecord {
+                white: 3,
+                black: None,
+                // stored as a win
+                result: Some(GameResult::White),
+            },
+            game(1, 2, Some(GameResult::White)),
+        ];
+        let standings = standings(&[1, 2, 3], &records);
+        let bye = standings.iter().find(|s| s.player == 3).unwrap();
+        assert_eq!(bye.score, 1.0);
+        assert_eq!(bye.games, 0);
+        assert_eq!(bye.buchholz, 0.0);
+    }
+
+    #[test]
+    fn unfinished_games_count_nothing() {
+        let standings = standings(&[1, 2], &[game(1, 2, None)]);
+        assert!(standings.iter().all(|s| s.score == 0.0 && s.games == 0));
+    }
+}
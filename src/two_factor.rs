@@ -0,0 +1,247 @@
+//! Optional second login step with an authenticator app, see `totp`.
+//! Recovery codes replace the app when it's lost, only their hashes are stored.
+
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, MySqlConnection, Pool};
+
+use crate::{totp, PlayerId};
+
+pub const RECOVERY_CODES: usize = 10;
+/// without the dash in the middle
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub enabled: bool,
+    /// by an admin, the player can't turn it off
+    pub required: bool,
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().replace('-', "").to_lowercase()
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(normalize_recovery_code(code).as_bytes())
+    )
+}
+
+/// lowercase letters and digits with a dash in the middle, e.g. `x7k2p-9qm4t`
+fn generate_recovery_code() -> String {
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RECOVERY_CODE_LENGTH)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+    format!("{first}-{second}")
+}
+
+pub async fn status(db_pool: &Pool<MySql>, player_id: PlayerId) -> Result<Status, sqlx::Error> {
+    let user = sqlx::query!(
+        "SELECT UNIX_TIMESTAMP(totp_enabled_at) `enabled_at: i64`,
+        two_factor_required `required!: bool`
+        FROM User WHERE id=?",
+        player_id as u64,
+    )
+    .fetch_one(db_pool)
+    .await?;
+    Ok(Status {
+        enabled: user.enabled_at.is_some(),
+        required: user.required,
+    })
+}
+
+pub async fn remaining_recovery_codes(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+) -> Result<i64, sqlx::Error> {
+    Ok(sqlx::query!(
+        "SELECT COUNT(*) `count!: i64` FROM RecoveryCodes WHERE player=? AND used_at IS NULL",
+        player_id as u64,
+    )
+    .fetch_one(db_pool)
+    .await?
+    .count)
+}
+
+/// a new secret for the app, replacing one that wasn't confirmed.
+/// None if two-factor is already on
+pub async fn start_enrollment(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+) -> Result<Option<String>, sqlx::Error> {
+    let secret = totp::generate_secret();
+    let updated = sqlx::query!(
+        "UPDATE User SET totp_secret=?, totp_last_step=NULL
+        WHERE id=? AND totp_enabled_at IS NULL",
+        secret,
+        player_id as u64,
+    )
+    .execute(db_pool)
+    .await?;
+    Ok((updated.rows_affected() == 1).then(|| secret))
+}
+
+/// turns two-factor on if the code is from the secret of `start_enrollment`,
+/// the recovery codes are only shown this once
+pub async fn confirm_enrollment(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    code: &str,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+    let user = sqlx::query!(
+        "SELECT totp_secret FROM User WHERE id=? AND totp_enabled_at IS NULL FOR UPDATE",
+        player_id as u64,
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let secret = match user.and_then(|user| user.totp_secret) {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    let step = match totp::verify(&secret, code.trim(), None) {
+        Some(step) => step,
+        None => return Ok(None),
+    };
+
+    sqlx::query!(
+        "UPDATE User SET totp_enabled_at=CURRENT_TIMESTAMP, totp_last_step=? WHERE id=?",
+        step,
+        player_id as u64,
+    )
+    .execute(&mut *tx)
+    .await?;
+    let codes = replace_recovery_codes(&mut tx, player_id).await?;
+    tx.commit().await?;
+    Ok(Some(codes))
+}
+
+/// a code from the app or an unused recovery code, either works only once
+pub async fn check_code(
+    db_pool: &Pool<MySql>,
+    player_id: PlayerId,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    let code = code.trim();
+    let mut tx = db_pool.begin().await?;
+    if totp::looks_like_code(code) {
+        let user = sqlx::query!(
+            "SELECT totp_secret, totp_last_step FROM User
+            WHERE id=? AND totp_enabled_at IS NOT NULL
+            FOR UPDATE",
+            player_id as u64,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let step = match user {
+            Some(user) => user
+                .totp_secret
+                .and_then(|secret| totp::verify(&secret, code, user.totp_last_step)),
+            None => None,
+        };
+        let step = match step {
+            Some(step) => step,
+            None => return Ok(false),
+        };
+        sqlx::query!(
+            "UPDATE User SET totp_last_step=? WHERE id=?",
+            step,
+            player_id as u64,
+        )
+        .execute(&mut *tx)
+        .await?;
+    } else {
+        let used = sqlx::query!(
+            "UPDATE RecoveryCodes SET used_at=CURRENT_TIMESTAMP
+            WHERE player=? AND code_hash=? AND used_at IS NULL
+            LIMIT 1",
+            player_id as u64,
+            hash_recovery_code(code),
+        )
+        .execute(&mut *tx)
+        .await?;
+        if used.rows_affected() == 0 {
+            return Ok(false);
+        }
+        println!("player {player_id} used a recovery code");
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// the old codes stop working, in a transaction so a failure keeps them
+pub async fn replace_recovery_codes(
+    conn: &mut MySqlConnection,
+    player_id: PlayerId,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query!("DELETE FROM RecoveryCodes WHERE player=?", player_id as u64)
+        .execute(&mut *conn)
+        .await?;
+    let mut codes = Vec::with_capacity(RECOVERY_CODES);
+    for _ in 0..RECOVERY_CODES {
+        let code = generate_recovery_code();
+        sqlx::query!(
+            "INSERT INTO RecoveryCodes(player, code_hash) VALUES (?, ?)",
+            player_id as u64,
+            hash_recovery_code(&code),
+        )
+        .execute(&mut *conn)
+        .await?;
+        codes.push(code);
+    }
+    Ok(codes)
+}
+
+/// by the player, or by an admin when the app and the codes are lost, in a transaction
+pub async fn disable(conn: &mut MySqlConnection, player_id: PlayerId) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query!(
+        "UPDATE User SET totp_secret=NULL, totp_enabled_at=NULL, totp_last_step=NULL
+        WHERE id=? AND totp_secret IS NOT NULL",
+        player_id as u64,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!("DELETE FROM RecoveryCodes WHERE player=?", player_id as u64)
+        .execute(&mut *conn)
+        .await?;
+    Ok(updated.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_codes_have_a_dash_in_the_middle() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), RECOVERY_CODE_LENGTH + 1);
+        let (first, second) = code.split_once('-').unwrap();
+        assert_eq!(first.len(), second.len());
+        assert!(code
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_digit() || c.is_ascii_lowercase()));
+        assert_ne!(generate_recovery_code(), code);
+    }
+
+    #[test]
+    fn hash_ignores_case_dash_and_spaces() {
+        let hash = hash_recovery_code("x7k2p-9qm4t");
+        assert_eq!(hash, hash_recovery_code(" X7K2P9QM4T\n"));
+        assert_eq!(hash, hash_recovery_code("x7k2p9qm4t"));
+        assert_ne!(hash, hash_recovery_code("x7k2p-9qm4u"));
+    }
+
+    #[test]
+    fn hash_is_sha256_of_the_normalized_code() {
+        assert_eq!(normalize_recovery_code(" AbC-dEf "), "abcdef");
+        assert_eq!(
+            hash_recovery_code("ABC"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}